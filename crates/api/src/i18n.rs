@@ -0,0 +1,372 @@
+use std::fmt::Display;
+
+/// Languages the bot talks to the users in.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum Language {
+    #[default]
+    En,
+    Ru,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::En, Language::Ru];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::En => "en",
+            Language::Ru => "ru",
+        }
+    }
+
+    /// Name of the language in itself
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::En => "English",
+            Language::Ru => "Русский",
+        }
+    }
+
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        Language::ALL
+            .into_iter()
+            .find(|language| language.code() == value)
+            .ok_or_else(|| anyhow::anyhow!("Unknown language {}", value))
+    }
+
+    /// Language of a Telegram `language_code`, e.g. `ru` or `en-US`, English when the
+    /// language is not supported or not known.
+    pub fn from_code(code: Option<&str>) -> Self {
+        let Some(code) = code else {
+            return Language::En;
+        };
+        let code = code.split(['-', '_']).next().unwrap_or_default();
+        Language::ALL
+            .into_iter()
+            .find(|language| language.code().eq_ignore_ascii_case(code))
+            .unwrap_or_default()
+    }
+
+    pub fn text(&self, text: Text) -> &'static str {
+        match self {
+            Language::En => text.en(),
+            Language::Ru => text.ru(),
+        }
+    }
+
+    /// The text with `{0}`, `{1}`... replaced with the arguments
+    pub fn format(&self, text: Text, args: &[&dyn Display]) -> String {
+        args.iter()
+            .enumerate()
+            .fold(self.text(text).to_string(), |text, (index, arg)| {
+                text.replace(&format!("{{{}}}", index), &arg.to_string())
+            })
+    }
+}
+
+/// Catalog of the messages the users see.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Text {
+    StatusQueued,
+    StatusLoading,
+    StatusUploading,
+    StatusFailed,
+    StatusCancelled,
+    CancelButton,
+    RefreshButton,
+    ReportButton,
+    FreshCopy,
+    CachedCopy,
+    ArchiveSnapshot,
+    CacheSnapshot,
+    CompressedNote,
+    PartNote,
+    InvalidUrl,
+    BlockedByPolicy,
+    FetchFailed,
+    RenderFailed,
+    Timeout,
+    TooLarge,
+    UploadFailed,
+    StorageFailed,
+    ConvertFailed,
+    TooManyRequests,
+    ServiceUnavailable,
+    NothingToCancel,
+    SendLink,
+    LinkNotFound,
+    ReportSaved,
+    ReportFailed,
+    SettingsTitle,
+    OnlyAdmins,
+    FormatHtml,
+    FormatGzip,
+    DetectLinks,
+    LanguageSetting,
+    LanguageFromApp,
+    CachedCopies,
+    On,
+    Off,
+    HelpTitle,
+    HelpCommand,
+    GetPageCommand,
+    CancelCommand,
+    SettingsCommand,
+    BotDescription,
+    BotShortDescription,
+}
+
+impl Text {
+    pub const ALL: [Text; 47] = [
+        Text::StatusQueued,
+        Text::StatusLoading,
+        Text::StatusUploading,
+        Text::StatusFailed,
+        Text::StatusCancelled,
+        Text::CancelButton,
+        Text::RefreshButton,
+        Text::ReportButton,
+        Text::FreshCopy,
+        Text::CachedCopy,
+        Text::ArchiveSnapshot,
+        Text::CacheSnapshot,
+        Text::CompressedNote,
+        Text::PartNote,
+        Text::InvalidUrl,
+        Text::BlockedByPolicy,
+        Text::FetchFailed,
+        Text::RenderFailed,
+        Text::Timeout,
+        Text::TooLarge,
+        Text::UploadFailed,
+        Text::StorageFailed,
+        Text::ConvertFailed,
+        Text::TooManyRequests,
+        Text::ServiceUnavailable,
+        Text::NothingToCancel,
+        Text::SendLink,
+        Text::LinkNotFound,
+        Text::ReportSaved,
+        Text::ReportFailed,
+        Text::SettingsTitle,
+        Text::OnlyAdmins,
+        Text::FormatHtml,
+        Text::FormatGzip,
+        Text::DetectLinks,
+        Text::LanguageSetting,
+        Text::LanguageFromApp,
+        Text::CachedCopies,
+        Text::On,
+        Text::Off,
+        Text::HelpTitle,
+        Text::HelpCommand,
+        Text::GetPageCommand,
+        Text::CancelCommand,
+        Text::SettingsCommand,
+        Text::BotDescription,
+        Text::BotShortDescription,
+    ];
+
+    fn en(self) -> &'static str {
+        match self {
+            Text::StatusQueued => "Queued",
+            Text::StatusLoading => "Loading the page",
+            Text::StatusUploading => "Uploading the page",
+            Text::StatusFailed => "Can't load the page: {0}",
+            Text::StatusCancelled => "Cancelled",
+            Text::CancelButton => "Cancel",
+            Text::RefreshButton => "Refresh",
+            Text::ReportButton => "Report broken",
+            Text::FreshCopy => "Fresh copy, saved on {0}",
+            Text::CachedCopy => "From cache, saved on {0}",
+            Text::ArchiveSnapshot => {
+                "The page can't be loaded now, this is the snapshot from {0} taken on {1}"
+            }
+            Text::CacheSnapshot => "The page can't be loaded now, this is the copy saved on {0}",
+            Text::CompressedNote => "The page is compressed with gzip to fit the upload limit",
+            Text::PartNote => {
+                "Part {0} of {1}. Join the parts and unpack them: cat {2}.gz.* | gunzip > {2}"
+            }
+            Text::InvalidUrl => "{0}",
+            Text::BlockedByPolicy => "{0}",
+            Text::FetchFailed => "The site can't be reached",
+            Text::RenderFailed => "The page can't be saved",
+            Text::Timeout => "Loading the page timed out after {0}",
+            Text::TooLarge => {
+                "The page is too large to send: {0} MB, even compressed and split it's over {1} MB"
+            }
+            Text::UploadFailed => "Telegram didn't accept the page: {0}",
+            Text::StorageFailed => "Something went wrong, try again later",
+            Text::ConvertFailed => "Can't convert the page",
+            Text::TooManyRequests => "Too many requests. Try again later",
+            Text::ServiceUnavailable => "The service is unavailable",
+            Text::NothingToCancel => "Nothing to cancel",
+            Text::SendLink => {
+                "Send a link with the command or reply with it to a message with a link"
+            }
+            Text::LinkNotFound => "The link of the page is not found",
+            Text::ReportSaved => "Thanks, the page is reported",
+            Text::ReportFailed => "Can't send the report. Try again later",
+            Text::SettingsTitle => "Settings of this chat, tap a button to change it",
+            Text::OnlyAdmins => "Only admins can change the settings",
+            Text::FormatHtml => "Format: HTML",
+            Text::FormatGzip => "Format: HTML in gzip",
+            Text::DetectLinks => "Detect links: {0}",
+            Text::LanguageSetting => "Language: {0}",
+            Text::LanguageFromApp => "from the app",
+            Text::CachedCopies => "Cached copies: {0}",
+            Text::On => "on",
+            Text::Off => "off",
+            Text::HelpTitle => "These commands are supported:",
+            Text::HelpCommand => "Show all commands",
+            Text::GetPageCommand => "Get a web page by the URL",
+            Text::CancelCommand => "Cancel pages requested in this chat",
+            Text::SettingsCommand => "Change the settings of this chat",
+            Text::BotDescription => {
+                "I save web pages into a single HTML file and send them as documents, to read them \
+                 offline or when the site is down. Send me a link or /getpage with the URL. In groups \
+                 mention me with a link or reply to a message with a link."
+            }
+            Text::BotShortDescription => "Saves web pages into single HTML files",
+        }
+    }
+
+    fn ru(self) -> &'static str {
+        match self {
+            Text::StatusQueued => "В очереди",
+            Text::StatusLoading => "Загружаю страницу",
+            Text::StatusUploading => "Отправляю страницу",
+            Text::StatusFailed => "Не удалось загрузить страницу: {0}",
+            Text::StatusCancelled => "Отменено",
+            Text::CancelButton => "Отменить",
+            Text::RefreshButton => "Обновить",
+            Text::ReportButton => "Страница сломана",
+            Text::FreshCopy => "Свежая копия, сохранена {0}",
+            Text::CachedCopy => "Из кэша, сохранена {0}",
+            Text::ArchiveSnapshot => {
+                "Страница сейчас недоступна, это снимок из {0}, сделанный {1}"
+            }
+            Text::CacheSnapshot => "Страница сейчас недоступна, это копия, сохранённая {0}",
+            Text::CompressedNote => "Страница сжата gzip, чтобы уложиться в лимит загрузки",
+            Text::PartNote => {
+                "Часть {0} из {1}. Соедините части и распакуйте их: cat {2}.gz.* | gunzip > {2}"
+            }
+            Text::InvalidUrl => "Неверная ссылка: {0}",
+            Text::BlockedByPolicy => "Ссылка запрещена: {0}",
+            Text::FetchFailed => "Сайт недоступен",
+            Text::RenderFailed => "Не удалось сохранить страницу",
+            Text::Timeout => "Страница не загрузилась за {0}",
+            Text::TooLarge => {
+                "Страница слишком большая: {0} МБ, даже сжатая и разбитая на части она больше {1} МБ"
+            }
+            Text::UploadFailed => "Telegram не принял страницу: {0}",
+            Text::StorageFailed => "Что-то пошло не так, попробуйте позже",
+            Text::ConvertFailed => "Не удалось преобразовать страницу",
+            Text::TooManyRequests => "Слишком много запросов. Попробуйте позже",
+            Text::ServiceUnavailable => "Сервис недоступен",
+            Text::NothingToCancel => "Нечего отменять",
+            Text::SendLink => {
+                "Отправьте ссылку вместе с командой или ответьте командой на сообщение со ссылкой"
+            }
+            Text::LinkNotFound => "Ссылка на страницу не найдена",
+            Text::ReportSaved => "Спасибо, мы получили жалобу",
+            Text::ReportFailed => "Не удалось отправить жалобу. Попробуйте позже",
+            Text::SettingsTitle => "Настройки этого чата, нажмите на кнопку, чтобы изменить",
+            Text::OnlyAdmins => "Только администраторы могут менять настройки",
+            Text::FormatHtml => "Формат: HTML",
+            Text::FormatGzip => "Формат: HTML в gzip",
+            Text::DetectLinks => "Находить ссылки: {0}",
+            Text::LanguageSetting => "Язык: {0}",
+            Text::LanguageFromApp => "как в приложении",
+            Text::CachedCopies => "Копии из кэша: {0}",
+            Text::On => "да",
+            Text::Off => "нет",
+            Text::HelpTitle => "Поддерживаются команды:",
+            Text::HelpCommand => "Показать все команды",
+            Text::GetPageCommand => "Получить веб-страницу по ссылке",
+            Text::CancelCommand => "Отменить запрошенные в этом чате страницы",
+            Text::SettingsCommand => "Изменить настройки этого чата",
+            Text::BotDescription => {
+                "Я сохраняю веб-страницы в один HTML-файл и присылаю их документом, чтобы их можно \
+                 было читать офлайн или когда сайт недоступен. Отправьте мне ссылку или /getpage со \
+                 ссылкой. В группах упомяните меня вместе со ссылкой или ответьте на сообщение со \
+                 ссылкой."
+            }
+            Text::BotShortDescription => "Сохраняет веб-страницы в один HTML-файл",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::i18n::{Language, Text};
+    use crate::{PageError, RenderDiagnostics};
+
+    #[test]
+    fn test_from_code() {
+        assert_eq!(Language::from_code(Some("ru")), Language::Ru);
+        assert_eq!(Language::from_code(Some("RU-ru")), Language::Ru);
+        assert_eq!(Language::from_code(Some("en-US")), Language::En);
+        assert_eq!(Language::from_code(Some("de")), Language::En);
+        assert_eq!(Language::from_code(None), Language::En);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            Language::En.format(Text::PartNote, &[&1, &2, &"page.html"]),
+            "Part 1 of 2. Join the parts and unpack them: cat page.html.gz.* | gunzip > page.html"
+        );
+        assert_eq!(
+            Language::Ru.format(Text::StatusFailed, &[&"404"]),
+            "Не удалось загрузить страницу: 404"
+        );
+    }
+
+    #[test]
+    fn test_translations_keep_arguments() {
+        let arguments = |text: &str| {
+            (0..3)
+                .filter(|index| text.contains(&format!("{{{}}}", index)))
+                .collect::<Vec<_>>()
+        };
+        for text in Text::ALL {
+            for language in Language::ALL {
+                assert!(!language.text(text).is_empty(), "{:?}", text);
+            }
+            assert_eq!(
+                arguments(Language::En.text(text)),
+                arguments(Language::Ru.text(text)),
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_page_errors_hide_renderer_output() {
+        let fetch_failed = PageError::FetchFailed {
+            message: "Can't run /usr/bin/single-file --proxy-server=http://10.0.0.1:3128"
+                .to_string(),
+            transient: true,
+        };
+        let render_failed = PageError::RenderFailed {
+            diagnostics: RenderDiagnostics {
+                command: "/usr/bin/single-file https://example.com /work/page.html".to_string(),
+                exit_code: Some(1),
+                stderr: "net::ERR_CERT_DATE_INVALID".to_string(),
+                duration: Duration::from_millis(1500),
+            },
+            transient: false,
+        };
+        assert_eq!(
+            fetch_failed.user_message(Language::En),
+            "The site can't be reached"
+        );
+        assert_eq!(
+            render_failed.user_message(Language::Ru),
+            "Не удалось сохранить страницу"
+        );
+    }
+}
@@ -1,8 +1,24 @@
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+
 use async_trait::async_trait;
-use time::PrimitiveDateTime;
+use thiserror::Error;
+use time::{Date, PrimitiveDateTime};
+
+use crate::i18n::{Language, Text};
+
+pub mod i18n;
+pub mod worker_protocol;
 
+#[derive(Clone)]
 pub struct PageData {
     pub url: String,
+    pub format: PageFormat,
+    /// Bot the page is sent with, cached documents of other bots can't be reused
+    pub bot_id: Option<String>,
+    /// Load the page again even when it's cached recently
+    pub fresh: bool,
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -11,24 +27,319 @@ pub enum PageResult {
     TelegramId(String),
 }
 
+/// The loaded page together with how it was loaded.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LoadedPage {
+    pub result: PageResult,
+    pub metadata: PageMetadata,
+}
+
+impl From<PageResult> for LoadedPage {
+    fn from(result: PageResult) -> Self {
+        LoadedPage {
+            result,
+            metadata: PageMetadata::default(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct PageMetadata {
+    pub egress: Egress,
+    pub source: PageSource,
+    /// `<title>` of the page
+    pub title: Option<String>,
+    /// Url the page declares as its canonical one
+    pub canonical_url: Option<String>,
+    /// When the page was loaded from the site
+    pub fetched_at: Option<PrimitiveDateTime>,
+    /// The document was sent before and is taken from the cache instead of being loaded again
+    pub from_cache: bool,
+}
+
+/// Where the page was taken from, the live page is replaced by older copies when it fails.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub enum PageSource {
+    #[default]
+    Live,
+    /// Snapshot of a web archive
+    Archive {
+        archive: String,
+        snapshot_at: PrimitiveDateTime,
+    },
+    /// Copy from our cache that is older than the cache lifetime
+    Cache { cached_at: PrimitiveDateTime },
+}
+
+/// Network route the page was fetched through.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Egress {
+    #[default]
+    Direct,
+    Proxy,
+    Tor,
+}
+
+impl Egress {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Egress::Direct => "direct",
+            Egress::Proxy => "proxy",
+            Egress::Tor => "tor",
+        }
+    }
+
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "direct" => Ok(Egress::Direct),
+            "proxy" => Ok(Egress::Proxy),
+            "tor" => Ok(Egress::Tor),
+            _ => anyhow::bail!("Unknown egress {}", value),
+        }
+    }
+}
+
 impl PageData {
     pub fn from_url(url: String) -> Self {
-        PageData { url }
+        PageData {
+            url,
+            format: PageFormat::Html,
+            bot_id: None,
+            fresh: false,
+        }
+    }
+}
+
+/// Format of the document sent to the chat.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum PageFormat {
+    /// The page as rendered by singlefile
+    Html,
+    /// Gzip compressed page, takes less traffic for big pages
+    Gzip,
+}
+
+impl PageFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PageFormat::Html => "html",
+            PageFormat::Gzip => "gzip",
+        }
+    }
+
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "html" => Ok(PageFormat::Html),
+            "gzip" => Ok(PageFormat::Gzip),
+            _ => anyhow::bail!("Unknown page format {}", value),
+        }
+    }
+}
+
+/// Why a page can't be loaded, sent or stored.
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum PageError {
+    #[error("{0}")]
+    InvalidUrl(String),
+    #[error("{0}")]
+    BlockedByPolicy(String),
+    /// `transient` tells whether the page may load on the next attempt
+    #[error("{message}")]
+    FetchFailed { message: String, transient: bool },
+    /// The renderer failed, the diagnostics tell how
+    #[error("{diagnostics}")]
+    RenderFailed {
+        diagnostics: RenderDiagnostics,
+        transient: bool,
+    },
+    #[error("Loading the page timed out after {0:?}")]
+    Timeout(Duration),
+    #[error(
+        "The page is too large to send: {} MB, even compressed and split it's over {} MB",
+        .size / 1024 / 1024,
+        .limit / 1024 / 1024
+    )]
+    TooLarge { size: u64, limit: u64 },
+    #[error("Telegram didn't accept the page: {0}")]
+    UploadFailed(String),
+    #[error("Storage failed: {0}")]
+    StorageFailed(String),
+}
+
+impl PageError {
+    /// Failure of loading the page that may go away on the next attempt
+    pub fn fetch(err: impl Display) -> Self {
+        PageError::FetchFailed {
+            message: err.to_string(),
+            transient: true,
+        }
+    }
+
+    pub fn upload(err: impl Display) -> Self {
+        PageError::UploadFailed(err.to_string())
+    }
+
+    pub fn storage(err: impl Display) -> Self {
+        PageError::StorageFailed(err.to_string())
+    }
+
+    /// True when another attempt may succeed
+    pub fn is_transient(&self) -> bool {
+        match self {
+            PageError::FetchFailed { transient, .. }
+            | PageError::RenderFailed { transient, .. } => *transient,
+            PageError::Timeout(_) | PageError::UploadFailed(_) | PageError::StorageFailed(_) => {
+                true
+            }
+            PageError::InvalidUrl(_)
+            | PageError::BlockedByPolicy(_)
+            | PageError::TooLarge { .. } => false,
+        }
+    }
+
+    /// True when the link itself is not accepted, the page is not looked up anywhere
+    pub fn is_rejected(&self) -> bool {
+        matches!(
+            self,
+            PageError::InvalidUrl(_) | PageError::BlockedByPolicy(_)
+        )
+    }
+
+    pub fn diagnostics(&self) -> Option<&RenderDiagnostics> {
+        match self {
+            PageError::RenderFailed { diagnostics, .. } => Some(diagnostics),
+            _ => None,
+        }
+    }
+
+    /// Text shown to the user, internal failures are not explained.
+    pub fn user_message(&self, language: Language) -> String {
+        match self {
+            PageError::InvalidUrl(reason) => language.format(Text::InvalidUrl, &[reason]),
+            PageError::BlockedByPolicy(reason) => language.format(Text::BlockedByPolicy, &[reason]),
+            PageError::FetchFailed { .. } => language.text(Text::FetchFailed).to_string(),
+            PageError::RenderFailed { .. } => language.text(Text::RenderFailed).to_string(),
+            PageError::Timeout(timeout) => {
+                language.format(Text::Timeout, &[&format!("{:?}", timeout)])
+            }
+            PageError::TooLarge { size, limit } => language.format(
+                Text::TooLarge,
+                &[&(size / 1024 / 1024), &(limit / 1024 / 1024)],
+            ),
+            PageError::UploadFailed(reason) => language.format(Text::UploadFailed, &[reason]),
+            PageError::StorageFailed(_) => language.text(Text::StorageFailed).to_string(),
+        }
+    }
+}
+
+/// How the renderer was run when it failed.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RenderDiagnostics {
+    pub command: String,
+    /// `None` when the renderer was killed by a signal
+    pub exit_code: Option<i32>,
+    /// The end of the renderer output
+    pub stderr: String,
+    pub duration: Duration,
+}
+
+impl Display for RenderDiagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.exit_code {
+            Some(exit_code) => write!(f, "singlefile failed with exit code {}", exit_code)?,
+            None => write!(f, "singlefile was killed by a signal")?,
+        }
+        write!(f, " after {:.1?}: {}", self.duration, self.stderr)
     }
 }
 
 #[async_trait]
 pub trait PageWorker: Sync + Send {
-    async fn submit_page_generation(&self, page_data: PageData) -> anyhow::Result<PageResult>;
+    async fn submit_page_generation(&self, page_data: PageData) -> Result<LoadedPage, PageError>;
+}
+
+#[async_trait]
+impl<T: PageWorker + ?Sized> PageWorker for Box<T> {
+    async fn submit_page_generation(&self, page_data: PageData) -> Result<LoadedPage, PageError> {
+        (**self).submit_page_generation(page_data).await
+    }
+}
+
+#[async_trait]
+impl<T: PageWorker + ?Sized> PageWorker for Arc<T> {
+    async fn submit_page_generation(&self, page_data: PageData) -> Result<LoadedPage, PageError> {
+        (**self).submit_page_generation(page_data).await
+    }
+}
+
+/// Wraps a page worker with extra behaviour, e.g. retries, a timeout or a cache.
+pub trait PageWorkerLayer: Sync + Send {
+    fn layer(&self, inner: Box<dyn PageWorker>) -> Box<dyn PageWorker>;
+}
+
+impl<T: PageWorkerLayer + ?Sized> PageWorkerLayer for Box<T> {
+    fn layer(&self, inner: Box<dyn PageWorker>) -> Box<dyn PageWorker> {
+        (**self).layer(inner)
+    }
+}
+
+/// Builds a page worker from layers around the worker that loads pages.
+/// The first added layer is the outermost one and sees the requests first.
+#[derive(Default)]
+pub struct PageWorkerBuilder {
+    layers: Vec<Box<dyn PageWorkerLayer>>,
+}
+
+impl PageWorkerBuilder {
+    pub fn new() -> Self {
+        PageWorkerBuilder::default()
+    }
+
+    pub fn layer(mut self, layer: impl PageWorkerLayer + 'static) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    /// Adds the layer when it's configured
+    pub fn option_layer(self, layer: Option<impl PageWorkerLayer + 'static>) -> Self {
+        match layer {
+            Some(layer) => self.layer(layer),
+            None => self,
+        }
+    }
+
+    pub fn service(self, worker: impl PageWorker + 'static) -> Box<dyn PageWorker> {
+        self.layers
+            .iter()
+            .rev()
+            .fold(Box::new(worker), |inner, layer| layer.layer(inner))
+    }
 }
 
 #[async_trait]
 pub trait PageUploader: Sync + Send {
+    /// Sends the page of the url to the recipient, the metadata makes the caption of the document.
+    /// The buttons of the document refer to the job.
     async fn send_page(
         &self,
-        chat_id: &str,
+        recipient: &Recipient,
+        job_id: &str,
+        page_url: &str,
         page_result: &PageResult,
-    ) -> anyhow::Result<Option<String>>;
+        metadata: &PageMetadata,
+    ) -> Result<Option<String>, PageError>;
+}
+
+/// Chat a page is sent to.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Recipient {
+    /// Bot the page is sent with, `None` for the default bot of the backend
+    pub bot_id: Option<String>,
+    pub chat_id: String,
+    /// Message of the chat the page is sent in reply to
+    pub reply_to_message_id: Option<i32>,
+    /// Language of the caption and the buttons
+    pub language: Language,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -37,10 +348,291 @@ pub struct PageInfo {
     pub file_hash: String,
     pub page_url: String,
     pub timestamp_ms: PrimitiveDateTime,
+    /// Bot that uploaded the document, telegram file ids are valid only for that bot
+    pub bot_id: Option<String>,
+    pub egress: Egress,
+    pub title: Option<String>,
+    pub canonical_url: Option<String>,
 }
 
 #[async_trait]
 pub trait PagePersistent: Sync + Send {
-    async fn save(&self, page_info: &PageInfo) -> anyhow::Result<()>;
-    async fn get(&self, page_url: &str) -> anyhow::Result<Option<PageInfo>>;
+    async fn save(&self, page_info: &PageInfo) -> Result<(), PageError>;
+    async fn get(
+        &self,
+        page_url: &str,
+        bot_id: Option<&str>,
+    ) -> Result<Option<PageInfo>, PageError>;
+}
+
+/// Stage of a page request as shown to the user in the status message.
+#[derive(Debug, PartialEq, Clone)]
+pub enum JobStatus {
+    Queued,
+    Loading,
+    Uploading,
+    /// The document was delivered, the status message is not needed anymore.
+    Done,
+    Failed(String),
+    Cancelled,
+}
+
+/// A message in the user's chat that is edited in place while the job moves between stages.
+#[derive(Debug, PartialEq, Clone)]
+pub struct StatusMessage {
+    /// Bot that sent the message, only it can edit the message
+    pub bot_id: Option<String>,
+    pub chat_id: String,
+    pub message_id: i32,
+    pub language: Language,
+}
+
+#[async_trait]
+pub trait StatusReporter: Sync + Send {
+    async fn report_status(
+        &self,
+        message: &StatusMessage,
+        status: &JobStatus,
+    ) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "queued" => Ok(JobState::Queued),
+            "running" => Ok(JobState::Running),
+            "done" => Ok(JobState::Done),
+            "failed" => Ok(JobState::Failed),
+            "cancelled" => Ok(JobState::Cancelled),
+            _ => anyhow::bail!("Unknown job state {}", value),
+        }
+    }
+}
+
+/// A page requested by a chat, persisted so it survives restarts of the backend.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Job {
+    pub id: String,
+    /// Canonical url of the page, the jobs for the same page are loaded together
+    pub page_url: String,
+    pub chat_id: String,
+    pub status_message_id: Option<i32>,
+    pub state: JobState,
+    /// Number of failed attempts to load the page
+    pub attempts: u32,
+    pub next_attempt_at: PrimitiveDateTime,
+    pub last_error: Option<String>,
+    pub created_at: PrimitiveDateTime,
+    /// Key the job was requested with, `None` for requests of our own bot
+    pub api_key_id: Option<String>,
+    pub format: PageFormat,
+    /// Bot the page is sent with, `None` for the default bot of the backend
+    pub bot_id: Option<String>,
+    /// Url as it was requested, the page is rendered from it
+    pub original_url: String,
+    /// The cached document is not sent, the page is loaded again
+    pub fresh: bool,
+    /// Message of the chat the page is sent in reply to, e.g. the post with the link
+    pub reply_to_message_id: Option<i32>,
+    /// Language of the messages sent to the chat
+    pub language: Language,
+}
+
+impl Job {
+    pub fn status_message(&self) -> Option<StatusMessage> {
+        self.status_message_id.map(|message_id| StatusMessage {
+            bot_id: self.bot_id.clone(),
+            chat_id: self.chat_id.clone(),
+            message_id,
+            language: self.language,
+        })
+    }
+
+    pub fn recipient(&self) -> Recipient {
+        Recipient {
+            bot_id: self.bot_id.clone(),
+            chat_id: self.chat_id.clone(),
+            reply_to_message_id: self.reply_to_message_id,
+            language: self.language,
+        }
+    }
+}
+
+/// Jobs of a page claimed by a backend instance, nobody else loads the page until the lease ends.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ClaimedPage {
+    pub page_url: String,
+    pub jobs: Vec<Job>,
+}
+
+#[async_trait]
+pub trait JobPersistent: Sync + Send {
+    async fn save_job(&self, job: &Job) -> anyhow::Result<()>;
+    async fn update_job(&self, job: &Job) -> anyhow::Result<()>;
+    async fn get_job(&self, job_id: &str) -> anyhow::Result<Option<Job>>;
+    /// Claims the jobs of the oldest page that is due and not loaded by any instance.
+    /// Running jobs with an expired lease are claimed again, their owner is considered dead.
+    async fn claim_page(
+        &self,
+        owner: &str,
+        now: PrimitiveDateTime,
+        lease_until: PrimitiveDateTime,
+    ) -> anyhow::Result<Option<ClaimedPage>>;
+    /// Extends the lease of the page jobs owned by the instance.
+    /// Returns the number of jobs still waiting for the page.
+    async fn renew_lease(
+        &self,
+        owner: &str,
+        page_url: &str,
+        lease_until: PrimitiveDateTime,
+    ) -> anyhow::Result<u64>;
+    /// Takes the jobs of the page owned by the instance together with jobs queued
+    /// for the same page while it was loading.
+    async fn take_page_jobs(&self, owner: &str, page_url: &str) -> anyhow::Result<Vec<Job>>;
+    /// Marks the job as cancelled, returns `None` if it's already finished or doesn't exist.
+    async fn cancel_job(&self, job_id: &str) -> anyhow::Result<Option<Job>>;
+    /// Number of queued and running jobs of the page.
+    async fn count_active_jobs(&self, page_url: &str) -> anyhow::Result<u64>;
+}
+
+/// Key of a client that uses the backend with its own bot.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// Secret the client signs requests with
+    pub secret: String,
+    pub rate_limit_per_minute: u32,
+    /// How many pages the client can request per day
+    pub daily_quota: u32,
+    pub allowed_formats: Vec<PageFormat>,
+    /// Token of the client's bot the pages are uploaded with
+    pub bot_token: Option<String>,
+    pub revoked: bool,
+    pub created_at: PrimitiveDateTime,
+}
+
+impl ApiKey {
+    /// Telegram id of the key's bot
+    pub fn bot_id(&self) -> Option<String> {
+        self.bot_token.as_deref().and_then(bot_id_from_token)
+    }
+}
+
+/// Telegram bot tokens start with the bot id, e.g. `123456:secret`.
+pub fn bot_id_from_token(token: &str) -> Option<String> {
+    token
+        .split_once(':')
+        .map(|(bot_id, _)| bot_id.to_string())
+        .filter(|bot_id| !bot_id.is_empty())
+}
+
+#[async_trait]
+pub trait ApiKeyPersistent: Sync + Send {
+    async fn save_api_key(&self, api_key: &ApiKey) -> anyhow::Result<()>;
+    async fn get_api_key(&self, id: &str) -> anyhow::Result<Option<ApiKey>>;
+    async fn list_api_keys(&self) -> anyhow::Result<Vec<ApiKey>>;
+    /// Marks the key as revoked, returns false if the key doesn't exist or is already revoked.
+    async fn revoke_api_key(&self, id: &str) -> anyhow::Result<bool>;
+    /// Counts a request of the key for the day.
+    /// Returns false without counting if the daily quota is already used.
+    async fn try_use_quota(&self, id: &str, day: Date, daily_quota: u32) -> anyhow::Result<bool>;
+    /// Number of requests made with the key during the day.
+    async fn get_usage(&self, id: &str, day: Date) -> anyhow::Result<u64>;
+    /// Token of the bot registered with an active key.
+    async fn get_bot_token(&self, bot_id: &str) -> anyhow::Result<Option<String>>;
+}
+
+/// Page that failed to load, kept for operators.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PageFailure {
+    pub page_url: String,
+    pub error: String,
+    pub transient: bool,
+    /// Set when the renderer itself failed
+    pub diagnostics: Option<RenderDiagnostics>,
+    pub failed_at: PrimitiveDateTime,
+}
+
+#[async_trait]
+pub trait FailureLog: Sync + Send {
+    async fn record_failure(&self, failure: &PageFailure) -> anyhow::Result<()>;
+    /// The latest failures first, only of the urls that contain `url_filter` when it's set.
+    async fn list_failures(
+        &self,
+        url_filter: Option<&str>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<PageFailure>>;
+}
+
+/// Sent page a user marked as broken, kept for operators to review.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PageReport {
+    pub page_url: String,
+    pub chat_id: String,
+    /// Telegram file id of the reported document
+    pub telegram_file_id: Option<String>,
+    /// Bot the document was sent with, file ids are valid only for that bot
+    pub bot_id: Option<String>,
+    pub reported_at: PrimitiveDateTime,
+}
+
+#[async_trait]
+pub trait ReportPersistent: Sync + Send {
+    async fn save_report(&self, report: &PageReport) -> anyhow::Result<()>;
+    /// The latest reports first.
+    async fn list_reports(&self, limit: u32) -> anyhow::Result<Vec<PageReport>>;
+}
+
+/// Preferences of a chat, changed with the /settings menu of the bot.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ChatSettings {
+    pub chat_id: String,
+    /// Format of the sent pages
+    pub format: PageFormat,
+    /// Links posted without the command are loaded too
+    pub auto_detect: bool,
+    /// Language of the bot messages, the one of the user's Telegram app when not set
+    pub language: Option<Language>,
+    /// Pages are always loaded again instead of sending the cached copies
+    pub always_fresh: bool,
+}
+
+impl ChatSettings {
+    /// Settings of a chat that never changed them.
+    pub fn new(chat_id: &str) -> Self {
+        ChatSettings {
+            chat_id: chat_id.to_string(),
+            format: PageFormat::Html,
+            auto_detect: true,
+            language: None,
+            always_fresh: false,
+        }
+    }
+}
+
+#[async_trait]
+pub trait ChatSettingsPersistent: Sync + Send {
+    /// Settings of the chat, the defaults when they were never saved.
+    async fn get_settings(&self, chat_id: &str) -> anyhow::Result<ChatSettings>;
+    async fn save_settings(&self, settings: &ChatSettings) -> anyhow::Result<()>;
 }
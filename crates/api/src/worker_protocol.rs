@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use serde_json::json;
+
+use crate::{PageError, RenderDiagnostics};
+
+/// Egress the worker loaded the page through, `direct` when not set
+pub const EGRESS_HEADER: &str = "x-egress";
+
+/// Answer of the API node to a worker that registers.
+#[derive(Debug, PartialEq)]
+pub struct Registration {
+    pub worker_id: String,
+    /// Tasks of the worker are given to other workers when it's silent for this long
+    pub lease_duration: Duration,
+}
+
+impl Registration {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "worker_id": self.worker_id,
+            "lease_seconds": self.lease_duration.as_secs(),
+        })
+    }
+
+    pub fn from_json(json: &serde_json::Value) -> anyhow::Result<Self> {
+        let worker_id = json["worker_id"]
+            .as_str()
+            .context("API node didn't return the worker id")?;
+        let lease_seconds = json["lease_seconds"]
+            .as_u64()
+            .context("API node didn't return the lease duration")?;
+        Ok(Registration {
+            worker_id: worker_id.to_string(),
+            lease_duration: Duration::from_secs(lease_seconds),
+        })
+    }
+}
+
+/// Page a worker claimed to render.
+#[derive(Debug, PartialEq)]
+pub struct RenderTask {
+    pub task_id: String,
+    pub page_url: String,
+}
+
+impl RenderTask {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "task_id": self.task_id,
+            "page_url": self.page_url,
+        })
+    }
+
+    pub fn from_json(json: &serde_json::Value) -> anyhow::Result<Self> {
+        let task_id = json["task_id"]
+            .as_str()
+            .context("API node didn't return the task id")?;
+        let page_url = json["page_url"]
+            .as_str()
+            .context("API node didn't return the page url")?;
+        Ok(RenderTask {
+            task_id: task_id.to_string(),
+            page_url: page_url.to_string(),
+        })
+    }
+}
+
+/// Failure of a page sent by a worker, with the diagnostics of the renderer when it has them.
+pub fn failure_to_json(error: &PageError) -> serde_json::Value {
+    let mut json = json!({ "error": error.to_string(), "transient": error.is_transient() });
+    if let Some(diagnostics) = error.diagnostics() {
+        json["diagnostics"] = json!({
+            "command": diagnostics.command,
+            "exit_code": diagnostics.exit_code,
+            "stderr": diagnostics.stderr,
+            "duration_ms": diagnostics.duration.as_millis() as u64,
+        });
+    }
+    json
+}
+
+/// Reads the failure sent by a worker, `None` when the error is not set.
+/// Workers that don't classify errors get their pages retried.
+pub fn failure_from_json(json: &serde_json::Value) -> Option<PageError> {
+    let message = json["error"].as_str()?.to_string();
+    let transient = json["transient"].as_bool().unwrap_or(true);
+    Some(match read_diagnostics(&json["diagnostics"]) {
+        Some(diagnostics) => PageError::RenderFailed {
+            diagnostics,
+            transient,
+        },
+        None => PageError::FetchFailed { message, transient },
+    })
+}
+
+/// Diagnostics of the renderer, the command is required
+fn read_diagnostics(diagnostics: &serde_json::Value) -> Option<RenderDiagnostics> {
+    Some(RenderDiagnostics {
+        command: diagnostics["command"].as_str()?.to_string(),
+        exit_code: diagnostics["exit_code"]
+            .as_i64()
+            .map(|exit_code| exit_code as i32),
+        stderr: diagnostics["stderr"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        duration: Duration::from_millis(diagnostics["duration_ms"].as_u64().unwrap_or_default()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::worker_protocol::{failure_from_json, failure_to_json, Registration, RenderTask};
+    use crate::{PageError, RenderDiagnostics};
+
+    #[test]
+    fn test_messages_survive_the_round_trip() -> anyhow::Result<()> {
+        let registration = Registration {
+            worker_id: "worker".to_string(),
+            lease_duration: Duration::from_secs(60),
+        };
+        assert_eq!(
+            Registration::from_json(&registration.to_json())?,
+            registration
+        );
+        let task = RenderTask {
+            task_id: "task".to_string(),
+            page_url: "https://example.com".to_string(),
+        };
+        assert_eq!(RenderTask::from_json(&task.to_json())?, task);
+        assert!(RenderTask::from_json(&serde_json::json!({ "task_id": "task" })).is_err());
+
+        let error = PageError::RenderFailed {
+            diagnostics: RenderDiagnostics {
+                command: "single-file https://example.com page.html".to_string(),
+                exit_code: Some(1),
+                stderr: "net::ERR_CERT_DATE_INVALID".to_string(),
+                duration: Duration::from_millis(1500),
+            },
+            transient: false,
+        };
+        assert_eq!(failure_from_json(&failure_to_json(&error)), Some(error));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unclassified_failures_are_transient() {
+        let failure = failure_from_json(&serde_json::json!({ "error": "Worker crashed" }));
+        assert_eq!(
+            failure,
+            Some(PageError::FetchFailed {
+                message: "Worker crashed".to_string(),
+                transient: true,
+            })
+        );
+        assert_eq!(failure_from_json(&serde_json::json!({})), None);
+    }
+}
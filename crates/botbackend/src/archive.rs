@@ -0,0 +1,255 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::Client;
+use time::macros::format_description;
+use time::PrimitiveDateTime;
+
+use api::{LoadedPage, PageData, PageError, PageSource, PageWorker};
+
+const ARCHIVE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Snapshot of a page kept by a web archive
+#[derive(Debug, PartialEq)]
+pub struct Snapshot {
+    pub url: String,
+    pub taken_at: PrimitiveDateTime,
+}
+
+/// Web archive the page is taken from when the live page can't be loaded.
+#[async_trait]
+pub trait ArchiveClient: Sync + Send {
+    /// Name of the archive shown to the user
+    fn name(&self) -> &str;
+    /// The most recent snapshot of the page, `None` if the page was never archived.
+    async fn latest_snapshot(&self, url: &str) -> anyhow::Result<Option<Snapshot>>;
+}
+
+/// Client of the Wayback Machine availability API.
+pub struct WaybackClient {
+    api_url: String,
+    client: Client,
+}
+
+impl WaybackClient {
+    /// Takes the url of the API, `https://archive.org` for the Wayback Machine itself.
+    pub fn new(api_url: &str) -> anyhow::Result<Self> {
+        let client = Client::builder().timeout(ARCHIVE_TIMEOUT).build()?;
+        Ok(WaybackClient {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            client,
+        })
+    }
+}
+
+#[async_trait]
+impl ArchiveClient for WaybackClient {
+    fn name(&self) -> &str {
+        "Wayback Machine"
+    }
+
+    async fn latest_snapshot(&self, url: &str) -> anyhow::Result<Option<Snapshot>> {
+        let response: serde_json::Value = self
+            .client
+            .get(format!("{}/wayback/available", self.api_url))
+            .query(&[("url", url)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let closest = &response["archived_snapshots"]["closest"];
+        if closest["available"].as_bool() != Some(true) || closest["status"] != "200" {
+            return Ok(None);
+        }
+        let snapshot_url = closest["url"]
+            .as_str()
+            .context("Snapshot has no url")?
+            .to_string();
+        let timestamp = closest["timestamp"]
+            .as_str()
+            .context("Snapshot has no timestamp")?;
+        let taken_at = PrimitiveDateTime::parse(
+            timestamp,
+            format_description!("[year][month][day][hour][minute][second]"),
+        )
+        .with_context(|| format!("Invalid snapshot timestamp {}", timestamp))?;
+        Ok(Some(Snapshot {
+            url: snapshot_url,
+            taken_at,
+        }))
+    }
+}
+
+/// Loads the latest snapshot of the page from the archive with the renderer
+/// used for live pages.
+pub struct ArchivePageWorker {
+    archive: Box<dyn ArchiveClient>,
+    renderer: Arc<dyn PageWorker>,
+}
+
+impl ArchivePageWorker {
+    pub fn new(archive: Box<dyn ArchiveClient>, renderer: Arc<dyn PageWorker>) -> Self {
+        ArchivePageWorker { archive, renderer }
+    }
+}
+
+#[async_trait]
+impl PageWorker for ArchivePageWorker {
+    async fn submit_page_generation(&self, page_data: PageData) -> Result<LoadedPage, PageError> {
+        let snapshot = self
+            .archive
+            .latest_snapshot(&page_data.url)
+            .await
+            .map_err(|err| PageError::fetch(format!("{:#}", err)))?;
+        let Some(snapshot) = snapshot else {
+            return Err(PageError::FetchFailed {
+                message: format!("{} has no snapshots of the page", self.archive.name()),
+                transient: false,
+            });
+        };
+        println!(
+            "Loading snapshot {} from {}",
+            snapshot.url,
+            self.archive.name()
+        );
+        let mut page = self
+            .renderer
+            .submit_page_generation(PageData {
+                url: snapshot.url,
+                ..page_data
+            })
+            .await?;
+        page.metadata.source = PageSource::Archive {
+            archive: self.archive.name().to_string(),
+            snapshot_at: snapshot.taken_at,
+        };
+        Ok(page)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use time::macros::datetime;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use api::{LoadedPage, PageData, PageError, PageResult, PageSource, PageWorker};
+
+    use crate::archive::{ArchiveClient, ArchivePageWorker, Snapshot, WaybackClient};
+
+    /// Answers every request with the body and keeps the request lines
+    async fn start_stub(body: &'static str) -> anyhow::Result<(String, Arc<Mutex<Vec<String>>>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = format!("http://{}", listener.local_addr()?);
+        let requests = Arc::new(Mutex::new(vec![]));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let size = stream.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..size]).to_string();
+                let line = request.lines().next().unwrap_or_default().to_string();
+                received.lock().unwrap().push(line);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        Ok((address, requests))
+    }
+
+    #[tokio::test]
+    async fn test_wayback_latest_snapshot() -> anyhow::Result<()> {
+        let (address, requests) = start_stub(
+            r#"{"url": "example.com/a", "archived_snapshots": {"closest": {"status": "200",
+            "available": true, "url": "http://web.archive.org/web/20240102030405/https://example.com/a",
+            "timestamp": "20240102030405"}}}"#,
+        )
+        .await?;
+        let client = WaybackClient::new(&address)?;
+
+        assert_eq!(
+            client.latest_snapshot("https://example.com/a").await?,
+            Some(Snapshot {
+                url: "http://web.archive.org/web/20240102030405/https://example.com/a".to_string(),
+                taken_at: datetime!(2024-01-02 03:04:05),
+            })
+        );
+        assert_eq!(
+            requests.lock().unwrap()[0],
+            "GET /wayback/available?url=https%3A%2F%2Fexample.com%2Fa HTTP/1.1"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wayback_no_snapshot() -> anyhow::Result<()> {
+        let (address, _) =
+            start_stub(r#"{"url": "example.com/a", "archived_snapshots": {}}"#).await?;
+        let client = WaybackClient::new(&address)?;
+
+        assert_eq!(client.latest_snapshot("https://example.com/a").await?, None);
+        Ok(())
+    }
+
+    struct StubArchive {}
+
+    #[async_trait]
+    impl ArchiveClient for StubArchive {
+        fn name(&self) -> &str {
+            "Stub archive"
+        }
+
+        async fn latest_snapshot(&self, url: &str) -> anyhow::Result<Option<Snapshot>> {
+            Ok(Some(Snapshot {
+                url: format!("https://archive.test/{}", url),
+                taken_at: datetime!(2024-01-02 03:04:05),
+            }))
+        }
+    }
+
+    /// Returns the url it was asked to render as the file path
+    struct EchoPageWorker {}
+
+    #[async_trait]
+    impl PageWorker for EchoPageWorker {
+        async fn submit_page_generation(
+            &self,
+            page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            Ok(PageResult::FilePath(page_data.url).into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_renders_snapshot() -> anyhow::Result<()> {
+        let worker = ArchivePageWorker::new(Box::new(StubArchive {}), Arc::new(EchoPageWorker {}));
+
+        let page = worker
+            .submit_page_generation(PageData::from_url("https://example.com/a".to_string()))
+            .await?;
+
+        assert_eq!(
+            page.result,
+            PageResult::FilePath("https://archive.test/https://example.com/a".to_string())
+        );
+        assert_eq!(
+            page.metadata.source,
+            PageSource::Archive {
+                archive: "Stub archive".to_string(),
+                snapshot_at: datetime!(2024-01-02 03:04:05),
+            }
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use std::net::SocketAddr;
+
+use anyhow::{bail, Context};
+use reqwest::Url;
+use tokio::io::{copy_bidirectional, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use crate::url_policy::UrlPolicy;
+
+/// Requests with a larger head are dropped
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+const FORBIDDEN_RESPONSE: &[u8] =
+    b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const CONNECTED_RESPONSE: &[u8] = b"HTTP/1.1 200 Connection established\r\n\r\n";
+/// Hop-by-hop headers of the browser, the connection to the site is closed after the answer
+const DROPPED_HEADERS: [&str; 3] = ["proxy-connection", "connection", "keep-alive"];
+
+/// Local HTTP proxy the browser loads pages through when they are not loaded through
+/// a proxy or Tor. Every connection of the browser, redirects and resources of the page
+/// included, is checked against the url policy and goes to the address that was checked,
+/// so a host can't point to the internal network after the check.
+pub struct EgressGuard {
+    address: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl EgressGuard {
+    pub async fn start(url_policy: UrlPolicy) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let url_policy = url_policy.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = serve(&url_policy, stream).await {
+                        println!("Egress guard dropped the connection: {:#}", err);
+                    }
+                });
+            }
+        });
+        Ok(EgressGuard { address, task })
+    }
+
+    /// Arguments of chromium that send all its connections through the guard,
+    /// loopback addresses are not sent to proxies by default
+    pub fn browser_args(&self) -> Vec<String> {
+        vec![
+            format!("--proxy-server=http://{}", self.address),
+            "--proxy-bypass-list=<-loopback>".to_string(),
+        ]
+    }
+}
+
+impl Drop for EgressGuard {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(url_policy: &UrlPolicy, mut browser: TcpStream) -> anyhow::Result<()> {
+    let (head, rest) = read_head(&mut browser).await?;
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        bail!("Malformed request line {}", request_line);
+    };
+
+    if method.eq_ignore_ascii_case("CONNECT") {
+        let Some((host, port)) = target.rsplit_once(':') else {
+            bail!("Malformed CONNECT target {}", target);
+        };
+        let port = port.parse().context("Malformed CONNECT port")?;
+        let Some(mut site) = connect(url_policy, &mut browser, host, port).await? else {
+            return Ok(());
+        };
+        browser.write_all(CONNECTED_RESPONSE).await?;
+        site.write_all(&rest).await?;
+        copy_bidirectional(&mut browser, &mut site).await?;
+        return Ok(());
+    }
+
+    // plain http requests come with the absolute url, https ones are tunnelled
+    let url = Url::parse(target).context("Malformed request target")?;
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        bail!("Request target {} has no host", target);
+    };
+    if url.scheme() != "http" {
+        browser.write_all(FORBIDDEN_RESPONSE).await?;
+        return Ok(());
+    }
+    let Some(mut site) = connect(url_policy, &mut browser, host, port).await? else {
+        return Ok(());
+    };
+    let path = &url[url::Position::BeforePath..url::Position::AfterQuery];
+    let mut request = format!("{} {} {}\r\n", method, path, version);
+    for header in lines.filter(|line| !line.is_empty()) {
+        let name = header.split(':').next().unwrap_or_default().trim();
+        if !DROPPED_HEADERS
+            .iter()
+            .any(|dropped| name.eq_ignore_ascii_case(dropped))
+        {
+            request.push_str(header);
+            request.push_str("\r\n");
+        }
+    }
+    request.push_str("Connection: close\r\n\r\n");
+    site.write_all(request.as_bytes()).await?;
+    site.write_all(&rest).await?;
+    copy_bidirectional(&mut browser, &mut site).await?;
+    Ok(())
+}
+
+/// Connects to the checked address of the host, the browser gets 403 when the policy
+/// rejects the host
+async fn connect(
+    url_policy: &UrlPolicy,
+    browser: &mut TcpStream,
+    host: &str,
+    port: u16,
+) -> anyhow::Result<Option<TcpStream>> {
+    match url_policy.check_address(host, port).await {
+        Ok(address) => Ok(Some(TcpStream::connect(address).await?)),
+        Err(err) => {
+            println!("Egress guard rejected {}:{}: {}", host, port, err);
+            browser.write_all(FORBIDDEN_RESPONSE).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Head of the request and the bytes of the body read with it
+async fn read_head(stream: &mut TcpStream) -> anyhow::Result<(String, Vec<u8>)> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            let rest = buffer.split_off(end + 4);
+            buffer.truncate(end);
+            return Ok((String::from_utf8(buffer)?, rest));
+        }
+        if buffer.len() > MAX_HEAD_SIZE {
+            bail!("Request head is too large");
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            bail!("Connection closed before the request head");
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::egress_guard::EgressGuard;
+    use crate::url_policy::UrlPolicy;
+
+    /// Site on the loopback that answers every request with the request itself
+    async fn echo_site() -> anyhow::Result<u16> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let read = stream.read(&mut request).await.unwrap_or_default();
+                let request = String::from_utf8_lossy(&request[..read]).to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                    request.len(),
+                    request
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        Ok(port)
+    }
+
+    async fn send_through(guard: &EgressGuard, request: &str) -> anyhow::Result<String> {
+        let mut stream = TcpStream::connect(guard.address).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn test_forwards_allowed_requests() -> anyhow::Result<()> {
+        let port = echo_site().await?;
+        let policy = UrlPolicy::new(vec!["127.0.0.1".to_string()], vec![]);
+        let guard = EgressGuard::start(policy).await?;
+
+        let request = format!(
+            "GET http://127.0.0.1:{}/page?a=1 HTTP/1.1\r\nHost: 127.0.0.1\r\n\
+             Proxy-Connection: keep-alive\r\n\r\n",
+            port
+        );
+        let response = send_through(&guard, &request).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("GET /page?a=1 HTTP/1.1\r\nHost: 127.0.0.1\r\n"));
+        assert!(response.contains("Connection: close"));
+        assert!(!response.contains("Proxy-Connection"));
+
+        // redirects of the page to other hosts are checked as well
+        let request = format!(
+            "GET http://localhost:{}/admin HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            port
+        );
+        let response = send_through(&guard, &request).await?;
+        assert!(
+            response.starts_with("HTTP/1.1 403 Forbidden"),
+            "{}",
+            response
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejects_tunnels_to_private_addresses() -> anyhow::Result<()> {
+        let port = echo_site().await?;
+        let guard = EgressGuard::start(UrlPolicy::default()).await?;
+
+        for target in [
+            format!("127.0.0.1:{}", port),
+            format!("localhost:{}", port),
+            "[::1]:443".to_string(),
+            "169.254.169.254:80".to_string(),
+        ] {
+            let request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, target);
+            let response = send_through(&guard, &request).await?;
+            assert!(response.starts_with("HTTP/1.1 403 Forbidden"), "{}", target);
+        }
+
+        let allowed =
+            EgressGuard::start(UrlPolicy::new(vec!["127.0.0.1".to_string()], vec![])).await?;
+        let mut stream = TcpStream::connect(allowed.address).await?;
+        let request = format!(
+            "CONNECT 127.0.0.1:{} HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+            port
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        assert!(response.starts_with("HTTP/1.1 200 Connection established\r\n\r\nHTTP/1.1 200 OK"));
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use api::{
+    LoadedPage, PageData, PageError, PageFormat, PageMetadata, PagePersistent, PageResult,
+    PageSource, PageWorker,
+};
+use utils::canonical_url::canonicalize;
+
+/// Tries the live page first and the fallbacks in order when it fails,
+/// e.g. web archives and older copies from the cache.
+///
+/// Pages rejected by the url policy are not looked up anywhere else.
+/// When every worker fails the error of the live page is returned.
+pub struct FallbackPageWorker {
+    live: Arc<dyn PageWorker>,
+    fallbacks: Vec<Box<dyn PageWorker>>,
+}
+
+impl FallbackPageWorker {
+    pub fn new(live: Arc<dyn PageWorker>) -> Self {
+        FallbackPageWorker {
+            live,
+            fallbacks: vec![],
+        }
+    }
+
+    pub fn with_fallback(mut self, fallback: Box<dyn PageWorker>) -> Self {
+        self.fallbacks.push(fallback);
+        self
+    }
+}
+
+#[async_trait]
+impl PageWorker for FallbackPageWorker {
+    async fn submit_page_generation(&self, page_data: PageData) -> Result<LoadedPage, PageError> {
+        let live_error = match self.live.submit_page_generation(page_data.clone()).await {
+            Ok(page) => return Ok(page),
+            Err(err) => err,
+        };
+        if live_error.is_rejected() || self.fallbacks.is_empty() {
+            return Err(live_error);
+        }
+        println!(
+            "Can't load {}, trying fallbacks: {:#}",
+            page_data.url, live_error
+        );
+        for fallback in &self.fallbacks {
+            match fallback.submit_page_generation(page_data.clone()).await {
+                Ok(page) => return Ok(page),
+                Err(err) => println!("Fallback for {} failed: {:#}", page_data.url, err),
+            }
+        }
+        Err(live_error)
+    }
+}
+
+/// Sends the cached copy of the page even when it's older than the cache lifetime.
+pub struct StaleCachePageWorker {
+    storage: Arc<dyn PagePersistent>,
+}
+
+impl StaleCachePageWorker {
+    pub fn new(storage: Arc<dyn PagePersistent>) -> Self {
+        StaleCachePageWorker { storage }
+    }
+}
+
+#[async_trait]
+impl PageWorker for StaleCachePageWorker {
+    async fn submit_page_generation(&self, page_data: PageData) -> Result<LoadedPage, PageError> {
+        // cached documents can't be converted to other formats
+        if page_data.format != PageFormat::Html {
+            return Err(not_found("Only html pages are cached"));
+        }
+        let cached = self
+            .storage
+            .get(&canonicalize(&page_data.url), page_data.bot_id.as_deref())
+            .await?;
+        let Some(cached) = cached else {
+            return Err(not_found("The page is not cached"));
+        };
+        Ok(LoadedPage {
+            result: PageResult::TelegramId(cached.telegram_file_id),
+            metadata: PageMetadata {
+                egress: cached.egress,
+                source: PageSource::Cache {
+                    cached_at: cached.timestamp_ms,
+                },
+                title: cached.title,
+                canonical_url: cached.canonical_url,
+                fetched_at: Some(cached.timestamp_ms),
+                from_cache: true,
+            },
+        })
+    }
+}
+
+fn not_found(message: &str) -> PageError {
+    PageError::FetchFailed {
+        message: message.to_string(),
+        transient: false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use time::macros::datetime;
+
+    use api::{
+        Egress, LoadedPage, PageData, PageError, PageInfo, PagePersistent, PageResult, PageSource,
+        PageWorker,
+    };
+
+    use crate::fallback_page_worker::{FallbackPageWorker, StaleCachePageWorker};
+    use crate::render_error::singlefile_error;
+    use crate::url_policy::UrlRejected;
+
+    struct FailingPageWorker {}
+
+    #[async_trait]
+    impl PageWorker for FailingPageWorker {
+        async fn submit_page_generation(
+            &self,
+            _page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            Err(singlefile_error(
+                "single-file https://example.com page.html".to_string(),
+                Some(1),
+                "net::ERR_BLOCKED_BY_ADMINISTRATOR",
+                Duration::from_secs(1),
+            ))
+        }
+    }
+
+    struct RejectingPageWorker {}
+
+    #[async_trait]
+    impl PageWorker for RejectingPageWorker {
+        async fn submit_page_generation(
+            &self,
+            _page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            Err(UrlRejected::PrivateAddress("localhost".to_string()).into())
+        }
+    }
+
+    struct CountingPageWorker {
+        calls: Arc<AtomicU32>,
+    }
+
+    #[async_trait]
+    impl PageWorker for CountingPageWorker {
+        async fn submit_page_generation(
+            &self,
+            _page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(PageResult::FilePath("/archived".to_string()).into())
+        }
+    }
+
+    struct TestPagePersistent {}
+
+    #[async_trait]
+    impl PagePersistent for TestPagePersistent {
+        async fn save(&self, _page_info: &PageInfo) -> Result<(), PageError> {
+            Ok(())
+        }
+
+        async fn get(
+            &self,
+            page_url: &str,
+            bot_id: Option<&str>,
+        ) -> Result<Option<PageInfo>, PageError> {
+            Ok((page_url == "https://example.com/a").then(|| PageInfo {
+                telegram_file_id: "old_file_id".to_string(),
+                file_hash: "hash".to_string(),
+                page_url: page_url.to_string(),
+                timestamp_ms: datetime!(2024-01-02 10:10:10),
+                bot_id: bot_id.map(str::to_string),
+                egress: Egress::Proxy,
+                title: None,
+                canonical_url: None,
+            }))
+        }
+    }
+
+    fn page_data(url: &str) -> PageData {
+        PageData::from_url(url.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_in_order() -> anyhow::Result<()> {
+        let calls = Arc::new(AtomicU32::new(0));
+        let worker = FallbackPageWorker::new(Arc::new(FailingPageWorker {}))
+            .with_fallback(Box::new(FailingPageWorker {}))
+            .with_fallback(Box::new(CountingPageWorker {
+                calls: calls.clone(),
+            }))
+            .with_fallback(Box::new(StaleCachePageWorker::new(Arc::new(
+                TestPagePersistent {},
+            ))));
+
+        let page = worker
+            .submit_page_generation(page_data("https://example.com/a"))
+            .await?;
+
+        assert_eq!(page.result, PageResult::FilePath("/archived".to_string()));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_stale_cache() -> anyhow::Result<()> {
+        let worker = FallbackPageWorker::new(Arc::new(FailingPageWorker {})).with_fallback(
+            Box::new(StaleCachePageWorker::new(Arc::new(TestPagePersistent {}))),
+        );
+
+        let page = worker
+            .submit_page_generation(page_data("https://Example.com/a/#top"))
+            .await?;
+
+        assert_eq!(
+            page.result,
+            PageResult::TelegramId("old_file_id".to_string())
+        );
+        assert_eq!(
+            page.metadata.source,
+            PageSource::Cache {
+                cached_at: datetime!(2024-01-02 10:10:10)
+            }
+        );
+        assert_eq!(page.metadata.egress, Egress::Proxy);
+
+        let err = worker
+            .submit_page_generation(page_data("https://example.com/b"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("ERR_BLOCKED_BY_ADMINISTRATOR"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejected_pages_are_not_looked_up() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let worker = FallbackPageWorker::new(Arc::new(RejectingPageWorker {})).with_fallback(
+            Box::new(CountingPageWorker {
+                calls: calls.clone(),
+            }),
+        );
+
+        let result = worker
+            .submit_page_generation(page_data("http://localhost/"))
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,649 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use rand::Rng;
+use time::{OffsetDateTime, PrimitiveDateTime};
+use tokio::sync::Semaphore;
+
+use api::{FailureLog, LoadedPage, PageData, PageError, PageFailure, PageWorker, PageWorkerLayer};
+
+use crate::url_policy::UrlPolicy;
+
+/// Longest delay between attempts of the retry layer by default
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Fails the page when the inner worker takes longer than the timeout.
+pub struct TimeoutLayer {
+    timeout: Duration,
+}
+
+impl TimeoutLayer {
+    pub fn new(timeout: Duration) -> Self {
+        TimeoutLayer { timeout }
+    }
+}
+
+impl PageWorkerLayer for TimeoutLayer {
+    fn layer(&self, inner: Box<dyn PageWorker>) -> Box<dyn PageWorker> {
+        Box::new(TimeoutPageWorker {
+            timeout: self.timeout,
+            inner,
+        })
+    }
+}
+
+struct TimeoutPageWorker {
+    timeout: Duration,
+    inner: Box<dyn PageWorker>,
+}
+
+#[async_trait]
+impl PageWorker for TimeoutPageWorker {
+    async fn submit_page_generation(&self, page_data: PageData) -> Result<LoadedPage, PageError> {
+        let loading = self.inner.submit_page_generation(page_data);
+        match tokio::time::timeout(self.timeout, loading).await {
+            Ok(result) => result,
+            Err(_) => Err(PageError::Timeout(self.timeout)),
+        }
+    }
+}
+
+/// Loads the page again when it fails with a transient error, e.g. a timeout or a crash
+/// of the browser. The delay doubles with every attempt up to the cap, a random part of it
+/// is dropped, so pages that failed together are not loaded again at the same moment.
+pub struct RetryLayer {
+    attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+impl RetryLayer {
+    /// `attempts` counts the first attempt as well
+    pub fn new(attempts: u32, base_delay: Duration) -> Self {
+        RetryLayer {
+            attempts: attempts.max(1),
+            base_delay,
+            max_delay: MAX_RETRY_DELAY,
+        }
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+}
+
+impl PageWorkerLayer for RetryLayer {
+    fn layer(&self, inner: Box<dyn PageWorker>) -> Box<dyn PageWorker> {
+        Box::new(RetryPageWorker {
+            attempts: self.attempts,
+            base_delay: self.base_delay,
+            max_delay: self.max_delay,
+            inner,
+        })
+    }
+}
+
+struct RetryPageWorker {
+    attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    inner: Box<dyn PageWorker>,
+}
+
+/// Delay after the failed attempt, between the half and the full exponential delay.
+/// Used by every retry of pages, so pages that failed together don't come back together.
+pub fn backoff_delay(base_delay: Duration, max_delay: Duration, attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    let delay = base_delay.saturating_mul(factor).min(max_delay);
+    rand::thread_rng().gen_range(delay / 2..=delay)
+}
+
+#[async_trait]
+impl PageWorker for RetryPageWorker {
+    async fn submit_page_generation(&self, page_data: PageData) -> Result<LoadedPage, PageError> {
+        let mut attempt = 1;
+        loop {
+            let err = match self.inner.submit_page_generation(page_data.clone()).await {
+                Ok(page) => {
+                    if attempt > 1 {
+                        println!("Loaded {} on attempt {}", page_data.url, attempt);
+                    }
+                    return Ok(page);
+                }
+                Err(err) => err,
+            };
+            if !err.is_transient() {
+                println!(
+                    "Attempt {} to load {} failed permanently: {:#}",
+                    attempt, page_data.url, err
+                );
+                return Err(err);
+            }
+            if attempt >= self.attempts {
+                println!(
+                    "Attempt {} to load {} failed, giving up: {:#}",
+                    attempt, page_data.url, err
+                );
+                return Err(err);
+            }
+            let delay = backoff_delay(self.base_delay, self.max_delay, attempt);
+            println!(
+                "Attempt {} to load {} failed, retry in {:?}: {:#}",
+                attempt, page_data.url, delay, err
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Limits how many pages are loaded at the same time, other pages wait for their turn.
+/// Workers made by the same layer share the limit.
+pub struct ConcurrencyLimitLayer {
+    permits: Arc<Semaphore>,
+}
+
+impl ConcurrencyLimitLayer {
+    pub fn new(max_pages: usize) -> Self {
+        ConcurrencyLimitLayer {
+            permits: Arc::new(Semaphore::new(max_pages.max(1))),
+        }
+    }
+}
+
+impl PageWorkerLayer for ConcurrencyLimitLayer {
+    fn layer(&self, inner: Box<dyn PageWorker>) -> Box<dyn PageWorker> {
+        Box::new(ConcurrencyLimitPageWorker {
+            permits: self.permits.clone(),
+            inner,
+        })
+    }
+}
+
+struct ConcurrencyLimitPageWorker {
+    permits: Arc<Semaphore>,
+    inner: Box<dyn PageWorker>,
+}
+
+#[async_trait]
+impl PageWorker for ConcurrencyLimitPageWorker {
+    async fn submit_page_generation(&self, page_data: PageData) -> Result<LoadedPage, PageError> {
+        let _permit = self.permits.acquire().await.map_err(PageError::fetch)?;
+        self.inner.submit_page_generation(page_data).await
+    }
+}
+
+/// Counters of the pages that went through the metrics layer
+#[derive(Default)]
+pub struct PageMetrics {
+    loaded: AtomicU64,
+    failed: AtomicU64,
+    load_time_ms: AtomicU64,
+}
+
+impl PageMetrics {
+    pub fn loaded(&self) -> u64 {
+        self.loaded.load(Ordering::Relaxed)
+    }
+
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Average time of the loaded and failed pages
+    pub fn average_load_time(&self) -> Duration {
+        let pages = self.loaded() + self.failed();
+        if pages == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_millis(self.load_time_ms.load(Ordering::Relaxed) / pages)
+    }
+}
+
+/// Counts loaded and failed pages and how long they take.
+pub struct MetricsLayer {
+    metrics: Arc<PageMetrics>,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: Arc<PageMetrics>) -> Self {
+        MetricsLayer { metrics }
+    }
+}
+
+impl PageWorkerLayer for MetricsLayer {
+    fn layer(&self, inner: Box<dyn PageWorker>) -> Box<dyn PageWorker> {
+        Box::new(MetricsPageWorker {
+            metrics: self.metrics.clone(),
+            inner,
+        })
+    }
+}
+
+struct MetricsPageWorker {
+    metrics: Arc<PageMetrics>,
+    inner: Box<dyn PageWorker>,
+}
+
+#[async_trait]
+impl PageWorker for MetricsPageWorker {
+    async fn submit_page_generation(&self, page_data: PageData) -> Result<LoadedPage, PageError> {
+        let started = Instant::now();
+        let result = self.inner.submit_page_generation(page_data).await;
+        let elapsed = started.elapsed();
+        let counter = match result {
+            Ok(_) => &self.metrics.loaded,
+            Err(_) => &self.metrics.failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.metrics
+            .load_time_ms
+            .fetch_add(elapsed.as_millis() as u64, Ordering::Relaxed);
+        println!(
+            "Page {} in {:?}, loaded: {}, failed: {}",
+            if result.is_ok() { "loaded" } else { "failed" },
+            elapsed,
+            self.metrics.loaded(),
+            self.metrics.failed()
+        );
+        result
+    }
+}
+
+/// Records pages that failed to load to the failure log, together with the diagnostics
+/// of the renderer. Pages rejected by the url policy are not recorded.
+pub struct FailureLogLayer {
+    failures: Arc<dyn FailureLog>,
+}
+
+impl FailureLogLayer {
+    pub fn new(failures: Arc<dyn FailureLog>) -> Self {
+        FailureLogLayer { failures }
+    }
+}
+
+impl PageWorkerLayer for FailureLogLayer {
+    fn layer(&self, inner: Box<dyn PageWorker>) -> Box<dyn PageWorker> {
+        Box::new(FailureLogPageWorker {
+            failures: self.failures.clone(),
+            inner,
+        })
+    }
+}
+
+struct FailureLogPageWorker {
+    failures: Arc<dyn FailureLog>,
+    inner: Box<dyn PageWorker>,
+}
+
+#[async_trait]
+impl PageWorker for FailureLogPageWorker {
+    async fn submit_page_generation(&self, page_data: PageData) -> Result<LoadedPage, PageError> {
+        let page_url = page_data.url.clone();
+        let result = self.inner.submit_page_generation(page_data).await;
+        if let Err(err) = &result {
+            if !err.is_rejected() {
+                let now = OffsetDateTime::now_utc();
+                let failure = PageFailure {
+                    page_url,
+                    error: err.to_string(),
+                    transient: err.is_transient(),
+                    diagnostics: err.diagnostics().cloned(),
+                    failed_at: PrimitiveDateTime::new(now.date(), now.time()),
+                };
+                if let Err(log_err) = self.failures.record_failure(&failure).await {
+                    println!(
+                        "Can't record the failure of {}: {:#}",
+                        failure.page_url, log_err
+                    );
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Checks the url against the policy before the page is given to the inner worker,
+/// e.g. once more when a queued page is handed over to render workers.
+pub struct UrlPolicyLayer {
+    url_policy: UrlPolicy,
+}
+
+impl UrlPolicyLayer {
+    pub fn new(url_policy: UrlPolicy) -> Self {
+        UrlPolicyLayer { url_policy }
+    }
+}
+
+impl PageWorkerLayer for UrlPolicyLayer {
+    fn layer(&self, inner: Box<dyn PageWorker>) -> Box<dyn PageWorker> {
+        Box::new(UrlPolicyPageWorker {
+            url_policy: self.url_policy.clone(),
+            inner,
+        })
+    }
+}
+
+struct UrlPolicyPageWorker {
+    url_policy: UrlPolicy,
+    inner: Box<dyn PageWorker>,
+}
+
+#[async_trait]
+impl PageWorker for UrlPolicyPageWorker {
+    async fn submit_page_generation(&self, page_data: PageData) -> Result<LoadedPage, PageError> {
+        self.url_policy.check(&page_data.url).await?;
+        self.inner.submit_page_generation(page_data).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+
+    use api::{
+        FailureLog, LoadedPage, PageData, PageError, PageFailure, PageResult, PageWorker,
+        PageWorkerBuilder, PageWorkerLayer,
+    };
+
+    use crate::layers::{
+        backoff_delay, ConcurrencyLimitLayer, FailureLogLayer, MetricsLayer, PageMetrics,
+        RetryLayer, TimeoutLayer, UrlPolicyLayer,
+    };
+    use crate::render_error::singlefile_error;
+    use crate::url_policy::UrlPolicy;
+
+    /// Fails the given number of times before the page is loaded
+    struct FlakyPageWorker {
+        failures: AtomicU32,
+        calls: Arc<AtomicU32>,
+    }
+
+    impl FlakyPageWorker {
+        fn new(failures: u32) -> (Self, Arc<AtomicU32>) {
+            let calls = Arc::new(AtomicU32::new(0));
+            let worker = FlakyPageWorker {
+                failures: AtomicU32::new(failures),
+                calls: calls.clone(),
+            };
+            (worker, calls)
+        }
+    }
+
+    #[async_trait]
+    impl PageWorker for FlakyPageWorker {
+        async fn submit_page_generation(
+            &self,
+            page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let failures = self.failures.load(Ordering::SeqCst);
+            if failures > 0 {
+                self.failures.store(failures - 1, Ordering::SeqCst);
+                return Err(PageError::fetch("broken page"));
+            }
+            Ok(PageResult::FilePath(page_data.url).into())
+        }
+    }
+
+    struct SlowPageWorker {
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl PageWorker for SlowPageWorker {
+        async fn submit_page_generation(
+            &self,
+            page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            tokio::time::sleep(self.delay).await;
+            Ok(PageResult::FilePath(page_data.url).into())
+        }
+    }
+
+    /// Records its name for every page, so the order of the layers can be seen
+    struct NamedLayer {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    struct NamedPageWorker {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<&'static str>>>,
+        inner: Box<dyn PageWorker>,
+    }
+
+    impl PageWorkerLayer for NamedLayer {
+        fn layer(&self, inner: Box<dyn PageWorker>) -> Box<dyn PageWorker> {
+            Box::new(NamedPageWorker {
+                name: self.name,
+                calls: self.calls.clone(),
+                inner,
+            })
+        }
+    }
+
+    #[async_trait]
+    impl PageWorker for NamedPageWorker {
+        async fn submit_page_generation(
+            &self,
+            page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            self.calls.lock().unwrap().push(self.name);
+            self.inner.submit_page_generation(page_data).await
+        }
+    }
+
+    fn page_data() -> PageData {
+        PageData::from_url("https://example.com/".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_first_layer_is_outermost() -> anyhow::Result<()> {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let (worker, _) = FlakyPageWorker::new(0);
+        let worker = PageWorkerBuilder::new()
+            .layer(NamedLayer {
+                name: "outer",
+                calls: calls.clone(),
+            })
+            .option_layer(None::<NamedLayer>)
+            .layer(NamedLayer {
+                name: "inner",
+                calls: calls.clone(),
+            })
+            .service(worker);
+
+        worker.submit_page_generation(page_data()).await?;
+
+        assert_eq!(*calls.lock().unwrap(), vec!["outer", "inner"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retries_failed_pages() -> anyhow::Result<()> {
+        let (worker, calls) = FlakyPageWorker::new(2);
+        let worker = PageWorkerBuilder::new()
+            .layer(RetryLayer::new(3, Duration::from_millis(1)))
+            .service(worker);
+
+        worker.submit_page_generation(page_data()).await?;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let (worker, calls) = FlakyPageWorker::new(5);
+        let worker = PageWorkerBuilder::new()
+            .layer(RetryLayer::new(2, Duration::from_millis(1)))
+            .service(worker);
+
+        assert!(worker.submit_page_generation(page_data()).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    struct GonePageWorker {
+        calls: Arc<AtomicU32>,
+    }
+
+    #[async_trait]
+    impl PageWorker for GonePageWorker {
+        async fn submit_page_generation(
+            &self,
+            _page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Err(singlefile_error(
+                "single-file https://example.com page.html".to_string(),
+                Some(1),
+                "net::ERR_NAME_NOT_RESOLVED",
+                Duration::from_secs(1),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_permanent_failures_are_not_retried() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let worker = PageWorkerBuilder::new()
+            .layer(RetryLayer::new(3, Duration::from_millis(1)))
+            .service(GonePageWorker {
+                calls: calls.clone(),
+            });
+
+        assert!(worker.submit_page_generation(page_data()).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_retry_delay_is_jittered_and_capped() {
+        let delay =
+            |attempt| backoff_delay(Duration::from_secs(10), Duration::from_secs(60), attempt);
+
+        for _ in 0..100 {
+            let first = delay(1);
+            assert!(first >= Duration::from_secs(5) && first <= Duration::from_secs(10));
+            let capped = delay(8);
+            assert!(capped >= Duration::from_secs(30) && capped <= Duration::from_secs(60));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejected_pages_are_not_retried() {
+        let (worker, calls) = FlakyPageWorker::new(0);
+        let worker = PageWorkerBuilder::new()
+            .layer(RetryLayer::new(3, Duration::from_millis(1)))
+            .layer(UrlPolicyLayer::new(UrlPolicy::default()))
+            .service(worker);
+
+        let result = worker
+            .submit_page_generation(PageData::from_url("http://127.0.0.1/".to_string()))
+            .await;
+
+        assert_eq!(
+            result.unwrap_err(),
+            PageError::BlockedByPolicy("127.0.0.1 points to a private network".to_string())
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_times_out_slow_pages() {
+        let worker = PageWorkerBuilder::new()
+            .layer(TimeoutLayer::new(Duration::from_millis(10)))
+            .service(SlowPageWorker {
+                delay: Duration::from_secs(10),
+            });
+
+        assert!(worker.submit_page_generation(page_data()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_limits_parallel_pages() -> anyhow::Result<()> {
+        let worker: Arc<dyn PageWorker> = Arc::from(
+            PageWorkerBuilder::new()
+                .layer(ConcurrencyLimitLayer::new(1))
+                .layer(TimeoutLayer::new(Duration::from_millis(150)))
+                .service(SlowPageWorker {
+                    delay: Duration::from_millis(100),
+                }),
+        );
+
+        // the second page waits for the first one, so its own timeout is not affected
+        let (first, second) = tokio::join!(
+            worker.submit_page_generation(page_data()),
+            worker.submit_page_generation(page_data())
+        );
+        first?;
+        second?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_counts_pages() -> anyhow::Result<()> {
+        let metrics = Arc::new(PageMetrics::default());
+        let (worker, _) = FlakyPageWorker::new(1);
+        let worker = PageWorkerBuilder::new()
+            .layer(MetricsLayer::new(metrics.clone()))
+            .service(worker);
+
+        assert!(worker.submit_page_generation(page_data()).await.is_err());
+        worker.submit_page_generation(page_data()).await?;
+
+        assert_eq!(metrics.loaded(), 1);
+        assert_eq!(metrics.failed(), 1);
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct RecordingFailureLog {
+        failures: Mutex<Vec<PageFailure>>,
+    }
+
+    #[async_trait]
+    impl FailureLog for RecordingFailureLog {
+        async fn record_failure(&self, failure: &PageFailure) -> anyhow::Result<()> {
+            self.failures.lock().unwrap().push(failure.clone());
+            Ok(())
+        }
+
+        async fn list_failures(
+            &self,
+            _url_filter: Option<&str>,
+            _limit: u32,
+        ) -> anyhow::Result<Vec<PageFailure>> {
+            Ok(self.failures.lock().unwrap().clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_records_failures() -> anyhow::Result<()> {
+        let failures = Arc::new(RecordingFailureLog::default());
+        let (worker, _) = FlakyPageWorker::new(1);
+        let worker = PageWorkerBuilder::new()
+            .layer(FailureLogLayer::new(failures.clone()))
+            .service(worker);
+        assert!(worker.submit_page_generation(page_data()).await.is_err());
+        worker.submit_page_generation(page_data()).await?;
+
+        let (worker, _) = FlakyPageWorker::new(0);
+        let worker = PageWorkerBuilder::new()
+            .layer(FailureLogLayer::new(failures.clone()))
+            .layer(UrlPolicyLayer::new(UrlPolicy::default()))
+            .service(worker);
+        let rejected = PageData::from_url("http://127.0.0.1/".to_string());
+        assert!(worker.submit_page_generation(rejected).await.is_err());
+
+        let failures = failures.list_failures(None, 10).await?;
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].page_url, "https://example.com/");
+        assert_eq!(failures[0].error, "broken page");
+        assert!(failures[0].transient);
+        Ok(())
+    }
+}
@@ -1 +1,10 @@
-pub mod parallel_page_worker;
\ No newline at end of file
+pub mod archive;
+pub mod egress_guard;
+pub mod fallback_page_worker;
+pub mod layers;
+pub mod parallel_page_worker;
+pub mod pipeline;
+pub mod proxy_pool;
+pub mod render_error;
+pub mod tor;
+pub mod url_policy;
@@ -0,0 +1,293 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::bail;
+
+use api::{FailureLog, PagePersistent, PageWorker, PageWorkerBuilder, PageWorkerLayer};
+
+use crate::archive::{ArchiveClient, ArchivePageWorker};
+use crate::fallback_page_worker::{FallbackPageWorker, StaleCachePageWorker};
+use crate::layers::{
+    ConcurrencyLimitLayer, FailureLogLayer, MetricsLayer, PageMetrics, RetryLayer, TimeoutLayer,
+    UrlPolicyLayer,
+};
+use crate::url_policy::UrlPolicy;
+
+/// Layer of the page pipeline as it's named in the layer list of the binaries.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PageLayer {
+    Retry,
+    Timeout,
+    Cache,
+    Metrics,
+    FailureLog,
+    ConcurrencyLimit,
+    UrlPolicy,
+}
+
+impl PageLayer {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PageLayer::Retry => "retry",
+            PageLayer::Timeout => "timeout",
+            PageLayer::Cache => "cache",
+            PageLayer::Metrics => "metrics",
+            PageLayer::FailureLog => "failure-log",
+            PageLayer::ConcurrencyLimit => "concurrency-limit",
+            PageLayer::UrlPolicy => "url-policy",
+        }
+    }
+
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        match value.trim() {
+            "retry" => Ok(PageLayer::Retry),
+            "timeout" => Ok(PageLayer::Timeout),
+            "cache" => Ok(PageLayer::Cache),
+            "metrics" => Ok(PageLayer::Metrics),
+            "failure-log" => Ok(PageLayer::FailureLog),
+            "concurrency-limit" => Ok(PageLayer::ConcurrencyLimit),
+            "url-policy" => Ok(PageLayer::UrlPolicy),
+            _ => bail!(
+                "Unknown page layer {}, expected retry, timeout, cache, metrics, failure-log, \
+                 concurrency-limit or url-policy",
+                value
+            ),
+        }
+    }
+}
+
+/// Builds the page worker from the layer list, the first layer sees the requests first.
+///
+/// The layers wrap loading of the live page. When it fails, the archive and the older
+/// cached copy are tried, snapshots of the archive are loaded through the same layers.
+/// Listed layers without their settings are skipped, e.g. the timeout when it's not set.
+pub struct PagePipeline {
+    layers: Vec<PageLayer>,
+    retry: Option<RetryLayer>,
+    timeout: Option<TimeoutLayer>,
+    cache: Option<Box<dyn PageWorkerLayer>>,
+    metrics: Option<MetricsLayer>,
+    failure_log: Option<FailureLogLayer>,
+    concurrency_limit: Option<ConcurrencyLimitLayer>,
+    url_policy: Option<UrlPolicyLayer>,
+    archive: Option<Box<dyn ArchiveClient>>,
+    stale_cache: Option<Arc<dyn PagePersistent>>,
+}
+
+impl PagePipeline {
+    pub fn new(layers: Vec<PageLayer>) -> Self {
+        PagePipeline {
+            layers,
+            retry: None,
+            timeout: None,
+            cache: None,
+            metrics: None,
+            failure_log: None,
+            concurrency_limit: None,
+            url_policy: None,
+            archive: None,
+            stale_cache: None,
+        }
+    }
+
+    /// `attempts` counts the first attempt as well
+    pub fn with_retry(mut self, attempts: u32, base_delay: Duration) -> Self {
+        self.retry = Some(RetryLayer::new(attempts, base_delay));
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(TimeoutLayer::new(timeout));
+        self
+    }
+
+    pub fn with_cache(mut self, cache: impl PageWorkerLayer + 'static) -> Self {
+        self.cache = Some(Box::new(cache));
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<PageMetrics>) -> Self {
+        self.metrics = Some(MetricsLayer::new(metrics));
+        self
+    }
+
+    pub fn with_failure_log(mut self, failures: Arc<dyn FailureLog>) -> Self {
+        self.failure_log = Some(FailureLogLayer::new(failures));
+        self
+    }
+
+    pub fn with_concurrency_limit(mut self, max_pages: usize) -> Self {
+        self.concurrency_limit = Some(ConcurrencyLimitLayer::new(max_pages));
+        self
+    }
+
+    pub fn with_url_policy(mut self, url_policy: UrlPolicy) -> Self {
+        self.url_policy = Some(UrlPolicyLayer::new(url_policy));
+        self
+    }
+
+    /// Archive unavailable pages are looked up in
+    pub fn with_archive(mut self, archive: Box<dyn ArchiveClient>) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    /// Cache the older copy of unavailable pages is taken from
+    pub fn with_stale_cache(mut self, storage: Arc<dyn PagePersistent>) -> Self {
+        self.stale_cache = Some(storage);
+        self
+    }
+
+    pub fn service(mut self, worker: impl PageWorker + 'static) -> Box<dyn PageWorker> {
+        let mut builder = PageWorkerBuilder::new();
+        for layer in self.layers.clone() {
+            builder = match layer {
+                PageLayer::Retry => builder.option_layer(self.retry.take()),
+                PageLayer::Timeout => builder.option_layer(self.timeout.take()),
+                PageLayer::Cache => builder.option_layer(self.cache.take()),
+                PageLayer::Metrics => builder.option_layer(self.metrics.take()),
+                PageLayer::FailureLog => builder.option_layer(self.failure_log.take()),
+                PageLayer::ConcurrencyLimit => builder.option_layer(self.concurrency_limit.take()),
+                PageLayer::UrlPolicy => builder.option_layer(self.url_policy.take()),
+            };
+        }
+        let live: Arc<dyn PageWorker> = Arc::from(builder.service(worker));
+        let mut fallback_worker = FallbackPageWorker::new(live.clone());
+        if let Some(archive) = self.archive {
+            fallback_worker =
+                fallback_worker.with_fallback(Box::new(ArchivePageWorker::new(archive, live)));
+        }
+        if let Some(storage) = self.stale_cache {
+            fallback_worker =
+                fallback_worker.with_fallback(Box::new(StaleCachePageWorker::new(storage)));
+        }
+        Box::new(fallback_worker)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+
+    use api::{
+        LoadedPage, PageData, PageError, PageMetadata, PageResult, PageWorker, PageWorkerLayer,
+    };
+
+    use crate::layers::PageMetrics;
+    use crate::pipeline::{PageLayer, PagePipeline};
+    use crate::url_policy::UrlPolicy;
+
+    struct LivePageWorker;
+
+    #[async_trait]
+    impl PageWorker for LivePageWorker {
+        async fn submit_page_generation(
+            &self,
+            _page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            Ok(LoadedPage::from(PageResult::FilePath(
+                "page.html".to_string(),
+            )))
+        }
+    }
+
+    /// Answers every page from the cache
+    struct FullCacheLayer;
+
+    impl PageWorkerLayer for FullCacheLayer {
+        fn layer(&self, _inner: Box<dyn PageWorker>) -> Box<dyn PageWorker> {
+            Box::new(FullCachePageWorker)
+        }
+    }
+
+    struct FullCachePageWorker;
+
+    #[async_trait]
+    impl PageWorker for FullCachePageWorker {
+        async fn submit_page_generation(
+            &self,
+            _page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            Ok(LoadedPage {
+                result: PageResult::TelegramId("cached".to_string()),
+                metadata: PageMetadata {
+                    from_cache: true,
+                    ..PageMetadata::default()
+                },
+            })
+        }
+    }
+
+    async fn load_through(layers: Vec<PageLayer>) -> anyhow::Result<Arc<PageMetrics>> {
+        let metrics = Arc::new(PageMetrics::default());
+        let worker = PagePipeline::new(layers)
+            .with_cache(FullCacheLayer)
+            .with_metrics(metrics.clone())
+            .service(LivePageWorker);
+        let page = worker
+            .submit_page_generation(PageData::from_url("https://example.com/".to_string()))
+            .await?;
+        assert_eq!(page.result, PageResult::TelegramId("cached".to_string()));
+        Ok(metrics)
+    }
+
+    #[tokio::test]
+    async fn test_layers_follow_the_list() -> anyhow::Result<()> {
+        let metrics = load_through(vec![PageLayer::Metrics, PageLayer::Cache]).await?;
+        assert_eq!(metrics.loaded(), 1);
+
+        // pages from the cache don't reach the metrics behind it
+        let metrics = load_through(vec![PageLayer::Cache, PageLayer::Metrics]).await?;
+        assert_eq!(metrics.loaded(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_skips_unlisted_and_unset_layers() -> anyhow::Result<()> {
+        let metrics = Arc::new(PageMetrics::default());
+        let worker = PagePipeline::new(vec![PageLayer::Metrics, PageLayer::Timeout])
+            .with_url_policy(UrlPolicy::new(vec![], vec!["example.com".to_string()]))
+            .with_retry(3, Duration::from_millis(1))
+            .with_metrics(metrics.clone())
+            .service(LivePageWorker);
+
+        worker
+            .submit_page_generation(PageData::from_url("https://example.com/".to_string()))
+            .await?;
+        assert_eq!(metrics.loaded(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_url_policy_layer() -> anyhow::Result<()> {
+        let worker = PagePipeline::new(vec![PageLayer::UrlPolicy])
+            .with_url_policy(UrlPolicy::new(vec![], vec!["example.com".to_string()]))
+            .service(LivePageWorker);
+
+        let result = worker
+            .submit_page_generation(PageData::from_url("https://example.com/".to_string()))
+            .await;
+        assert!(matches!(result, Err(PageError::BlockedByPolicy(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parses_layers() -> anyhow::Result<()> {
+        for layer in [
+            PageLayer::Retry,
+            PageLayer::Timeout,
+            PageLayer::Cache,
+            PageLayer::Metrics,
+            PageLayer::FailureLog,
+            PageLayer::ConcurrencyLimit,
+            PageLayer::UrlPolicy,
+        ] {
+            assert_eq!(PageLayer::parse(layer.as_str())?, layer);
+        }
+        assert!(PageLayer::parse("cors").is_err());
+        Ok(())
+    }
+}
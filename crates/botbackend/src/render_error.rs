@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use api::{PageError, RenderDiagnostics};
+
+/// Exit codes of a browser killed by SIGABRT, SIGKILL (e.g. out of memory) and SIGSEGV
+const CRASH_EXIT_CODES: [i32; 3] = [134, 137, 139];
+
+/// Errors of singlefile and chromium that won't go away when the page is loaded again
+const PERMANENT_MARKERS: [&str; 9] = [
+    "err_name_not_resolved",
+    "err_invalid_url",
+    "invalid url",
+    "err_cert_",
+    "err_ssl_",
+    "err_too_many_redirects",
+    "err_blocked_by",
+    "err_unsafe_port",
+    "err_file_not_found",
+];
+
+/// Errors of singlefile and chromium that are likely gone on the next attempt
+const TRANSIENT_MARKERS: [&str; 13] = [
+    "err_connection_reset",
+    "err_connection_closed",
+    "err_connection_refused",
+    "err_timed_out",
+    "err_network_changed",
+    "err_internet_disconnected",
+    "err_proxy_connection_failed",
+    "econnreset",
+    "socket hang up",
+    "timeout",
+    "target closed",
+    "browser has disconnected",
+    "crashed",
+];
+
+/// How much of the singlefile output is kept in the error
+const MAX_STDERR_LENGTH: usize = 500;
+
+/// Failure of singlefile classified by its exit code and output.
+pub fn singlefile_error(
+    command: String,
+    exit_code: Option<i32>,
+    stderr: &str,
+    duration: Duration,
+) -> PageError {
+    let stderr = stderr.trim();
+    let transient = is_transient_exit(exit_code, stderr);
+    let start = stderr.len().saturating_sub(MAX_STDERR_LENGTH);
+    let start = (start..stderr.len())
+        .find(|index| stderr.is_char_boundary(*index))
+        .unwrap_or(stderr.len());
+    PageError::RenderFailed {
+        diagnostics: RenderDiagnostics {
+            command,
+            exit_code,
+            stderr: stderr[start..].to_string(),
+            duration,
+        },
+        transient,
+    }
+}
+
+fn is_transient_exit(exit_code: Option<i32>, stderr: &str) -> bool {
+    // killed by a signal
+    let Some(exit_code) = exit_code else {
+        return true;
+    };
+    if CRASH_EXIT_CODES.contains(&exit_code) {
+        return true;
+    }
+    let stderr = stderr.to_lowercase();
+    if PERMANENT_MARKERS
+        .iter()
+        .any(|marker| stderr.contains(marker))
+    {
+        return false;
+    }
+    TRANSIENT_MARKERS
+        .iter()
+        .any(|marker| stderr.contains(marker))
+        || stderr.is_empty()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use api::{PageError, RenderDiagnostics};
+
+    use crate::render_error::singlefile_error;
+
+    fn failure(exit_code: Option<i32>, stderr: &str) -> PageError {
+        singlefile_error(
+            "single-file https://example.com page.html".to_string(),
+            exit_code,
+            stderr,
+            Duration::from_millis(1500),
+        )
+    }
+
+    #[test]
+    fn test_classifies_singlefile_failures() {
+        let transient = [
+            (None, ""),
+            (Some(139), "Segmentation fault"),
+            (Some(1), "net::ERR_CONNECTION_RESET at https://example.com"),
+            (
+                Some(1),
+                "TimeoutError: Navigation timeout of 60000 ms exceeded",
+            ),
+            (Some(1), "Protocol error: Target closed."),
+            (Some(1), ""),
+        ];
+        for (exit_code, stderr) in transient {
+            assert!(failure(exit_code, stderr).is_transient(), "{}", stderr);
+        }
+        let permanent = [
+            (
+                Some(1),
+                "net::ERR_NAME_NOT_RESOLVED at https://nowhere.example",
+            ),
+            (Some(1), "net::ERR_CERT_AUTHORITY_INVALID"),
+            (Some(1), "Error: Invalid URL"),
+            (Some(1), "Unexpected token in the page"),
+        ];
+        for (exit_code, stderr) in permanent {
+            assert!(!failure(exit_code, stderr).is_transient(), "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn test_keeps_end_of_output() {
+        let stderr = format!("{}net::ERR_TIMED_OUT", "ы".repeat(1000));
+        let Some(diagnostics) = failure(Some(1), &stderr).diagnostics().cloned() else {
+            panic!("Diagnostics are expected");
+        };
+        assert!(diagnostics.stderr.len() <= 500);
+        assert!(diagnostics.stderr.ends_with("net::ERR_TIMED_OUT"));
+    }
+
+    #[test]
+    fn test_keeps_diagnostics() {
+        let error = failure(None, " Target closed\n");
+
+        assert_eq!(
+            error.diagnostics(),
+            Some(&RenderDiagnostics {
+                command: "single-file https://example.com page.html".to_string(),
+                exit_code: None,
+                stderr: "Target closed".to_string(),
+                duration: Duration::from_millis(1500),
+            })
+        );
+        assert_eq!(
+            error.to_string(),
+            "singlefile was killed by a signal after 1.5s: Target closed"
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use reqwest::redirect::Policy;
+use reqwest::{Client, Proxy, Url};
+use thiserror::Error;
+
+use api::PageError;
+
+use crate::tor::is_onion;
+
+const MAX_REDIRECTS: usize = 10;
+const REDIRECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error, PartialEq)]
+pub enum UrlRejected {
+    #[error("The link is not a valid url")]
+    InvalidUrl,
+    #[error("Only http and https links are supported, got {0}")]
+    UnsupportedScheme(String),
+    #[error("Pages from {0} are not allowed")]
+    DeniedHost(String),
+    #[error("{0} points to a private network")]
+    PrivateAddress(String),
+    #[error("Can't resolve {0}")]
+    UnknownHost(String),
+    #[error("Too many redirects")]
+    TooManyRedirects,
+    #[error("Onion links are not supported")]
+    OnionDisabled,
+}
+
+impl From<UrlRejected> for PageError {
+    fn from(err: UrlRejected) -> Self {
+        match err {
+            UrlRejected::InvalidUrl
+            | UrlRejected::UnsupportedScheme(_)
+            | UrlRejected::UnknownHost(_) => PageError::InvalidUrl(err.to_string()),
+            // the page may still be in the archives
+            UrlRejected::TooManyRedirects => PageError::FetchFailed {
+                message: err.to_string(),
+                transient: false,
+            },
+            UrlRejected::DeniedHost(_)
+            | UrlRejected::PrivateAddress(_)
+            | UrlRejected::OnionDisabled => PageError::BlockedByPolicy(err.to_string()),
+        }
+    }
+}
+
+/// The url the redirects end with, the answer of the site itself is up to the renderer:
+/// sites may refuse clients that are not browsers
+#[derive(Debug, PartialEq)]
+pub struct ResolvedUrl {
+    pub url: Url,
+}
+
+/// Decides which urls can be rendered, so users can't reach the internal network
+/// of the renderer through the bot.
+///
+/// Only http and https urls pointing to public addresses are allowed. Denied hosts are
+/// always rejected. When allowed hosts are set, only they can be rendered and they may
+/// point to private addresses. Hosts match their subdomains as well.
+/// Onion links are accepted only when pages can be loaded through Tor.
+#[derive(Clone, Default)]
+pub struct UrlPolicy {
+    allowed_hosts: Vec<String>,
+    denied_hosts: Vec<String>,
+    allow_onion: bool,
+}
+
+impl UrlPolicy {
+    pub fn new(allowed_hosts: Vec<String>, denied_hosts: Vec<String>) -> Self {
+        UrlPolicy {
+            allowed_hosts: normalize_hosts(allowed_hosts),
+            denied_hosts: normalize_hosts(denied_hosts),
+            allow_onion: false,
+        }
+    }
+
+    pub fn with_onion(mut self, allow_onion: bool) -> Self {
+        self.allow_onion = allow_onion;
+        self
+    }
+
+    /// Checks the url and the addresses its host resolves to.
+    pub async fn check(&self, url: &str) -> Result<Url, UrlRejected> {
+        let url = Url::parse(url).map_err(|_| UrlRejected::InvalidUrl)?;
+        self.check_url(&url).await?;
+        Ok(url)
+    }
+
+    /// Follows the redirects of the url through the proxy the page is loaded with
+    /// and checks every hop.
+    pub async fn resolve_redirects(
+        &self,
+        url: &str,
+        proxy: Option<&str>,
+    ) -> Result<ResolvedUrl, UrlRejected> {
+        let mut url = self.check(url).await?;
+        let mut client = Client::builder()
+            .redirect(Policy::none())
+            .timeout(REDIRECT_TIMEOUT);
+        if let Some(proxy) = proxy {
+            // names are resolved by the proxy, otherwise onion links can't be reached
+            let proxy = match proxy.strip_prefix("socks5://") {
+                Some(address) => format!("socks5h://{}", address),
+                None => proxy.to_string(),
+            };
+            let proxy = Proxy::all(proxy).map_err(|_| UrlRejected::InvalidUrl)?;
+            client = client.proxy(proxy);
+        }
+        let client = client.build().map_err(|_| UrlRejected::InvalidUrl)?;
+        for _ in 0..MAX_REDIRECTS {
+            // the renderer reports unreachable pages itself
+            let Ok(response) = client.get(url.clone()).send().await else {
+                return Ok(ResolvedUrl { url });
+            };
+            if !response.status().is_redirection() {
+                return Ok(ResolvedUrl { url });
+            }
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|location| location.to_str().ok());
+            let Some(location) = location else {
+                return Ok(ResolvedUrl { url });
+            };
+            url = url.join(location).map_err(|_| UrlRejected::InvalidUrl)?;
+            self.check_url(&url).await?;
+        }
+        Err(UrlRejected::TooManyRedirects)
+    }
+
+    /// Address of the host the connection can be made to, the one that was checked.
+    /// Connecting to it instead of resolving the host again keeps the host from pointing
+    /// to the internal network after the check.
+    pub async fn check_address(&self, host: &str, port: u16) -> Result<SocketAddr, UrlRejected> {
+        let url = Url::parse(&format!("http://{}:{}/", host, port))
+            .map_err(|_| UrlRejected::InvalidUrl)?;
+        let addresses = match self.checked_addresses(&url).await? {
+            Some(addresses) => addresses,
+            // allowed hosts may point anywhere, onion links don't resolve and are rejected here
+            None => resolve(&url, port).await?,
+        };
+        let host = url.host_str().unwrap_or(host).to_string();
+        let address = addresses
+            .into_iter()
+            .next()
+            .ok_or(UrlRejected::UnknownHost(host))?;
+        Ok(SocketAddr::new(address, port))
+    }
+
+    async fn check_url(&self, url: &Url) -> Result<(), UrlRejected> {
+        self.checked_addresses(url).await.map(|_| ())
+    }
+
+    /// Checks the url and returns the checked addresses of its host. Onion links and allowed
+    /// hosts are accepted by the name, they are not resolved.
+    async fn checked_addresses(&self, url: &Url) -> Result<Option<Vec<IpAddr>>, UrlRejected> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(UrlRejected::UnsupportedScheme(url.scheme().to_string()));
+        }
+        let host = url.host_str().ok_or(UrlRejected::InvalidUrl)?;
+        let host = host.trim_end_matches('.').to_lowercase();
+        if matches_any(&host, &self.denied_hosts) {
+            return Err(UrlRejected::DeniedHost(host));
+        }
+        if is_onion(&host) {
+            // onion names don't resolve outside of Tor and can't point to our network
+            return if self.allow_onion {
+                Ok(None)
+            } else {
+                Err(UrlRejected::OnionDisabled)
+            };
+        }
+        if !self.allowed_hosts.is_empty() {
+            return if matches_any(&host, &self.allowed_hosts) {
+                Ok(None)
+            } else {
+                Err(UrlRejected::DeniedHost(host))
+            };
+        }
+
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addresses = resolve(url, port).await?;
+        if addresses.is_empty() {
+            return Err(UrlRejected::UnknownHost(host));
+        }
+        if addresses.iter().any(|address| !is_public(address)) {
+            return Err(UrlRejected::PrivateAddress(host));
+        }
+        Ok(Some(addresses))
+    }
+}
+
+async fn resolve(url: &Url, port: u16) -> Result<Vec<IpAddr>, UrlRejected> {
+    let host = match url.host() {
+        Some(url::Host::Ipv4(address)) => return Ok(vec![IpAddr::V4(address)]),
+        Some(url::Host::Ipv6(address)) => return Ok(vec![IpAddr::V6(address)]),
+        Some(url::Host::Domain(domain)) => domain,
+        None => return Err(UrlRejected::InvalidUrl),
+    };
+    let addresses = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| UrlRejected::UnknownHost(host.to_string()))?;
+    Ok(addresses.map(|address| address.ip()).collect())
+}
+
+fn normalize_hosts(hosts: Vec<String>) -> Vec<String> {
+    hosts
+        .into_iter()
+        .map(|host| host.trim().trim_end_matches('.').to_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect()
+}
+
+fn matches_any(host: &str, hosts: &[String]) -> bool {
+    hosts.iter().any(|pattern| matches_host(host, pattern))
+}
+
+/// True for the domain itself and its subdomains
+pub fn matches_host(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+fn is_public(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => is_public_v4(address),
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(address) => is_public_v4(&address),
+            None => is_public_v6(address),
+        },
+    }
+}
+
+fn is_public_v4(address: &Ipv4Addr) -> bool {
+    let [first, second, ..] = address.octets();
+    !(address.is_private()
+        || address.is_loopback()
+        || address.is_link_local()
+        || address.is_unspecified()
+        || address.is_broadcast()
+        || address.is_multicast()
+        || address.is_documentation()
+        // "this network" and the shared address space of carrier NAT
+        || first == 0
+        || (first == 100 && (64..128).contains(&second)))
+}
+
+fn is_public_v6(address: &Ipv6Addr) -> bool {
+    let first_segment = address.segments()[0];
+    !(address.is_loopback()
+        || address.is_unspecified()
+        || address.is_multicast()
+        // unique local addresses fc00::/7
+        || (first_segment & 0xfe00) == 0xfc00
+        // link-local addresses fe80::/10
+        || (first_segment & 0xffc0) == 0xfe80)
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use api::PageError;
+
+    use crate::url_policy::{UrlPolicy, UrlRejected};
+
+    #[tokio::test]
+    async fn test_rejects_unsupported_schemes() {
+        let policy = UrlPolicy::default();
+
+        assert_eq!(
+            policy.check("file:///etc/passwd").await,
+            Err(UrlRejected::UnsupportedScheme("file".to_string()))
+        );
+        assert_eq!(
+            policy.check("not a url").await,
+            Err(UrlRejected::InvalidUrl)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejects_private_addresses() {
+        let policy = UrlPolicy::default();
+
+        for url in [
+            "http://localhost:5432",
+            "http://127.0.0.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.0.0.1/",
+            "http://192.168.1.1/",
+            "http://100.64.0.1/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[fd00::1]/",
+            "http://[::ffff:127.0.0.1]/",
+        ] {
+            assert!(
+                matches!(policy.check(url).await, Err(UrlRejected::PrivateAddress(_))),
+                "{} must be rejected",
+                url
+            );
+        }
+        assert!(policy.check("http://93.184.215.14/page").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_allow_and_deny_lists() {
+        let policy = UrlPolicy::new(
+            vec!["intranet.local".to_string(), "127.0.0.1".to_string()],
+            vec!["secret.intranet.local".to_string()],
+        );
+
+        assert!(policy.check("http://127.0.0.1/").await.is_ok());
+        assert!(policy.check("https://docs.intranet.local/").await.is_ok());
+        assert_eq!(
+            policy.check("https://secret.intranet.local/").await,
+            Err(UrlRejected::DeniedHost("secret.intranet.local".to_string()))
+        );
+        assert_eq!(
+            policy.check("https://example.com/").await,
+            Err(UrlRejected::DeniedHost("example.com".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_onion_links() {
+        let url = "http://example2x3yz.onion/page";
+
+        assert_eq!(
+            UrlPolicy::default().check(url).await,
+            Err(UrlRejected::OnionDisabled)
+        );
+        assert!(UrlPolicy::default()
+            .with_onion(true)
+            .check(url)
+            .await
+            .is_ok());
+        let denied = UrlPolicy::new(vec![], vec!["example2x3yz.onion".to_string()]);
+        assert!(matches!(
+            denied.with_onion(true).check(url).await,
+            Err(UrlRejected::DeniedHost(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_deny_list_matches_subdomains() {
+        let policy = UrlPolicy::new(vec![], vec!["Example.com".to_string()]);
+
+        assert!(matches!(
+            policy.check("https://www.example.com/").await,
+            Err(UrlRejected::DeniedHost(_))
+        ));
+        assert!(matches!(
+            policy.check("https://example.com./").await,
+            Err(UrlRejected::DeniedHost(_))
+        ));
+        assert!(policy.check("https://93.184.215.14/").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_checks_redirects() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).await;
+                let response = "HTTP/1.1 302 Found\r\nLocation: http://localhost/admin\r\n\
+                                Content-Length: 0\r\nConnection: close\r\n\r\n";
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        let policy = UrlPolicy::new(vec!["127.0.0.1".to_string()], vec![]);
+
+        assert_eq!(
+            policy
+                .resolve_redirects(&format!("http://127.0.0.1:{}/", port), None)
+                .await,
+            Err(UrlRejected::DeniedHost("localhost".to_string()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejections_are_page_errors() {
+        assert_eq!(
+            PageError::from(UrlRejected::UnsupportedScheme("file".to_string())),
+            PageError::InvalidUrl("Only http and https links are supported, got file".to_string())
+        );
+        let blocked = PageError::from(UrlRejected::PrivateAddress("10.0.0.1".to_string()));
+        assert_eq!(
+            blocked,
+            PageError::BlockedByPolicy("10.0.0.1 points to a private network".to_string())
+        );
+        assert!(blocked.is_rejected());
+        assert!(!blocked.is_transient());
+        assert!(!PageError::from(UrlRejected::TooManyRedirects).is_rejected());
+    }
+}
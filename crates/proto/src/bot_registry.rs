@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use anyhow::{bail, Context};
+use teloxide::Bot;
+use url::Url;
+
+use api::{bot_id_from_token, ApiKeyPersistent};
+
+/// Bot clients keyed by the telegram bot id, so several bots can share one backend.
+/// Tokens of the tenant bots are taken from their API keys, the client of a bot is kept
+/// while its key stays active and is made again when the key is replaced.
+pub struct BotRegistry {
+    default_bot: Bot,
+    default_bot_id: Option<String>,
+    bots: RwLock<HashMap<String, Bot>>,
+    api_keys: Option<Arc<dyn ApiKeyPersistent>>,
+}
+
+impl BotRegistry {
+    pub fn new(default_bot: Bot) -> Self {
+        let default_bot_id = bot_id_from_token(default_bot.token());
+        BotRegistry {
+            default_bot,
+            default_bot_id,
+            bots: RwLock::new(HashMap::new()),
+            api_keys: None,
+        }
+    }
+
+    /// Looks up tokens of unknown bots among the API keys.
+    pub fn with_api_keys(mut self, api_keys: Arc<dyn ApiKeyPersistent>) -> Self {
+        self.api_keys = Some(api_keys);
+        self
+    }
+
+    /// Client of the bot, `None` is the default bot.
+    pub async fn resolve(&self, bot_id: Option<&str>) -> anyhow::Result<Bot> {
+        let Some(bot_id) = bot_id else {
+            return Ok(self.default_bot.clone());
+        };
+        if self.default_bot_id.as_deref() == Some(bot_id) {
+            return Ok(self.default_bot.clone());
+        }
+        let Some(api_keys) = &self.api_keys else {
+            bail!("Unknown bot {}", bot_id)
+        };
+        // keys are revoked and created while the backend runs
+        let Some(token) = api_keys.get_bot_token(bot_id).await? else {
+            self.bots.write().unwrap().remove(bot_id);
+            bail!("Unknown bot {}", bot_id)
+        };
+        if let Some(bot) = self.bots.read().unwrap().get(bot_id) {
+            if bot.token() == token {
+                return Ok(bot.clone());
+            }
+        }
+        // tenant bots talk to the same Bot API server as the default one
+        let bot = Bot::with_client(token, self.default_bot.client().clone())
+            .set_api_url(self.default_bot.api_url());
+        self.bots
+            .write()
+            .unwrap()
+            .insert(bot_id.to_string(), bot.clone());
+        Ok(bot)
+    }
+}
+
+/// Bot of the `TELOXIDE_TOKEN` env variable, talking to a self-hosted Bot API server
+/// when its url is set.
+pub fn bot_from_env(api_url: Option<&str>) -> anyhow::Result<Bot> {
+    let bot = Bot::from_env();
+    let Some(api_url) = api_url else {
+        return Ok(bot);
+    };
+    let api_url = Url::parse(api_url.trim_end_matches('/'))
+        .with_context(|| format!("Invalid Bot API url {}", api_url))?;
+    Ok(bot.set_api_url(api_url))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use teloxide::Bot;
+    use time::Date;
+
+    use api::{ApiKey, ApiKeyPersistent};
+
+    use crate::bot_registry::BotRegistry;
+
+    /// Keys of a single tenant bot, only its token is read by the registry
+    #[derive(Default)]
+    struct TokenStore {
+        token: Mutex<Option<String>>,
+    }
+
+    #[async_trait]
+    impl ApiKeyPersistent for TokenStore {
+        async fn save_api_key(&self, _api_key: &ApiKey) -> anyhow::Result<()> {
+            unimplemented!()
+        }
+
+        async fn get_api_key(&self, _id: &str) -> anyhow::Result<Option<ApiKey>> {
+            unimplemented!()
+        }
+
+        async fn list_api_keys(&self) -> anyhow::Result<Vec<ApiKey>> {
+            unimplemented!()
+        }
+
+        async fn revoke_api_key(&self, _id: &str) -> anyhow::Result<bool> {
+            unimplemented!()
+        }
+
+        async fn try_use_quota(
+            &self,
+            _id: &str,
+            _day: Date,
+            _daily_quota: u32,
+        ) -> anyhow::Result<bool> {
+            unimplemented!()
+        }
+
+        async fn get_usage(&self, _id: &str, _day: Date) -> anyhow::Result<u64> {
+            unimplemented!()
+        }
+
+        async fn get_bot_token(&self, _bot_id: &str) -> anyhow::Result<Option<String>> {
+            Ok(self.token.lock().unwrap().clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_default_bot() -> anyhow::Result<()> {
+        let registry = BotRegistry::new(Bot::new("123:token"));
+
+        assert_eq!(registry.resolve(None).await?.token(), "123:token");
+        assert_eq!(registry.resolve(Some("123")).await?.token(), "123:token");
+        assert!(registry.resolve(Some("456")).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_follows_changed_keys() -> anyhow::Result<()> {
+        let keys = Arc::new(TokenStore::default());
+        *keys.token.lock().unwrap() = Some("456:first".to_string());
+        let registry = BotRegistry::new(Bot::new("123:token")).with_api_keys(keys.clone());
+
+        assert_eq!(registry.resolve(Some("456")).await?.token(), "456:first");
+
+        *keys.token.lock().unwrap() = Some("456:second".to_string());
+        assert_eq!(registry.resolve(Some("456")).await?.token(), "456:second");
+
+        // pages of a revoked key are not sent with its bot anymore
+        *keys.token.lock().unwrap() = None;
+        assert!(registry.resolve(Some("456")).await.is_err());
+        Ok(())
+    }
+}
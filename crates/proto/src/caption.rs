@@ -0,0 +1,150 @@
+use time::PrimitiveDateTime;
+
+use api::i18n::{Language, Text};
+use api::{PageMetadata, PageSource};
+
+/// Telegram doesn't accept captions over 1024 characters, long titles are cut
+const MAX_TITLE_LENGTH: usize = 300;
+/// Longer urls are left out, a cut url is of no use
+const MAX_URL_LENGTH: usize = 500;
+
+/// Caption of the sent document with the title and the url of the page, when it
+/// was loaded and whether it's a fresh copy or the one from the cache. Pages that are not
+/// loaded from the live site are labelled with where they come from instead.
+/// The canonical url of the page is preferred to the requested one.
+pub fn page_caption(page_url: &str, metadata: &PageMetadata, language: Language) -> Option<String> {
+    let mut lines = Vec::new();
+    if let Some(title) = &metadata.title {
+        lines.push(truncate(title, MAX_TITLE_LENGTH));
+    }
+    let url = metadata
+        .canonical_url
+        .iter()
+        .map(String::as_str)
+        .chain([page_url])
+        .find(|url| url.chars().count() <= MAX_URL_LENGTH);
+    if let Some(url) = url {
+        lines.push(url.to_string());
+    }
+    let label = match &metadata.source {
+        PageSource::Live => metadata.fetched_at.map(|fetched_at| {
+            let copy = if metadata.from_cache {
+                Text::CachedCopy
+            } else {
+                Text::FreshCopy
+            };
+            language.format(copy, &[&format_time(&fetched_at)])
+        }),
+        PageSource::Archive {
+            archive,
+            snapshot_at,
+        } => Some(language.format(Text::ArchiveSnapshot, &[archive, &format_time(snapshot_at)])),
+        PageSource::Cache { cached_at } => {
+            Some(language.format(Text::CacheSnapshot, &[&format_time(cached_at)]))
+        }
+    };
+    lines.extend(label);
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+fn truncate(text: &str, max_length: usize) -> String {
+    if text.chars().count() <= max_length {
+        return text.to_string();
+    }
+    let mut truncated = text.chars().take(max_length - 1).collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+fn format_time(time: &PrimitiveDateTime) -> String {
+    format!(
+        "{} {:02}:{:02} UTC",
+        time.date(),
+        time.hour(),
+        time.minute()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use time::macros::datetime;
+
+    use api::i18n::Language;
+    use api::{PageMetadata, PageSource};
+
+    use crate::caption::page_caption;
+
+    const URL: &str = "https://example.com/page?id=1";
+
+    #[test]
+    fn test_labels_fallback_pages() {
+        assert_eq!(
+            page_caption(URL, &PageMetadata::default(), Language::En).as_deref(),
+            Some(URL)
+        );
+
+        let archived = PageMetadata {
+            source: PageSource::Archive {
+                archive: "Wayback Machine".to_string(),
+                snapshot_at: datetime!(2024-01-02 03:04:05),
+            },
+            ..PageMetadata::default()
+        };
+        assert_eq!(
+            page_caption(URL, &archived, Language::En).as_deref(),
+            Some(
+                "https://example.com/page?id=1\n\
+                 The page can't be loaded now, this is the snapshot from Wayback Machine \
+                 taken on 2024-01-02 03:04 UTC"
+            )
+        );
+
+        let cached = PageMetadata {
+            source: PageSource::Cache {
+                cached_at: datetime!(2024-01-02 10:10:10),
+            },
+            ..PageMetadata::default()
+        };
+        assert_eq!(
+            page_caption(URL, &cached, Language::En).as_deref(),
+            Some(
+                "https://example.com/page?id=1\n\
+                 The page can't be loaded now, this is the copy saved on 2024-01-02 10:10 UTC"
+            )
+        );
+    }
+
+    #[test]
+    fn test_describes_live_pages() {
+        let fresh = PageMetadata {
+            title: Some("Example page".to_string()),
+            canonical_url: Some("https://example.com/page".to_string()),
+            fetched_at: Some(datetime!(2024-01-02 10:10:10)),
+            ..PageMetadata::default()
+        };
+        assert_eq!(
+            page_caption(URL, &fresh, Language::En).as_deref(),
+            Some(
+                "Example page\nhttps://example.com/page\nFresh copy, saved on 2024-01-02 10:10 UTC"
+            )
+        );
+
+        let cached = PageMetadata {
+            from_cache: true,
+            canonical_url: None,
+            ..fresh
+        };
+        assert_eq!(
+            page_caption(URL, &cached, Language::En).as_deref(),
+            Some("Example page\nhttps://example.com/page?id=1\nFrom cache, saved on 2024-01-02 10:10 UTC")
+        );
+
+        let long_title = PageMetadata {
+            title: Some("a".repeat(400)),
+            ..PageMetadata::default()
+        };
+        let caption = page_caption(&"a".repeat(600), &long_title, Language::En).unwrap();
+        assert_eq!(caption.chars().count(), 300);
+        assert!(caption.ends_with('…'));
+    }
+}
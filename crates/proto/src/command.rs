@@ -1,11 +1,180 @@
+use ::teloxide::payloads::{
+    SetMyCommandsSetters, SetMyDescriptionSetters, SetMyShortDescriptionSetters,
+};
+use ::teloxide::prelude::Requester;
+use ::teloxide::types::{BotCommand, BotCommandScope};
 use ::teloxide::utils::command::BotCommands;
+use ::teloxide::Bot;
+
+use api::i18n::{Language, Text};
 
 #[derive(BotCommands, Clone)]
-#[command(rename_rule = "lowercase", description = "These commands are supported:")]
+#[command(
+    rename_rule = "lowercase",
+    description = "These commands are supported:"
+)]
 pub enum Command {
     #[command(description = "Show all commands")]
     Help,
 
     #[command(description = "Get a web page by the URL")]
-    GetPage { url: String }
-}
\ No newline at end of file
+    GetPage { url: String },
+
+    #[command(description = "Cancel pages requested in this chat")]
+    Cancel,
+
+    #[command(description = "Change the settings of this chat")]
+    Settings,
+}
+
+/// Commands only admins can run in groups, the group members don't see them in the menu
+const ADMIN_COMMANDS: [&str; 1] = ["settings"];
+
+/// Chats the command menu is registered for, Telegram shows admins of a group their own menu
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CommandScope {
+    Private,
+    Group,
+    Admins,
+}
+
+impl CommandScope {
+    pub const ALL: [CommandScope; 3] = [
+        CommandScope::Private,
+        CommandScope::Group,
+        CommandScope::Admins,
+    ];
+
+    fn bot_command_scope(self) -> BotCommandScope {
+        match self {
+            CommandScope::Private => BotCommandScope::AllPrivateChats,
+            CommandScope::Group => BotCommandScope::AllGroupChats,
+            CommandScope::Admins => BotCommandScope::AllChatAdministrators,
+        }
+    }
+
+    /// Menu of the scope in the language
+    pub fn commands(self, language: Language) -> Vec<BotCommand> {
+        localized_commands(language)
+            .into_iter()
+            .filter(|command| {
+                self != CommandScope::Group || !ADMIN_COMMANDS.contains(&command.command.as_str())
+            })
+            .collect()
+    }
+}
+
+/// Registers the command menus and the descriptions of the bot for every language,
+/// the English ones are shown to the users of other languages
+pub async fn register_commands(bot: &Bot) -> anyhow::Result<()> {
+    let languages = std::iter::once(None).chain(Language::ALL.map(Some));
+    for language in languages {
+        let code = language.map(|language| language.code()).unwrap_or_default();
+        let language = language.unwrap_or_default();
+        for scope in CommandScope::ALL {
+            bot.set_my_commands(scope.commands(language))
+                .scope(scope.bot_command_scope())
+                .language_code(code)
+                .await?;
+        }
+        bot.set_my_description()
+            .description(language.text(Text::BotDescription))
+            .language_code(code)
+            .await?;
+        bot.set_my_short_description()
+            .short_description(language.text(Text::BotShortDescription))
+            .language_code(code)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Commands with their descriptions in the language, for the help and the command menu
+pub fn localized_commands(language: Language) -> Vec<BotCommand> {
+    Command::bot_commands()
+        .into_iter()
+        .map(|command| {
+            let name = command.command.trim_start_matches('/').to_string();
+            let description = match description_text(&name) {
+                Some(text) => language.text(text).to_string(),
+                None => command.description,
+            };
+            BotCommand::new(name, description)
+        })
+        .collect()
+}
+
+pub fn help_text(language: Language) -> String {
+    let mut lines = vec![language.text(Text::HelpTitle).to_string()];
+    lines.extend(
+        localized_commands(language)
+            .into_iter()
+            .map(|command| format!("/{} — {}", command.command, command.description)),
+    );
+    lines.join("\n")
+}
+
+fn description_text(command: &str) -> Option<Text> {
+    match command {
+        "help" => Some(Text::HelpCommand),
+        "getpage" => Some(Text::GetPageCommand),
+        "cancel" => Some(Text::CancelCommand),
+        "settings" => Some(Text::SettingsCommand),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use teloxide::utils::command::BotCommands;
+
+    use api::i18n::{Language, Text};
+
+    use crate::command::{description_text, help_text, localized_commands, Command, CommandScope};
+
+    #[test]
+    fn test_commands_are_translated() {
+        for command in Command::bot_commands() {
+            assert!(
+                description_text(command.command.trim_start_matches('/')).is_some(),
+                "{}",
+                command.command
+            );
+        }
+        let commands = localized_commands(Language::Ru);
+        assert_eq!(commands[1].command, "getpage");
+        assert_eq!(commands[1].description, "Получить веб-страницу по ссылке");
+        assert!(help_text(Language::En).starts_with("These commands are supported:\n/help — "));
+    }
+
+    #[test]
+    fn test_scope_commands() {
+        let names = |scope: CommandScope| {
+            scope
+                .commands(Language::En)
+                .into_iter()
+                .map(|command| command.command)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(CommandScope::Private),
+            vec!["help", "getpage", "cancel", "settings"]
+        );
+        assert_eq!(
+            names(CommandScope::Group),
+            vec!["help", "getpage", "cancel"]
+        );
+        assert_eq!(names(CommandScope::Admins), names(CommandScope::Private));
+    }
+
+    #[test]
+    fn test_descriptions_fit_limits() {
+        for language in Language::ALL {
+            assert!(language.text(Text::BotDescription).chars().count() <= 512);
+            assert!(language.text(Text::BotShortDescription).chars().count() <= 120);
+            for command in localized_commands(language) {
+                assert!(command.description.chars().count() <= 256);
+            }
+        }
+    }
+}
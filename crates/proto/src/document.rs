@@ -0,0 +1,683 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::bail;
+use teloxide::payloads::SendDocumentSetters;
+use teloxide::prelude::Requester;
+use teloxide::types::{
+    FileId, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, MessageId, ReplyParameters,
+};
+use teloxide::Bot;
+use time::{OffsetDateTime, PrimitiveDateTime};
+use url::Url;
+
+use api::i18n::{Language, Text};
+use api::{
+    LoadedPage, PageError, PageFormat, PageInfo, PageMetadata, PagePersistent, PageResult,
+    PageSource, Recipient,
+};
+use utils::compress::{gzip_file, split_file};
+use utils::hash::make_hash_for_file;
+
+use crate::caption::page_caption;
+
+/// Largest document a bot can send through the public Bot API
+pub const TELEGRAM_UPLOAD_LIMIT: u64 = 50 * 1024 * 1024;
+/// Callback data of the button that loads the page of the document again
+const REFRESH_CALLBACK: &str = "refresh";
+/// Callback data of the button that reports the document as broken
+const REPORT_CALLBACK: &str = "report";
+
+/// Largest document a bot can send through a self-hosted Bot API server
+pub const LOCAL_SERVER_UPLOAD_LIMIT: u64 = 2000 * 1024 * 1024;
+/// Pages that need more parts are not sent
+const MAX_PARTS: usize = 10;
+/// Longest title part of a document name, in characters
+const MAX_NAME_LENGTH: usize = 80;
+
+/// How pages are handed over to the Bot API server
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UploadConfig {
+    pub limit: u64,
+    /// The server shares the work dir, documents are passed as `file://` paths
+    /// instead of being uploaded
+    pub local_files: bool,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        UploadConfig {
+            limit: TELEGRAM_UPLOAD_LIMIT,
+            local_files: false,
+        }
+    }
+}
+
+impl UploadConfig {
+    /// Config for the Bot API server the bot talks to, the public one when `api_url` is not set
+    pub fn new(api_url: Option<&str>, local_files: bool) -> anyhow::Result<Self> {
+        match api_url {
+            Some(_) => Ok(UploadConfig {
+                limit: LOCAL_SERVER_UPLOAD_LIMIT,
+                local_files,
+            }),
+            None if local_files => {
+                bail!("Local files can be sent only with a local Bot API server")
+            }
+            None => Ok(UploadConfig::default()),
+        }
+    }
+}
+
+/// File the page is sent as, with a note on how to open it
+#[derive(Debug, PartialEq)]
+pub struct UploadPart {
+    pub path: String,
+    /// Name of the document shown in the chat
+    pub file_name: String,
+    pub note: Option<String>,
+}
+
+/// The page prepared to fit the upload limit. Pages over the limit are compressed with gzip
+/// and split into parts when compressing is not enough.
+#[derive(Debug)]
+pub struct PreparedUpload {
+    pub parts: Vec<UploadPart>,
+    /// Files made for the upload, removed once it's sent
+    created: Vec<String>,
+}
+
+impl PreparedUpload {
+    /// True when the page is sent as it is
+    pub fn is_original(&self) -> bool {
+        self.created.is_empty()
+    }
+
+    pub async fn cleanup(self) {
+        for path in self.created {
+            tokio::fs::remove_file(path).await.ok();
+        }
+    }
+}
+
+/// `name` is the document name of the page, compressed files and parts are named after it.
+pub async fn prepare_upload(
+    path: &str,
+    name: &str,
+    limit: u64,
+    language: Language,
+) -> Result<PreparedUpload, PageError> {
+    let size = file_size(path).await?;
+    if size <= limit {
+        return Ok(PreparedUpload {
+            parts: vec![UploadPart {
+                path: path.to_string(),
+                file_name: name.to_string(),
+                note: None,
+            }],
+            created: vec![],
+        });
+    }
+    let source = path.to_string();
+    let compressed = tokio::task::spawn_blocking(move || gzip_file(&source))
+        .await
+        .map_err(PageError::storage)?
+        .map_err(PageError::storage)?;
+    let compressed_size = file_size(&compressed).await?;
+    println!(
+        "Page {} has {} bytes, compressed to {} bytes",
+        path, size, compressed_size
+    );
+    if compressed_size <= limit {
+        return Ok(PreparedUpload {
+            parts: vec![UploadPart {
+                path: compressed.clone(),
+                file_name: format!("{}.gz", name),
+                note: Some(language.text(Text::CompressedNote).to_string()),
+            }],
+            created: vec![compressed],
+        });
+    }
+    if compressed_size > limit * MAX_PARTS as u64 {
+        tokio::fs::remove_file(&compressed).await.ok();
+        return Err(PageError::TooLarge {
+            size: compressed_size,
+            limit: limit * MAX_PARTS as u64,
+        });
+    }
+    let source = compressed.clone();
+    let split = tokio::task::spawn_blocking(move || split_file(&source, limit))
+        .await
+        .map_err(PageError::storage)?;
+    let paths = match split {
+        Ok(paths) => paths,
+        Err(err) => {
+            tokio::fs::remove_file(&compressed).await.ok();
+            return Err(PageError::storage(err));
+        }
+    };
+    let parts = paths
+        .iter()
+        .enumerate()
+        .map(|(index, part)| UploadPart {
+            path: part.clone(),
+            file_name: format!("{}.gz.{:03}", name, index + 1),
+            note: Some(language.format(Text::PartNote, &[&(index + 1), &paths.len(), &name])),
+        })
+        .collect();
+    let mut created = paths;
+    created.push(compressed);
+    Ok(PreparedUpload { parts, created })
+}
+
+/// Button of a sent page with the job the page was sent for. Callback data is too short
+/// for long urls, the url is taken from the job.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PageButton {
+    /// Loads the page again
+    Refresh(String),
+    /// Reports the document as broken
+    Report(String),
+}
+
+impl PageButton {
+    pub fn job_id(&self) -> &str {
+        match self {
+            PageButton::Refresh(job_id) | PageButton::Report(job_id) => job_id,
+        }
+    }
+
+    pub fn callback_data(&self) -> String {
+        match self {
+            PageButton::Refresh(job_id) => format!("{}:{}", REFRESH_CALLBACK, job_id),
+            PageButton::Report(job_id) => format!("{}:{}", REPORT_CALLBACK, job_id),
+        }
+    }
+
+    /// Buttons of documents sent before the job was kept have no job id
+    pub fn parse(data: &str) -> Option<Self> {
+        let (action, job_id) = data.split_once(':').unwrap_or((data, ""));
+        match action {
+            REFRESH_CALLBACK => Some(PageButton::Refresh(job_id.to_string())),
+            REPORT_CALLBACK => Some(PageButton::Report(job_id.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// Keyboard attached to every sent page
+pub fn page_keyboard(job_id: &str, language: Language) -> InlineKeyboardMarkup {
+    let refresh = PageButton::Refresh(job_id.to_string());
+    let report = PageButton::Report(job_id.to_string());
+    InlineKeyboardMarkup::new([[
+        InlineKeyboardButton::callback(language.text(Text::RefreshButton), refresh.callback_data()),
+        InlineKeyboardButton::callback(language.text(Text::ReportButton), report.callback_data()),
+    ]])
+}
+
+/// Sends the page of the url to the recipient named after its title and with the caption made
+/// of its metadata, pages over the limit are compressed or split first. The bot of the recipient
+/// is resolved by the caller, the buttons of the document refer to the job.
+/// Returns the telegram file id when the page is sent as a single unchanged document,
+/// so it can be reused.
+pub async fn send_page_document(
+    bot: &Bot,
+    recipient: &Recipient,
+    job_id: &str,
+    page_url: &str,
+    page_result: &PageResult,
+    metadata: &PageMetadata,
+    config: UploadConfig,
+) -> Result<Option<String>, PageError> {
+    let caption = page_caption(page_url, metadata, recipient.language);
+    let path = match page_result {
+        PageResult::TelegramId(id) => {
+            let document = InputFile::file_id(FileId::from(id.to_string()));
+            return send_document(bot, recipient, job_id, document, caption).await;
+        }
+        PageResult::FilePath(path) => path,
+    };
+    let name = document_name(metadata.title.as_deref(), path);
+    let upload = prepare_upload(path, &name, config.limit, recipient.language).await?;
+    let mut file_id = None;
+    let mut result = Ok(());
+    for part in &upload.parts {
+        let caption = join_captions(caption.clone(), part.note.clone());
+        let document = document_file(part, config.local_files);
+        match send_document(bot, recipient, job_id, document, caption).await {
+            Ok(id) => file_id = id,
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        }
+    }
+    let is_original = upload.is_original();
+    upload.cleanup().await;
+    result?;
+    Ok(file_id.filter(|_| is_original))
+}
+
+/// Local files are read by the server itself, the path must be the same for both of them.
+/// The server names them after the file on the disk.
+fn document_file(part: &UploadPart, local_files: bool) -> InputFile {
+    match local_files.then(|| local_file_url(&part.path)).flatten() {
+        Some(url) => InputFile::url(url),
+        None => InputFile::file(PathBuf::from(&part.path)).file_name(part.file_name.clone()),
+    }
+}
+
+fn local_file_url(path: &str) -> Option<Url> {
+    let path = std::path::absolute(path).ok()?;
+    Url::from_file_path(path).ok()
+}
+
+async fn send_document(
+    bot: &Bot,
+    recipient: &Recipient,
+    job_id: &str,
+    document: InputFile,
+    caption: Option<String>,
+) -> Result<Option<String>, PageError> {
+    let mut request = bot
+        .send_document(recipient.chat_id.clone(), document)
+        .reply_markup(page_keyboard(job_id, recipient.language));
+    // the page is still sent when the message is deleted meanwhile
+    if let Some(message_id) = recipient.reply_to_message_id {
+        request = request.reply_parameters(
+            ReplyParameters::new(MessageId(message_id)).allow_sending_without_reply(),
+        );
+    }
+    if let Some(caption) = caption {
+        request = request.caption(caption);
+    }
+    let message = request.await.map_err(PageError::upload)?;
+    Ok(message
+        .document()
+        .map(|document| document.file.id.to_string()))
+}
+
+fn join_captions(caption: Option<String>, note: Option<String>) -> Option<String> {
+    match (caption, note) {
+        (Some(caption), Some(note)) => Some(format!("{}\n\n{}", caption, note)),
+        (caption, note) => caption.or(note),
+    }
+}
+
+async fn file_size(path: &str) -> Result<u64, PageError> {
+    tokio::fs::metadata(path)
+        .await
+        .map(|metadata| metadata.len())
+        .map_err(PageError::storage)
+}
+
+/// The slugified title with the extensions of the file, the file name when there's no title
+fn document_name(title: Option<&str>, path: &str) -> String {
+    let file_name = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let slug = title.map(slugify).unwrap_or_default();
+    if slug.is_empty() {
+        return file_name;
+    }
+    let extensions = file_name.find('.').map(|dot| &file_name[dot..]);
+    format!("{}{}", slug, extensions.unwrap_or_default())
+}
+
+fn slugify(title: &str) -> String {
+    let words = title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    let mut slug = String::new();
+    for word in words {
+        let length = slug.chars().count() + word.chars().count() + 1;
+        if !slug.is_empty() && length > MAX_NAME_LENGTH {
+            break;
+        }
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug.push_str(&word);
+    }
+    slug.chars().take(MAX_NAME_LENGTH).collect()
+}
+
+/// Converts the loaded page to the format requested by the chat.
+pub async fn convert_page(result: &PageResult, format: PageFormat) -> anyhow::Result<PageResult> {
+    match (format, result) {
+        (PageFormat::Html, _) => Ok(result.clone()),
+        (PageFormat::Gzip, PageResult::FilePath(path)) => {
+            let path = path.clone();
+            let compressed = tokio::task::spawn_blocking(move || gzip_file(&path)).await??;
+            Ok(PageResult::FilePath(compressed))
+        }
+        (_, PageResult::TelegramId(_)) => {
+            bail!(
+                "The page is not available as a file to convert it to {}",
+                format.as_str()
+            )
+        }
+    }
+}
+
+/// Removes the file of the page, pages sent by their file id have none
+pub async fn clear_data(result: PageResult) {
+    if let PageResult::FilePath(path) = result {
+        tokio::fs::remove_file(path).await.ok();
+    }
+}
+
+/// Keeps the file id of the sent page, so the page is not loaded again for a while.
+pub async fn save_to_cache(
+    file_id: &str,
+    page: &LoadedPage,
+    cache: &Arc<dyn PagePersistent>,
+    page_url: String,
+    bot_id: Option<String>,
+) {
+    // older copies must not replace the page once the site is back
+    if page.metadata.source != PageSource::Live {
+        return;
+    }
+    let now = OffsetDateTime::now_utc();
+    let page_info = prepare_page_hash(&page.result).map(|hash| PageInfo {
+        telegram_file_id: file_id.to_string(),
+        file_hash: hash,
+        page_url,
+        timestamp_ms: PrimitiveDateTime::new(now.date(), now.time()),
+        bot_id,
+        egress: page.metadata.egress,
+        title: page.metadata.title.clone(),
+        canonical_url: page.metadata.canonical_url.clone(),
+    });
+
+    if let Some(page_info) = page_info {
+        let _ = cache.save(&page_info).await;
+    }
+}
+
+fn prepare_page_hash(page_result: &PageResult) -> Option<String> {
+    match page_result {
+        PageResult::FilePath(path) => make_hash_for_file(path),
+        PageResult::TelegramId(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tempfile::tempdir;
+    use time::macros::datetime;
+
+    use api::i18n::Language;
+    use api::{
+        LoadedPage, PageError, PageFormat, PageInfo, PageMetadata, PagePersistent, PageResult,
+        PageSource,
+    };
+
+    use crate::document::{
+        convert_page, document_name, local_file_url, prepare_page_hash, prepare_upload,
+        save_to_cache, PageButton, UploadConfig, LOCAL_SERVER_UPLOAD_LIMIT,
+    };
+
+    /// Content that doesn't compress well, like images embedded into the page
+    fn random_content(size: usize) -> Vec<u8> {
+        let mut state: u32 = 1;
+        (0..size)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn write(path: &Path, content: &[u8]) -> anyhow::Result<String> {
+        File::create(path)?.write_all(content)?;
+        Ok(path.to_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_small_pages_are_sent_as_they_are() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = write(&dir.path().join("page.html"), b"<html/>")?;
+
+        let upload = prepare_upload(&path, "page.html", 1000, Language::En).await?;
+
+        assert!(upload.is_original());
+        assert_eq!(upload.parts.len(), 1);
+        assert_eq!(upload.parts[0].path, path);
+        assert_eq!(upload.parts[0].note, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_large_pages_are_compressed() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = write(&dir.path().join("page.html"), &b"<p>text</p>".repeat(1000))?;
+
+        let upload = prepare_upload(&path, "page.html", 1000, Language::En).await?;
+
+        assert!(!upload.is_original());
+        assert_eq!(upload.parts.len(), 1);
+        assert!(upload.parts[0].path.ends_with("page.html.gz"));
+        assert_eq!(upload.parts[0].file_name, "page.html.gz");
+        assert!(upload.parts[0].note.is_some());
+        let compressed = upload.parts[0].path.clone();
+        upload.cleanup().await;
+        assert!(!Path::new(&compressed).exists());
+        assert!(Path::new(&path).exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pages_over_limit_are_split() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let content = random_content(2500);
+        let path = write(&dir.path().join("page.html"), &content)?;
+
+        let upload = prepare_upload(&path, "page.html", 1000, Language::En).await?;
+
+        assert_eq!(upload.parts.len(), 3);
+        assert_eq!(upload.parts[2].file_name, "page.html.gz.003");
+        assert_eq!(
+            upload.parts[0].note.as_deref(),
+            Some("Part 1 of 3. Join the parts and unpack them: cat page.html.gz.* | gunzip > page.html")
+        );
+        let mut joined = Vec::new();
+        for part in &upload.parts {
+            joined.extend(std::fs::read(&part.path)?);
+        }
+        let mut expected = GzEncoder::new(Vec::new(), Compression::default());
+        expected.write_all(&content)?;
+        assert_eq!(joined, expected.finish()?);
+        upload.cleanup().await;
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_too_many_parts() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = write(&dir.path().join("page.html"), &random_content(20_000))?;
+
+        let err = prepare_upload(&path, "page.html", 1000, Language::En)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, PageError::TooLarge { limit: 10_000, .. }));
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_upload_config() -> anyhow::Result<()> {
+        assert_eq!(UploadConfig::new(None, false)?, UploadConfig::default());
+        assert!(UploadConfig::new(None, true).is_err());
+        let config = UploadConfig::new(Some("http://localhost:8081"), true)?;
+        assert_eq!(config.limit, LOCAL_SERVER_UPLOAD_LIMIT);
+        assert!(config.local_files);
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_file_url() {
+        assert_eq!(
+            local_file_url("/data/pages/page 1.html").unwrap().as_str(),
+            "file:///data/pages/page%201.html"
+        );
+        let relative = local_file_url("pages/page.html").unwrap();
+        assert!(relative.path().starts_with('/'));
+        assert!(relative.path().ends_with("/pages/page.html"));
+    }
+
+    #[test]
+    fn test_document_name() {
+        assert_eq!(
+            document_name(Some("Rust & Tokio: a guide!"), "/work/V1StGXR8.html"),
+            "rust-tokio-a-guide.html"
+        );
+        assert_eq!(
+            document_name(Some("Новости дня"), "/work/V1StGXR8.html.gz"),
+            "новости-дня.html.gz"
+        );
+        assert_eq!(
+            document_name(Some("!!!"), "/work/V1StGXR8.html"),
+            "V1StGXR8.html"
+        );
+        assert_eq!(document_name(None, "/work/V1StGXR8.html"), "V1StGXR8.html");
+        let long_title = "word ".repeat(30);
+        assert_eq!(
+            document_name(Some(&long_title), "/work/V1StGXR8.html"),
+            format!("{}.html", ["word"; 16].join("-"))
+        );
+    }
+
+    #[test]
+    fn test_prepare_page_info_empty_result() {
+        assert_eq!(
+            prepare_page_hash(&PageResult::TelegramId("id".to_string())),
+            None
+        )
+    }
+
+    #[test]
+    fn test_prepare_page_info_file() -> anyhow::Result<()> {
+        let tmpdir = tempdir()?;
+        let file_path = tmpdir.path().join("test.txt");
+        let mut file = File::create(&file_path)?;
+        write!(file, "test hash")?;
+
+        let result = prepare_page_hash(&PageResult::FilePath(
+            file_path.to_str().unwrap().to_string(),
+        ));
+        assert_eq!(
+            result,
+            Some("VKZIO4rKVcnfKjW69x2ZZd39YjRo2B1RIpvV630eHBs=".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct RecordingPagePersistent {
+        saved: Mutex<Vec<PageInfo>>,
+    }
+
+    #[async_trait]
+    impl PagePersistent for RecordingPagePersistent {
+        async fn save(&self, page_info: &PageInfo) -> Result<(), PageError> {
+            self.saved.lock().unwrap().push(page_info.clone());
+            Ok(())
+        }
+
+        async fn get(
+            &self,
+            _page_url: &str,
+            _bot_id: Option<&str>,
+        ) -> Result<Option<PageInfo>, PageError> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_archived_pages_are_not_cached() -> anyhow::Result<()> {
+        let tmpdir = tempdir()?;
+        let file_path = tmpdir.path().join("page.html");
+        std::fs::write(&file_path, "<html/>")?;
+        let recording = Arc::new(RecordingPagePersistent::default());
+        let cache: Arc<dyn PagePersistent> = recording.clone();
+        let live = LoadedPage::from(PageResult::FilePath(
+            file_path.to_str().unwrap().to_string(),
+        ));
+        let archived = LoadedPage {
+            metadata: PageMetadata {
+                source: PageSource::Archive {
+                    archive: "Wayback Machine".to_string(),
+                    snapshot_at: datetime!(2024-01-02 03:04:05),
+                },
+                ..PageMetadata::default()
+            },
+            ..live.clone()
+        };
+        let url = "https://example.com/".to_string();
+
+        save_to_cache("archived_id", &archived, &cache, url.clone(), None).await;
+        save_to_cache("live_id", &live, &cache, url, None).await;
+
+        let saved = recording.saved.lock().unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].telegram_file_id, "live_id");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_convert_page_to_gzip() -> anyhow::Result<()> {
+        let tmpdir = tempdir()?;
+        let file_path = tmpdir.path().join("page.html");
+        std::fs::write(&file_path, "<html/>")?;
+        let page = PageResult::FilePath(file_path.to_str().unwrap().to_string());
+
+        assert_eq!(convert_page(&page, PageFormat::Html).await?, page);
+        let PageResult::FilePath(compressed) = convert_page(&page, PageFormat::Gzip).await? else {
+            panic!("File path is expected");
+        };
+        assert!(compressed.ends_with("page.html.gz"));
+        let mut content = String::new();
+        flate2::read::GzDecoder::new(File::open(compressed)?).read_to_string(&mut content)?;
+        assert_eq!(content, "<html/>");
+
+        let cached = PageResult::TelegramId("id".to_string());
+        assert!(convert_page(&cached, PageFormat::Gzip).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_buttons_keep_the_job() {
+        let job_id = "V1StGXR8_Z5jdHi6B-myT";
+        for button in [
+            PageButton::Refresh(job_id.to_string()),
+            PageButton::Report(job_id.to_string()),
+        ] {
+            let data = button.callback_data();
+            // the limit of telegram for callback data
+            assert!(data.len() <= 64);
+            assert_eq!(PageButton::parse(&data), Some(button));
+        }
+        assert_eq!(
+            PageButton::parse("refresh"),
+            Some(PageButton::Refresh(String::new()))
+        );
+        assert_eq!(PageButton::parse("settings:format"), None);
+    }
+}
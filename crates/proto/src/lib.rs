@@ -1 +1,5 @@
+pub mod bot_registry;
+pub mod caption;
 pub mod command;
+pub mod document;
+pub mod status_reporter;
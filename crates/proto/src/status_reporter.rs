@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use teloxide::payloads::EditMessageTextSetters;
+use teloxide::prelude::Requester;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId};
+use teloxide::Bot;
+
+use api::i18n::{Language, Text};
+use api::{JobStatus, StatusMessage, StatusReporter};
+
+use crate::bot_registry::BotRegistry;
+
+/// Callback data of the button that cancels the request the status message belongs to
+pub const CANCEL_CALLBACK: &str = "cancel";
+
+/// Reports job progress by editing the status message sent when the request was accepted.
+/// The message is edited by the bot that sent it.
+#[derive(Clone)]
+pub struct TeloxideStatusReporter {
+    bots: Arc<BotRegistry>,
+}
+
+impl TeloxideStatusReporter {
+    pub fn new(bot: Bot) -> Self {
+        Self::from_registry(Arc::new(BotRegistry::new(bot)))
+    }
+
+    pub fn from_registry(bots: Arc<BotRegistry>) -> Self {
+        TeloxideStatusReporter { bots }
+    }
+}
+
+#[async_trait]
+impl StatusReporter for TeloxideStatusReporter {
+    async fn report_status(
+        &self,
+        message: &StatusMessage,
+        status: &JobStatus,
+    ) -> anyhow::Result<()> {
+        let chat_id = message.chat_id.to_string();
+        let message_id = MessageId(message.message_id);
+        let bot = self.bots.resolve(message.bot_id.as_deref()).await?;
+        let language = message.language;
+        match status_text(status, language) {
+            None => {
+                bot.delete_message(chat_id, message_id).await?;
+            }
+            Some(text) if is_in_progress(status) => {
+                bot.edit_message_text(chat_id, message_id, text)
+                    .reply_markup(cancel_keyboard(language))
+                    .await?;
+            }
+            Some(text) => {
+                bot.edit_message_text(chat_id, message_id, text).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Text of the status message for the given stage, `None` when the message should be removed.
+pub fn status_text(status: &JobStatus, language: Language) -> Option<String> {
+    let text = match status {
+        JobStatus::Queued => Text::StatusQueued,
+        JobStatus::Loading => Text::StatusLoading,
+        JobStatus::Uploading => Text::StatusUploading,
+        JobStatus::Done => return None,
+        JobStatus::Failed(reason) => return Some(language.format(Text::StatusFailed, &[reason])),
+        JobStatus::Cancelled => Text::StatusCancelled,
+    };
+    Some(language.text(text).to_string())
+}
+
+/// Keyboard attached to the status message while the request can still be cancelled.
+pub fn cancel_keyboard(language: Language) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        language.text(Text::CancelButton),
+        CANCEL_CALLBACK,
+    )]])
+}
+
+fn is_in_progress(status: &JobStatus) -> bool {
+    matches!(
+        status,
+        JobStatus::Queued | JobStatus::Loading | JobStatus::Uploading
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use api::i18n::Language;
+    use api::JobStatus;
+
+    use crate::status_reporter::status_text;
+
+    #[test]
+    fn test_status_text() {
+        assert_eq!(
+            status_text(&JobStatus::Queued, Language::En),
+            Some("Queued".to_string())
+        );
+        assert_eq!(status_text(&JobStatus::Done, Language::En), None);
+        assert_eq!(
+            status_text(&JobStatus::Failed("timeout".to_string()), Language::En),
+            Some("Can't load the page: timeout".to_string())
+        );
+        assert_eq!(
+            status_text(&JobStatus::Cancelled, Language::Ru),
+            Some("Отменено".to_string())
+        );
+    }
+}
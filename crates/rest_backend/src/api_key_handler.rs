@@ -0,0 +1,332 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::middleware::from_fn;
+use axum::routing::{delete, post};
+use axum::{Json, Router};
+use nanoid::nanoid;
+use serde_json::json;
+
+use api::{bot_id_from_token, ApiKey, ApiKeyPersistent, PageFormat};
+
+use crate::auth::require_internal;
+use crate::error::AppError;
+use crate::load_page_handler::current_time;
+use crate::rate_limiter::RateLimiter;
+
+const DEFAULT_RATE_LIMIT_PER_MINUTE: u32 = 10;
+const DEFAULT_DAILY_QUOTA: u32 = 1000;
+
+/// Routes to manage API keys, available with the shared secret only.
+pub(crate) fn router(api_keys: Arc<dyn ApiKeyPersistent>) -> Router {
+    Router::new()
+        .route("/v1/admin/keys", post(create_key).get(list_keys))
+        .route("/v1/admin/keys/{id}", delete(revoke_key))
+        .route_layer(from_fn(require_internal))
+        .with_state(api_keys)
+}
+
+/// Bot the tenant's pages are sent with, tenants never use the bots of the backend.
+pub(crate) fn tenant_bot_id(
+    api_key: &ApiKey,
+    requested_bot: Option<&str>,
+) -> Result<String, AppError> {
+    let bot_id = api_key.bot_id().ok_or(AppError::Forbidden)?;
+    if requested_bot.is_some_and(|requested_bot| requested_bot != bot_id) {
+        return Err(AppError::Forbidden);
+    }
+    Ok(bot_id)
+}
+
+/// Checks that the key can request one more page in the format.
+pub(crate) async fn check_limits(
+    api_keys: &Arc<dyn ApiKeyPersistent>,
+    rate_limiter: &RateLimiter,
+    api_key: &ApiKey,
+    format: PageFormat,
+) -> Result<(), AppError> {
+    if !api_key.allowed_formats.contains(&format) {
+        return Err(AppError::BadRequest(format!(
+            "Format {} is not allowed for the key",
+            format.as_str()
+        )));
+    }
+    if !rate_limiter.try_acquire(&api_key.id, api_key.rate_limit_per_minute) {
+        return Err(AppError::TooManyRequests("Rate limit exceeded".to_string()));
+    }
+    let day = current_time().date();
+    let quota_used = api_keys
+        .try_use_quota(&api_key.id, day, api_key.daily_quota)
+        .await;
+    // the request is not made, so it doesn't count against the rate limit
+    if !matches!(quota_used, Ok(true)) {
+        rate_limiter.release(&api_key.id);
+    }
+    if !quota_used? {
+        return Err(AppError::TooManyRequests(
+            "Daily quota exceeded".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+async fn create_key(
+    State(api_keys): State<Arc<dyn ApiKeyPersistent>>,
+    Json(payload): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let name = payload["name"]
+        .as_str()
+        .ok_or(AppError::BadRequest("Name is not set".to_string()))?
+        .to_owned();
+    let allowed_formats = match payload["allowed_formats"].as_array() {
+        None => vec![PageFormat::Html],
+        Some(formats) => formats
+            .iter()
+            .map(|format| {
+                format
+                    .as_str()
+                    .and_then(|format| PageFormat::parse(format).ok())
+                    .ok_or(AppError::BadRequest(format!("Unknown format {}", format)))
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+    // pages of the tenant are sent only with its own bot
+    let bot_token = payload["bot_token"]
+        .as_str()
+        .ok_or(AppError::BadRequest("Bot token is not set".to_string()))?;
+    if bot_id_from_token(bot_token).is_none() {
+        return Err(AppError::BadRequest(
+            "Bot token must start with the bot id".to_string(),
+        ));
+    }
+    let api_key = ApiKey {
+        id: nanoid!(),
+        name,
+        secret: nanoid!(32),
+        rate_limit_per_minute: read_limit(&payload, "rate_limit_per_minute")?
+            .unwrap_or(DEFAULT_RATE_LIMIT_PER_MINUTE),
+        daily_quota: read_limit(&payload, "daily_quota")?.unwrap_or(DEFAULT_DAILY_QUOTA),
+        allowed_formats,
+        bot_token: Some(bot_token.to_string()),
+        revoked: false,
+        created_at: current_time(),
+    };
+    api_keys.save_api_key(&api_key).await?;
+    println!("API key {} created for {}", api_key.id, api_key.name);
+
+    // the secret is shown only once
+    let mut response = key_json(&api_key, 0);
+    response["secret"] = json!(api_key.secret);
+    Ok(Json(response))
+}
+
+async fn list_keys(
+    State(api_keys): State<Arc<dyn ApiKeyPersistent>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let day = current_time().date();
+    let mut keys = Vec::new();
+    for api_key in api_keys.list_api_keys().await? {
+        let usage = api_keys.get_usage(&api_key.id, day).await?;
+        keys.push(key_json(&api_key, usage));
+    }
+    Ok(Json(json!({ "keys": keys })))
+}
+
+async fn revoke_key(
+    State(api_keys): State<Arc<dyn ApiKeyPersistent>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    if api_keys.revoke_api_key(&id).await? {
+        println!("API key {} revoked", id);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+fn read_limit(payload: &serde_json::Value, field: &str) -> Result<Option<u32>, AppError> {
+    let value = &payload[field];
+    if value.is_null() {
+        return Ok(None);
+    }
+    value
+        .as_u64()
+        .and_then(|limit| u32::try_from(limit).ok())
+        .map(Some)
+        .ok_or(AppError::BadRequest(format!("{} must be a number", field)))
+}
+
+fn key_json(api_key: &ApiKey, usage_today: u64) -> serde_json::Value {
+    json!({
+        "id": api_key.id,
+        "name": api_key.name,
+        "rate_limit_per_minute": api_key.rate_limit_per_minute,
+        "daily_quota": api_key.daily_quota,
+        "allowed_formats": api_key
+            .allowed_formats
+            .iter()
+            .map(PageFormat::as_str)
+            .collect::<Vec<_>>(),
+        "has_bot_token": api_key.bot_token.is_some(),
+        "revoked": api_key.revoked,
+        "created_at": api_key.created_at.to_string(),
+        "usage_today": usage_today,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use axum::{Extension, Router};
+    use tower::ServiceExt;
+
+    use api::{ApiKeyPersistent, PageFormat};
+    use sqlite::sqlite_persistent::in_memory_db;
+
+    use crate::api_key_handler::{check_limits, router, tenant_bot_id};
+    use crate::auth::test::api_key;
+    use crate::auth::Caller;
+    use crate::error::AppError;
+    use crate::load_page_handler::current_time;
+    use crate::rate_limiter::RateLimiter;
+
+    async fn send(
+        router: &Router,
+        method: &str,
+        uri: &str,
+        body: &str,
+    ) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))?;
+        let response = router.clone().oneshot(request).await?;
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await?;
+        let json = serde_json::from_slice(&bytes).unwrap_or_default();
+        Ok((status, json))
+    }
+
+    #[tokio::test]
+    async fn test_create_list_and_revoke_key() -> anyhow::Result<()> {
+        let db: Arc<dyn ApiKeyPersistent> = Arc::new(in_memory_db().await?);
+        let router = router(db.clone()).layer(Extension(Caller::Internal));
+
+        let body = r#"{"name": "Other team", "daily_quota": 5, "allowed_formats": ["html", "gzip"],
+                       "bot_token": "123:token"}"#;
+        let (status, created) = send(&router, "POST", "/v1/admin/keys", body).await?;
+        assert_eq!(status, StatusCode::OK);
+        let id = created["id"].as_str().unwrap();
+        assert_eq!(created["secret"].as_str().map(str::len), Some(32));
+        let api_key = db.get_api_key(id).await?.unwrap();
+        assert_eq!(api_key.daily_quota, 5);
+        assert_eq!(api_key.rate_limit_per_minute, 10);
+        assert_eq!(
+            api_key.allowed_formats,
+            vec![PageFormat::Html, PageFormat::Gzip]
+        );
+        assert_eq!(api_key.bot_token, Some("123:token".to_string()));
+
+        let (status, listed) = send(&router, "GET", "/v1/admin/keys", "").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(listed["keys"][0]["id"], id);
+        assert_eq!(listed["keys"][0]["usage_today"], 0);
+        assert_eq!(listed["keys"][0]["secret"], serde_json::Value::Null);
+
+        let uri = format!("/v1/admin/keys/{}", id);
+        let (status, _) = send(&router, "DELETE", &uri, "").await?;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&router, "DELETE", &uri, "").await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_key_validation() -> anyhow::Result<()> {
+        let router = router(Arc::new(in_memory_db().await?)).layer(Extension(Caller::Internal));
+
+        let (status, _) = send(&router, "POST", "/v1/admin/keys", r#"{}"#).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body = r#"{"name": "team", "allowed_formats": ["pdf"], "bot_token": "123:token"}"#;
+        let (status, _) = send(&router, "POST", "/v1/admin/keys", body).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body = r#"{"name": "team", "daily_quota": -1, "bot_token": "123:token"}"#;
+        let (status, _) = send(&router, "POST", "/v1/admin/keys", body).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body = r#"{"name": "team"}"#;
+        let (status, _) = send(&router, "POST", "/v1/admin/keys", body).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body = r#"{"name": "team", "bot_token": "token"}"#;
+        let (status, _) = send(&router, "POST", "/v1/admin/keys", body).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check_limits() -> anyhow::Result<()> {
+        let db: Arc<dyn ApiKeyPersistent> = Arc::new(in_memory_db().await?);
+        let rate_limiter = RateLimiter::new(Duration::from_secs(60));
+        let mut api_key = api_key("key_1", false);
+        api_key.rate_limit_per_minute = 2;
+        api_key.daily_quota = 3;
+
+        let check = |format| check_limits(&db, &rate_limiter, &api_key, format);
+        assert!(matches!(
+            check(PageFormat::Gzip).await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(check(PageFormat::Html).await.is_ok());
+        assert!(check(PageFormat::Html).await.is_ok());
+        assert!(matches!(
+            check(PageFormat::Html).await,
+            Err(AppError::TooManyRequests(_))
+        ));
+
+        // another instance has its own rate limit, but the daily quota is shared
+        let rate_limiter = RateLimiter::new(Duration::from_secs(60));
+        let check = |format| check_limits(&db, &rate_limiter, &api_key, format);
+        assert!(check(PageFormat::Html).await.is_ok());
+        assert!(matches!(
+            check(PageFormat::Html).await,
+            Err(AppError::TooManyRequests(_))
+        ));
+        assert_eq!(db.get_usage("key_1", current_time().date()).await?, 3);
+
+        // requests over the quota don't use up the rate limit
+        let mut api_key = api_key.clone();
+        api_key.daily_quota = 10;
+        let check = |format| check_limits(&db, &rate_limiter, &api_key, format);
+        assert!(check(PageFormat::Html).await.is_ok());
+        assert!(matches!(
+            check(PageFormat::Html).await,
+            Err(AppError::TooManyRequests(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tenant_bot_id() {
+        let mut api_key = api_key("key_1", false);
+        assert!(matches!(
+            tenant_bot_id(&api_key, None),
+            Err(AppError::Forbidden)
+        ));
+
+        api_key.bot_token = Some("123:token".to_string());
+        assert_eq!(tenant_bot_id(&api_key, None).ok(), Some("123".to_string()));
+        assert_eq!(
+            tenant_bot_id(&api_key, Some("123")).ok(),
+            Some("123".to_string())
+        );
+        assert!(matches!(
+            tenant_bot_id(&api_key, Some("456")),
+            Err(AppError::Forbidden)
+        ));
+    }
+}
@@ -0,0 +1,245 @@
+use std::sync::Arc;
+
+use axum::body::{to_bytes, Body};
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Extension;
+
+use api::{ApiKey, ApiKeyPersistent};
+use utils::signature::{verify_request, API_KEY_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+use crate::error::AppError;
+use crate::worker_handler::MAX_PAGE_SIZE;
+
+/// Who signed the request, added to the request extensions.
+#[derive(Clone, Debug)]
+pub(crate) enum Caller {
+    /// Our bot, render workers and admins that know the shared secret
+    Internal,
+    Tenant(ApiKey),
+}
+
+#[derive(Clone)]
+pub(crate) struct AuthState {
+    pub(crate) api_secret: Arc<String>,
+    pub(crate) api_keys: Arc<dyn ApiKeyPersistent>,
+}
+
+/// Rejects requests that are not signed with the shared secret or an active API key.
+pub(crate) async fn verify_signature(
+    State(state): State<AuthState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let (mut parts, body) = request.into_parts();
+    let header = |name: &str| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let timestamp = header(TIMESTAMP_HEADER).and_then(|timestamp| timestamp.parse().ok());
+    let (Some(timestamp), Some(signature)) = (timestamp, header(SIGNATURE_HEADER)) else {
+        return Err(AppError::Unauthorized);
+    };
+    let caller = match header(API_KEY_HEADER) {
+        None => Caller::Internal,
+        Some(key_id) => match state.api_keys.get_api_key(&key_id).await? {
+            Some(api_key) if !api_key.revoked => Caller::Tenant(api_key),
+            _ => return Err(AppError::Unauthorized),
+        },
+    };
+    let secret = match &caller {
+        Caller::Internal => state.api_secret.as_str(),
+        Caller::Tenant(api_key) => api_key.secret.as_str(),
+    };
+
+    let body = to_bytes(body, MAX_PAGE_SIZE)
+        .await
+        .map_err(|_| AppError::BadRequest("Can't read the request body".to_string()))?;
+    let path_and_query = parts
+        .uri
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .unwrap_or(parts.uri.path());
+    let verified = verify_request(
+        secret,
+        timestamp,
+        &signature,
+        parts.method.as_str(),
+        path_and_query,
+        &body,
+    );
+    if !verified {
+        return Err(AppError::Unauthorized);
+    }
+    parts.extensions.insert(caller);
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
+
+/// Allows only requests signed with the shared secret, used for admin and render worker routes.
+pub(crate) async fn require_internal(
+    Extension(caller): Extension<Caller>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    match caller {
+        Caller::Internal => Ok(next.run(request).await),
+        Caller::Tenant(_) => Err(AppError::Forbidden),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use axum::middleware::{from_fn, from_fn_with_state};
+    use axum::routing::post;
+    use axum::Router;
+    use time::macros::datetime;
+    use tower::ServiceExt;
+
+    use api::{ApiKey, ApiKeyPersistent, PageFormat};
+    use sqlite::sqlite_persistent::in_memory_db;
+    use utils::signature::{sign_request, API_KEY_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+    use crate::auth::{require_internal, verify_signature, AuthState};
+
+    async fn router() -> anyhow::Result<Router> {
+        let db = in_memory_db().await?;
+        db.save_api_key(&api_key("key_1", false)).await?;
+        db.save_api_key(&api_key("key_2", true)).await?;
+        let state = AuthState {
+            api_secret: Arc::new("secret".to_string()),
+            api_keys: Arc::new(db),
+        };
+        let internal = Router::new()
+            .route("/v1/admin", post(|| async { "admin" }))
+            .route_layer(from_fn(require_internal));
+        Ok(Router::new()
+            .route("/v1/test", post(|body: String| async move { body }))
+            .merge(internal)
+            .layer(from_fn_with_state(state, verify_signature)))
+    }
+
+    pub(crate) fn api_key(id: &str, revoked: bool) -> ApiKey {
+        ApiKey {
+            id: id.to_string(),
+            name: "Other team".to_string(),
+            secret: format!("{}_secret", id),
+            rate_limit_per_minute: 10,
+            daily_quota: 10,
+            allowed_formats: vec![PageFormat::Html],
+            bot_token: None,
+            revoked,
+            created_at: datetime!(2024-01-02 10:00:00),
+        }
+    }
+
+    async fn send(uri: &str, headers: &[(&str, String)], body: &str) -> anyhow::Result<StatusCode> {
+        let mut request = Request::builder().method("POST").uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+        let response = router()
+            .await?
+            .oneshot(request.body(Body::from(body.to_string()))?)
+            .await?;
+        Ok(response.status())
+    }
+
+    fn signed_with_key(key_id: &str, secret: &str, path: &str) -> Vec<(&'static str, String)> {
+        let mut headers = sign_request(secret, "POST", path, b"body").to_vec();
+        headers.push((API_KEY_HEADER, key_id.to_string()));
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_signed_request() -> anyhow::Result<()> {
+        let headers = sign_request("secret", "POST", "/v1/test", b"body");
+        assert_eq!(send("/v1/test", &headers, "body").await?, StatusCode::OK);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reject_unsigned_request() -> anyhow::Result<()> {
+        assert_eq!(
+            send("/v1/test", &[], "body").await?,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let headers = sign_request("other", "POST", "/v1/test", b"body");
+        assert_eq!(
+            send("/v1/test", &headers, "body").await?,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let headers = sign_request("secret", "POST", "/v1/test", b"body");
+        assert_eq!(
+            send("/v1/test", &headers, "changed").await?,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let headers = sign_request("secret", "POST", "/v1/test?limit=1", b"body");
+        assert_eq!(
+            send("/v1/test?limit=1", &headers, "body").await?,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send("/v1/test?limit=1000", &headers, "body").await?,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let headers = [
+            (TIMESTAMP_HEADER, "1".to_string()),
+            (SIGNATURE_HEADER, headers[1].1.clone()),
+        ];
+        assert_eq!(
+            send("/v1/test", &headers, "body").await?,
+            StatusCode::UNAUTHORIZED
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_signed_with_api_key() -> anyhow::Result<()> {
+        let headers = signed_with_key("key_1", "key_1_secret", "/v1/test");
+        assert_eq!(send("/v1/test", &headers, "body").await?, StatusCode::OK);
+
+        let headers = signed_with_key("key_1", "secret", "/v1/test");
+        assert_eq!(
+            send("/v1/test", &headers, "body").await?,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let revoked = signed_with_key("key_2", "key_2_secret", "/v1/test");
+        assert_eq!(
+            send("/v1/test", &revoked, "body").await?,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let unknown = signed_with_key("key_3", "key_3_secret", "/v1/test");
+        assert_eq!(
+            send("/v1/test", &unknown, "body").await?,
+            StatusCode::UNAUTHORIZED
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_internal_routes() -> anyhow::Result<()> {
+        let headers = sign_request("secret", "POST", "/v1/admin", b"body");
+        assert_eq!(send("/v1/admin", &headers, "body").await?, StatusCode::OK);
+
+        let headers = signed_with_key("key_1", "key_1_secret", "/v1/admin");
+        assert_eq!(
+            send("/v1/admin", &headers, "body").await?,
+            StatusCode::FORBIDDEN
+        );
+        Ok(())
+    }
+}
@@ -1,10 +1,19 @@
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 
+use api::i18n::Language;
+use api::PageError;
+
 // pub(crate) struct AppError(anyhow::Error);
 pub(crate) enum AppError {
     BadRequest(String),
+    Unauthorized,
+    Forbidden,
+    TooManyRequests(String),
     ServerError(anyhow::Error),
+    /// Made with `map_err`, `?` turns page errors into server errors.
+    /// The message is in the language of the request
+    Page(PageError, Language),
 }
 
 // Tell axum how to convert `AppError` into a response.
@@ -12,13 +21,38 @@ impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         match self {
             AppError::BadRequest(message) => (StatusCode::BAD_REQUEST, message).into_response(),
-            AppError::ServerError(_) => {
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED.into_response(),
+            AppError::Forbidden => StatusCode::FORBIDDEN.into_response(),
+            AppError::TooManyRequests(message) => {
+                (StatusCode::TOO_MANY_REQUESTS, message).into_response()
+            }
+            AppError::ServerError(err) => {
+                println!("Request failed: {:#}", err);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong").into_response()
             }
+            AppError::Page(err, language) => {
+                if let PageError::StorageFailed(_) = err {
+                    println!("Request failed: {}", err);
+                }
+                (page_error_status(&err), err.user_message(language)).into_response()
+            }
         }
     }
 }
 
+fn page_error_status(err: &PageError) -> StatusCode {
+    match err {
+        PageError::InvalidUrl(_) => StatusCode::BAD_REQUEST,
+        PageError::BlockedByPolicy(_) => StatusCode::FORBIDDEN,
+        PageError::FetchFailed { .. }
+        | PageError::RenderFailed { .. }
+        | PageError::UploadFailed(_) => StatusCode::BAD_GATEWAY,
+        PageError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        PageError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        PageError::StorageFailed(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
 // This enables using `?` on functions that return `Result<_, anyhow::Error>` to turn them into
 // `Result<_, AppError>`. That way you don't need to do that manually.
 impl<E> From<E> for AppError
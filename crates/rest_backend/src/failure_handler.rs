@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::extract::{Query, State};
+use axum::middleware::from_fn;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::json;
+
+use api::{FailureLog, PageFailure};
+
+use crate::auth::require_internal;
+use crate::error::AppError;
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 1000;
+
+/// Routes to look into pages that failed to load, available with the shared secret only.
+pub(crate) fn router(failures: Arc<dyn FailureLog>) -> Router {
+    Router::new()
+        .route("/v1/admin/failures", get(list_failures))
+        .route_layer(from_fn(require_internal))
+        .with_state(failures)
+}
+
+async fn list_failures(
+    State(failures): State<Arc<dyn FailureLog>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let limit = match params.get("limit") {
+        None => DEFAULT_LIMIT,
+        Some(limit) => limit
+            .parse::<u32>()
+            .map_err(|_| AppError::BadRequest("limit must be a number".to_string()))?
+            .min(MAX_LIMIT),
+    };
+    let url_filter = params.get("url").map(String::as_str);
+    let failures = failures
+        .list_failures(url_filter, limit)
+        .await?
+        .iter()
+        .map(failure_json)
+        .collect::<Vec<_>>();
+    Ok(Json(json!({ "failures": failures })))
+}
+
+fn failure_json(failure: &PageFailure) -> serde_json::Value {
+    let mut json = json!({
+        "page_url": failure.page_url,
+        "error": failure.error,
+        "transient": failure.transient,
+        "failed_at": failure.failed_at.to_string(),
+    });
+    if let Some(diagnostics) = &failure.diagnostics {
+        json["diagnostics"] = json!({
+            "command": diagnostics.command,
+            "exit_code": diagnostics.exit_code,
+            "stderr": diagnostics.stderr,
+            "duration_ms": diagnostics.duration.as_millis() as u64,
+        });
+    }
+    json
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use axum::{Extension, Router};
+    use time::macros::datetime;
+    use tower::ServiceExt;
+
+    use api::{FailureLog, PageFailure, RenderDiagnostics};
+    use sqlite::sqlite_persistent::in_memory_db;
+
+    use crate::auth::Caller;
+    use crate::failure_handler::router;
+
+    async fn get(router: &Router, uri: &str) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+        let request = Request::builder().uri(uri).body(Body::empty())?;
+        let response = router.clone().oneshot(request).await?;
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await?;
+        let json = serde_json::from_slice(&bytes).unwrap_or_default();
+        Ok((status, json))
+    }
+
+    #[tokio::test]
+    async fn test_lists_failures() -> anyhow::Result<()> {
+        let db: Arc<dyn FailureLog> = Arc::new(in_memory_db().await?);
+        db.record_failure(&PageFailure {
+            page_url: "https://example.com/a".to_string(),
+            error: "singlefile failed".to_string(),
+            transient: false,
+            diagnostics: Some(RenderDiagnostics {
+                command: "single-file https://example.com/a a.html".to_string(),
+                exit_code: Some(1),
+                stderr: "net::ERR_CERT_DATE_INVALID".to_string(),
+                duration: Duration::from_millis(2500),
+            }),
+            failed_at: datetime!(2024-01-02 10:00:00),
+        })
+        .await?;
+        let router = router(db).layer(Extension(Caller::Internal));
+
+        let (status, json) = get(&router, "/v1/admin/failures?url=example.com").await?;
+        assert_eq!(status, StatusCode::OK);
+        let failure = &json["failures"][0];
+        assert_eq!(failure["page_url"], "https://example.com/a");
+        assert_eq!(failure["transient"], false);
+        assert_eq!(failure["diagnostics"]["exit_code"], 1);
+        assert_eq!(failure["diagnostics"]["duration_ms"], 2500);
+        assert_eq!(
+            failure["diagnostics"]["stderr"],
+            "net::ERR_CERT_DATE_INVALID"
+        );
+
+        let (_, json) = get(&router, "/v1/admin/failures?url=other.com").await?;
+        assert_eq!(json["failures"], serde_json::json!([]));
+        let (status, _) = get(&router, "/v1/admin/failures?limit=many").await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+}
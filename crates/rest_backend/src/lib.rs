@@ -1,66 +1,204 @@
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::anyhow;
-use axum::extract::State;
-use axum::routing::post;
-use axum::{Json, Router};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::middleware::from_fn_with_state;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, post};
+use axum::{Extension, Json, Router};
+use nanoid::nanoid;
+use serde_json::json;
 use tokio::net::TcpListener;
 
-use api::{PagePersistent, PageUploader, PageWorker};
+use api::i18n::Language;
+use api::{
+    ApiKeyPersistent, ChatSettingsPersistent, FailureLog, Job, JobPersistent, JobState, PageFormat,
+    PagePersistent, PageUploader, PageWorker, ReportPersistent, StatusReporter,
+};
+use botbackend::layers::PageMetrics;
+use botbackend::url_policy::UrlPolicy;
+use utils::canonical_url::canonicalize;
 
+use crate::api_key_handler::{check_limits, tenant_bot_id};
+use crate::auth::{verify_signature, AuthState, Caller};
 use crate::error::AppError;
+use crate::load_page_handler::current_time;
 use crate::queue_load_page_handler::QueuePageHandler;
+pub use crate::queue_load_page_handler::{QueueConfig, RetryPolicy};
+use crate::rate_limiter::RateLimiter;
+pub use crate::worker_pool::RenderWorkerPool;
 
+mod api_key_handler;
+mod auth;
 mod error;
+mod failure_handler;
 mod load_page_handler;
+mod metrics_handler;
 mod queue_load_page_handler;
+mod rate_limiter;
+mod report_handler;
+mod settings_handler;
+mod worker_handler;
+mod worker_pool;
+
+/// Where the backend listens and the secret the clients sign requests with.
+pub struct ServerConfig {
+    pub port: u16,
+    pub api_secret: String,
+}
+
+/// All persistent stores of the backend, backed by the same database.
+pub struct Storage {
+    pub pages: Arc<dyn PagePersistent>,
+    pub jobs: Arc<dyn JobPersistent>,
+    pub api_keys: Arc<dyn ApiKeyPersistent>,
+    pub failures: Arc<dyn FailureLog>,
+    pub reports: Arc<dyn ReportPersistent>,
+    pub settings: Arc<dyn ChatSettingsPersistent>,
+}
+
+impl<T> From<T> for Storage
+where
+    T: PagePersistent
+        + JobPersistent
+        + ApiKeyPersistent
+        + FailureLog
+        + ReportPersistent
+        + ChatSettingsPersistent
+        + 'static,
+{
+    fn from(persistent: T) -> Self {
+        let persistent = Arc::new(persistent);
+        Storage {
+            pages: persistent.clone(),
+            jobs: persistent.clone(),
+            api_keys: persistent.clone(),
+            failures: persistent.clone(),
+            reports: persistent.clone(),
+            settings: persistent,
+        }
+    }
+}
 
 pub struct RestBackend {
     port: u16,
+    api_secret: String,
+    page_loader: Arc<QueuePageHandler>,
+    api_keys: Arc<dyn ApiKeyPersistent>,
+    failures: Arc<dyn FailureLog>,
+    reports: Arc<dyn ReportPersistent>,
+    settings: Arc<dyn ChatSettingsPersistent>,
+    render_workers: Option<RenderWorkerPool>,
+    page_metrics: Option<Arc<PageMetrics>>,
+    url_policy: UrlPolicy,
+}
+
+#[derive(Clone)]
+struct AppState {
     page_loader: Arc<QueuePageHandler>,
+    api_keys: Arc<dyn ApiKeyPersistent>,
+    rate_limiter: Arc<RateLimiter>,
+    url_policy: UrlPolicy,
 }
 
 impl RestBackend {
     pub fn new(
-        port: u16,
+        server_config: ServerConfig,
         page_loader: impl PageWorker + 'static,
         page_uploader: impl PageUploader + 'static,
-        page_persistent: Arc<dyn PagePersistent + 'static>,
+        status_reporter: impl StatusReporter + 'static,
+        storage: Storage,
+        queue_config: QueueConfig,
     ) -> Self {
         let handler = QueuePageHandler::new(
             Box::new(page_loader),
             Box::new(page_uploader),
-            page_persistent,
+            Box::new(status_reporter),
+            storage.pages,
+            storage.jobs,
+            queue_config,
         );
         RestBackend {
-            port,
+            port: server_config.port,
+            api_secret: server_config.api_secret,
             page_loader: Arc::new(handler),
+            api_keys: storage.api_keys,
+            failures: storage.failures,
+            reports: storage.reports,
+            settings: storage.settings,
+            render_workers: None,
+            page_metrics: None,
+            url_policy: UrlPolicy::default(),
         }
     }
+
+    /// Exposes the routes render workers use to take pages from the pool.
+    pub fn with_render_workers(mut self, pool: RenderWorkerPool) -> Self {
+        self.render_workers = Some(pool);
+        self
+    }
+
+    /// Exposes the counters of the metrics layer of the page worker.
+    pub fn with_page_metrics(mut self, metrics: Arc<PageMetrics>) -> Self {
+        self.page_metrics = Some(metrics);
+        self
+    }
+
+    /// Urls rejected by the policy are not queued.
+    pub fn with_url_policy(mut self, url_policy: UrlPolicy) -> Self {
+        self.url_policy = url_policy;
+        self
+    }
 }
 
 pub async fn init(backend_config: RestBackend) -> anyhow::Result<()> {
-    let router = Router::new()
+    tokio::spawn(backend_config.page_loader.clone().run());
+    let state = AppState {
+        page_loader: backend_config.page_loader,
+        api_keys: backend_config.api_keys.clone(),
+        rate_limiter: Arc::new(RateLimiter::new(Duration::from_secs(60))),
+        url_policy: backend_config.url_policy,
+    };
+    let mut router = Router::new()
         .route("/v1/requestPageForUser", post(load_page))
-        .with_state(backend_config.page_loader);
+        .route("/v1/jobs/{id}", delete(cancel_job).get(show_job))
+        .with_state(state)
+        .merge(api_key_handler::router(backend_config.api_keys.clone()))
+        .merge(failure_handler::router(backend_config.failures))
+        .merge(report_handler::router(backend_config.reports))
+        .merge(settings_handler::router(backend_config.settings));
+    if let Some(pool) = backend_config.render_workers {
+        router = router.merge(worker_handler::router(pool));
+    }
+    if let Some(metrics) = backend_config.page_metrics {
+        router = router.merge(metrics_handler::router(metrics));
+    }
+    let auth_state = AuthState {
+        api_secret: Arc::new(backend_config.api_secret),
+        api_keys: backend_config.api_keys,
+    };
+    let router = router.layer(from_fn_with_state(auth_state, verify_signature));
     let listener = create_listener(backend_config.port).await?;
     axum::serve(listener, router).await?;
-    return Ok(());
+    Ok(())
 }
 
 async fn create_listener(port: u16) -> anyhow::Result<TcpListener> {
     let ipv4addr = Ipv4Addr::new(0, 0, 0, 0);
     let socket_addr = SocketAddr::new(IpAddr::V4(ipv4addr), port);
-    return TcpListener::bind(socket_addr)
+    TcpListener::bind(socket_addr)
         .await
-        .map_err(|err| anyhow!("Can't bind on the given port: {}, error: {}", port, err));
+        .map_err(|err| anyhow!("Can't bind on the given port: {}, error: {}", port, err))
 }
 
 async fn load_page(
-    State(page_loader): State<Arc<QueuePageHandler>>,
+    State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
     Json(payload): Json<serde_json::Value>,
-) -> Result<(), AppError> {
+) -> Result<Json<serde_json::Value>, AppError> {
     println!("Load page request for {}", payload);
     let user_id = payload["user_id"]
         .as_str()
@@ -68,12 +206,111 @@ async fn load_page(
         .to_owned();
     let page_url = payload["page_url"]
         .as_str()
-        .ok_or(AppError::BadRequest("User id is not set".to_string()))?
+        .ok_or(AppError::BadRequest("Page url is not set".to_string()))?
         .to_owned();
+    let status_message_id = payload["status_message_id"]
+        .as_i64()
+        .map(|message_id| message_id as i32);
+    let reply_to_message_id = payload["reply_to_message_id"]
+        .as_i64()
+        .map(|message_id| message_id as i32);
+    let language = Language::from_code(payload["language"].as_str());
+    let format = match payload["format"].as_str() {
+        None => PageFormat::Html,
+        Some(format) => PageFormat::parse(format)
+            .map_err(|_| AppError::BadRequest(format!("Unknown format {}", format)))?,
+    };
+    state
+        .url_policy
+        .check(&page_url)
+        .await
+        .map_err(|err| AppError::Page(err.into(), language))?;
+    // the cached document is not sent, e.g. when the user asked to refresh the page
+    let fresh = payload["fresh"].as_bool().unwrap_or(false);
+    let requested_bot = payload["bot_id"].as_str().map(|bot_id| bot_id.to_owned());
+    let (api_key_id, bot_id) = match &caller {
+        Caller::Internal => {
+            if let Some(bot_id) = &requested_bot {
+                check_bot_known(&state.api_keys, bot_id).await?;
+            }
+            (None, requested_bot)
+        }
+        Caller::Tenant(api_key) => {
+            let bot_id = tenant_bot_id(api_key, requested_bot.as_deref())?;
+            check_limits(&state.api_keys, &state.rate_limiter, api_key, format).await?;
+            (Some(api_key.id.clone()), Some(bot_id))
+        }
+    };
+    let job = Job {
+        id: nanoid!(),
+        page_url: canonicalize(&page_url),
+        chat_id: user_id,
+        status_message_id,
+        state: JobState::Queued,
+        attempts: 0,
+        next_attempt_at: current_time(),
+        last_error: None,
+        created_at: current_time(),
+        api_key_id,
+        format,
+        bot_id,
+        original_url: page_url,
+        fresh,
+        reply_to_message_id,
+        language,
+    };
+    state.page_loader.submit_job(&job).await?;
+
+    Ok(Json(json!({ "job_id": job.id })))
+}
 
-    tokio::spawn(async move {
-        let _ = page_loader.load_page_for_user(page_url, user_id).await;
+async fn check_bot_known(
+    api_keys: &Arc<dyn ApiKeyPersistent>,
+    bot_id: &str,
+) -> Result<(), AppError> {
+    match api_keys.get_bot_token(bot_id).await? {
+        Some(_) => Ok(()),
+        None => Err(AppError::BadRequest(format!("Unknown bot {}", bot_id))),
+    }
+}
+
+/// The job of a sent page, its buttons refer to the job
+async fn show_job(
+    State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
+    Path(job_id): Path<String>,
+) -> Result<Response, AppError> {
+    let job = state.page_loader.get_job(&job_id).await?;
+    // clients with API keys can see only their own jobs
+    let job = job.filter(|job| match &caller {
+        Caller::Tenant(api_key) => job.api_key_id.as_ref() == Some(&api_key.id),
+        Caller::Internal => true,
     });
+    match job {
+        Some(job) => Ok(Json(json!({
+            "page_url": job.original_url,
+            "state": job.state.as_str(),
+        }))
+        .into_response()),
+        None => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
 
-    return Ok(());
+async fn cancel_job(
+    State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
+    Path(job_id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    // clients with API keys can cancel only their own jobs
+    if let Caller::Tenant(api_key) = caller {
+        let job = state.page_loader.get_job(&job_id).await?;
+        if job.and_then(|job| job.api_key_id) != Some(api_key.id) {
+            return Ok(StatusCode::NOT_FOUND);
+        }
+    }
+    if state.page_loader.cancel_job(&job_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
 }
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::middleware::from_fn;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::json;
+
+use botbackend::layers::PageMetrics;
+
+use crate::auth::require_internal;
+
+/// Counters of the metrics layer, available with the shared secret only.
+pub(crate) fn router(metrics: Arc<PageMetrics>) -> Router {
+    Router::new()
+        .route("/v1/admin/metrics", get(show_metrics))
+        .route_layer(from_fn(require_internal))
+        .with_state(metrics)
+}
+
+async fn show_metrics(State(metrics): State<Arc<PageMetrics>>) -> Json<serde_json::Value> {
+    Json(json!({
+        "loaded": metrics.loaded(),
+        "failed": metrics.failed(),
+        "average_load_time_ms": metrics.average_load_time().as_millis() as u64,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use axum::Extension;
+    use tower::ServiceExt;
+
+    use api::{LoadedPage, PageData, PageError, PageResult, PageWorker, PageWorkerBuilder};
+    use botbackend::layers::{MetricsLayer, PageMetrics};
+
+    use crate::auth::Caller;
+    use crate::metrics_handler::router;
+
+    struct FixedPageWorker;
+
+    #[async_trait]
+    impl PageWorker for FixedPageWorker {
+        async fn submit_page_generation(
+            &self,
+            page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            match page_data.url.as_str() {
+                "https://example.com/" => {
+                    Ok(LoadedPage::from(PageResult::TelegramId("id".to_string())))
+                }
+                _ => Err(PageError::fetch("Page is not available")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shows_metrics_of_the_layer() -> anyhow::Result<()> {
+        let metrics = Arc::new(PageMetrics::default());
+        let worker = PageWorkerBuilder::new()
+            .layer(MetricsLayer::new(metrics.clone()))
+            .service(FixedPageWorker);
+        for url in [
+            "https://example.com/",
+            "https://example.com/",
+            "https://a.com/",
+        ] {
+            let _ = worker
+                .submit_page_generation(PageData::from_url(url.to_string()))
+                .await;
+        }
+
+        let request = Request::builder()
+            .uri("/v1/admin/metrics")
+            .body(Body::empty())?;
+        let response = router(metrics.clone())
+            .layer(Extension(Caller::Internal))
+            .oneshot(request)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await?;
+        let json: serde_json::Value = serde_json::from_slice(&bytes)?;
+        assert_eq!(json["loaded"], 2);
+        assert_eq!(json["failed"], 1);
+        assert!(json["average_load_time_ms"].is_u64());
+        Ok(())
+    }
+}
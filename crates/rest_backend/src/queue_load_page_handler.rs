@@ -1,117 +1,805 @@
-use std::collections::hash_map::Entry;
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use api::{PageData, PagePersistent, PageResult, PageUploader, PageWorker};
+use nanoid::nanoid;
+use tokio::sync::Notify;
 
-use crate::load_page_handler::{clear_data, save_to_cache};
+use api::{
+    ClaimedPage, Job, JobPersistent, JobState, JobStatus, LoadedPage, PageData, PageError,
+    PageFormat, PagePersistent, PageResult, PageUploader, PageWorker, StatusReporter,
+};
+use botbackend::layers::backoff_delay;
+use proto::document::{clear_data, convert_page, save_to_cache};
 
-type ChatQueue = Arc<Mutex<HashMap<String, VecDeque<String>>>>;
+use crate::load_page_handler::current_time;
+
+/// How many times a page is loaded before the job fails and how long to wait between attempts.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(10),
+            max_delay: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Jittered exponential delay before the next attempt, `attempts` is the number of failed
+    /// attempts. Instances sharing the queue don't retry the pages that failed together at once.
+    fn delay(&self, attempts: u32) -> Duration {
+        backoff_delay(self.base_delay, self.max_delay, attempts)
+    }
+}
+
+/// Settings of the job queue shared by all backend instances.
+#[derive(Clone, Debug)]
+pub struct QueueConfig {
+    pub retry_policy: RetryPolicy,
+    /// How long a claimed page belongs to the instance, the lease is renewed while it loads
+    pub lease_duration: Duration,
+    /// How often the queue is checked for jobs submitted to other instances and due retries
+    pub poll_interval: Duration,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig {
+            retry_policy: RetryPolicy::default(),
+            lease_duration: Duration::from_secs(60),
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+}
 
 pub struct QueuePageHandler {
     page_loader: Box<dyn PageWorker>,
     page_uploader: Box<dyn PageUploader>,
+    status_reporter: Box<dyn StatusReporter>,
     cache: Arc<dyn PagePersistent>,
-    queue: ChatQueue,
+    jobs: Arc<dyn JobPersistent>,
+    config: QueueConfig,
+    /// Owner of the jobs claimed by this instance
+    instance_id: String,
+    /// Cancel signals of the pages loading by this instance
+    loading: Mutex<HashMap<String, Arc<Notify>>>,
+    new_jobs: Notify,
 }
 
 impl QueuePageHandler {
     pub(crate) fn new(
         loader: Box<dyn PageWorker>,
         page_uploader: Box<dyn PageUploader>,
+        status_reporter: Box<dyn StatusReporter>,
         cache: Arc<dyn PagePersistent + 'static>,
+        jobs: Arc<dyn JobPersistent + 'static>,
+        config: QueueConfig,
     ) -> Self {
         QueuePageHandler {
             page_loader: loader,
             page_uploader,
+            status_reporter,
             cache,
-            queue: Arc::new(Mutex::new(HashMap::new())),
+            jobs,
+            config,
+            instance_id: nanoid!(),
+            loading: Mutex::new(HashMap::new()),
+            new_jobs: Notify::new(),
         }
     }
 
-    pub(crate) async fn load_page_for_user(
-        &self,
-        page_url: String,
-        chat_id: String,
-    ) -> anyhow::Result<()> {
-        println!("Load page for {}, user id: {}", page_url, chat_id);
-        let already_in_progress = add_to_queue(&page_url, &chat_id, self.queue.clone());
+    /// Persists a new job, it's loaded by the first instance that claims the page.
+    pub(crate) async fn submit_job(&self, job: &Job) -> anyhow::Result<()> {
+        self.jobs.save_job(job).await?;
+        self.new_jobs.notify_one();
+        Ok(())
+    }
+
+    /// Claims pages from the queue and loads them until the process stops.
+    /// Jobs left unfinished by a restart or a dead instance are claimed once their lease expires.
+    pub(crate) async fn run(self: Arc<Self>) {
+        println!("Queue worker {} started", self.instance_id);
+        loop {
+            match self.claim_page().await {
+                Ok(Some(claimed)) => {
+                    let handler = self.clone();
+                    tokio::spawn(async move { handler.load_claimed_page(claimed).await });
+                    continue;
+                }
+                Ok(None) => {}
+                Err(err) => println!("Can't claim a page: {:#}", err),
+            }
+            tokio::select! {
+                _ = self.new_jobs.notified() => {}
+                _ = tokio::time::sleep(self.config.poll_interval) => {}
+            }
+        }
+    }
 
+    async fn claim_page(&self) -> anyhow::Result<Option<ClaimedPage>> {
+        self.jobs
+            .claim_page(&self.instance_id, current_time(), self.lease_until())
+            .await
+    }
+
+    pub(crate) async fn load_claimed_page(&self, claimed: ClaimedPage) {
+        let page_url = claimed.page_url;
         println!(
-            "Loading for page {} already in progress: {}",
-            page_url, already_in_progress
+            "Load page {} for jobs {:?}",
+            page_url,
+            claimed.jobs.iter().map(|job| &job.id).collect::<Vec<_>>()
         );
-        if already_in_progress {
-            return Ok(());
+        let cancel = Arc::new(Notify::new());
+        self.loading
+            .lock()
+            .unwrap()
+            .insert(page_url.clone(), cancel.clone());
+        for job in &claimed.jobs {
+            self.report(job, &JobStatus::Loading).await;
         }
+        // formats other than html are made from the loaded file, so the cached document won't do
+        let format = claimed
+            .jobs
+            .iter()
+            .map(|job| job.format)
+            .find(|format| *format != PageFormat::Html)
+            .unwrap_or(PageFormat::Html);
+        // file ids are valid only for the bot that uploaded them, jobs of other bots are
+        // queued again when the cached document comes back
+        let bot_id = claimed.jobs.first().and_then(|job| job.bot_id.clone());
+        // the canonical url is only the key, the page is rendered from the link as it was sent
+        let render_url = claimed
+            .jobs
+            .first()
+            .map(|job| job.original_url.clone())
+            .unwrap_or_else(|| page_url.clone());
+        let page_data = PageData {
+            format,
+            bot_id: bot_id.clone(),
+            fresh: claimed.jobs.iter().any(|job| job.fresh),
+            ..PageData::from_url(render_url)
+        };
 
-        let result = self
-            .page_loader
-            .submit_page_generation(PageData::from_url(page_url.clone()))
-            .await?;
+        let result = tokio::select! {
+            result = self.page_loader.submit_page_generation(page_data) => Some(result),
+            _ = cancel.notified() => None,
+            _ = self.keep_lease(&page_url) => None,
+        };
+        self.loading.lock().unwrap().remove(&page_url);
+        let Some(result) = result else {
+            println!("Loading for page {} cancelled", page_url);
+            return;
+        };
 
-        let file_id = self.page_uploader.send_page(&chat_id, &result).await?;
-        if let Some(file_id) = &file_id {
-            println!("Saving file id {} to cache", file_id);
-            save_to_cache(file_id, &result, &self.cache, page_url.clone()).await;
+        let jobs = match self.jobs.take_page_jobs(&self.instance_id, &page_url).await {
+            Ok(jobs) => jobs,
+            Err(err) => {
+                // the lease expires and the page is loaded again by someone
+                println!("Can't take jobs of {}: {:#}", page_url, err);
+                return;
+            }
+        };
+        match result {
+            Ok(page) => {
+                self.send_result(&page_url, bot_id.as_deref(), &page, jobs)
+                    .await;
+                clear_data(page.result).await;
+            }
+            Err(err) => self.handle_failure(jobs, err).await,
         }
+    }
 
-        let chat_id_queue = get_chat_ids(&page_url, self.queue.clone());
+    pub(crate) async fn get_job(&self, job_id: &str) -> anyhow::Result<Option<Job>> {
+        self.jobs.get_job(job_id).await
+    }
 
-        let tg_result = prepare_result(file_id, &result);
-        send_result(tg_result, &chat_id, chat_id_queue, &self.page_uploader).await;
+    /// Marks the job as cancelled. The page loading is stopped when no other chat waits
+    /// for the same page. Returns false if the job is not found.
+    pub(crate) async fn cancel_job(&self, job_id: &str) -> anyhow::Result<bool> {
+        let Some(job) = self.jobs.cancel_job(job_id).await? else {
+            return Ok(false);
+        };
+        println!("Job {} cancelled", job_id);
+        self.report(&job, &JobStatus::Cancelled).await;
 
-        clear_data(result).await;
-        Ok(())
+        // the pages loading by other instances are stopped on the next lease renewal
+        let cancel = self.loading.lock().unwrap().get(&job.page_url).cloned();
+        if let Some(cancel) = cancel {
+            if self.jobs.count_active_jobs(&job.page_url).await? == 0 {
+                cancel.notify_one();
+            }
+        }
+        Ok(true)
     }
-}
 
-fn add_to_queue(page_url: &str, chat_id: &str, queue: ChatQueue) -> bool {
-    // todo if an error happens what should be done?
-    let mut queue_lock = queue.lock().unwrap();
+    /// Renews the lease of the page while it's loading.
+    /// Returns once nobody waits for the page anymore.
+    async fn keep_lease(&self, page_url: &str) {
+        loop {
+            tokio::time::sleep(self.config.lease_duration / 3).await;
+            match self
+                .jobs
+                .renew_lease(&self.instance_id, page_url, self.lease_until())
+                .await
+            {
+                Ok(0) => return,
+                Ok(_) => {}
+                Err(err) => println!("Can't renew lease for {}: {:#}", page_url, err),
+            }
+        }
+    }
+
+    fn lease_until(&self) -> time::PrimitiveDateTime {
+        current_time() + self.config.lease_duration
+    }
 
-    let entry = queue_lock.entry(page_url.to_string());
-    match entry {
-        Entry::Occupied(mut queue) => {
-            queue.get_mut().push_back(chat_id.to_string());
-            true
+    /// Schedules the next attempt for the jobs or fails them once all attempts are used.
+    /// Pages that failed for a reason another attempt won't fix are not retried.
+    async fn handle_failure(&self, jobs: Vec<Job>, err: PageError) {
+        let retry_policy = &self.config.retry_policy;
+        let attempts = jobs.iter().map(|job| job.attempts).max().unwrap_or(0) + 1;
+        let give_up = attempts >= retry_policy.max_attempts || !err.is_transient();
+        let delay = retry_policy.delay(attempts);
+        if !err.is_transient() {
+            println!("Loading failed permanently: {}", err);
+        } else if give_up {
+            println!("Loading failed after {} attempts: {}", attempts, err);
+        } else {
+            println!(
+                "Attempt {} to load a page failed, retry in {:?}: {}",
+                attempts, delay, err
+            );
         }
-        Entry::Vacant(queue) => {
-            let mut deque: VecDeque<String> = VecDeque::new();
-            deque.push_back(chat_id.to_string());
-            queue.insert(deque);
-            false
+
+        for mut job in jobs {
+            job.attempts = attempts;
+            job.last_error = Some(err.to_string());
+            if give_up {
+                job.state = JobState::Failed;
+                self.report(&job, &JobStatus::Failed(err.user_message(job.language)))
+                    .await;
+            } else {
+                job.state = JobState::Queued;
+                job.next_attempt_at = current_time() + delay;
+            }
+            self.update_job(&job).await;
         }
     }
-}
 
-fn get_chat_ids(page_url: &str, queue: ChatQueue) -> VecDeque<String> {
-    queue
-        .lock()
-        .unwrap()
-        .remove(page_url)
-        .unwrap_or_else(|| VecDeque::new())
-}
+    async fn send_result(
+        &self,
+        page_url: &str,
+        bot_id: Option<&str>,
+        page: &LoadedPage,
+        jobs: Vec<Job>,
+    ) {
+        println!("Sending result to the queue, size: {}", jobs.len());
+        let result = &page.result;
+        let (jobs, requeued): (Vec<Job>, Vec<Job>) = jobs.into_iter().partition(|job| {
+            matches!(result, PageResult::FilePath(_))
+                || (job.format == PageFormat::Html && job.bot_id.as_deref() == bot_id)
+        });
+        // jobs that joined the queue while the cached document was loading need the file
+        // or were requested for another bot
+        for mut job in requeued {
+            job.state = JobState::Queued;
+            self.update_job(&job).await;
+            self.new_jobs.notify_one();
+        }
 
-fn prepare_result(file_id: Option<String>, result: &PageResult) -> PageResult {
-    return file_id.map_or_else(|| result.clone(), |id| PageResult::TelegramId(id));
+        // once the page is uploaded the telegram file id is reused for the rest of the queue
+        // and the bot that uploaded it
+        let mut uploads: HashMap<(Option<String>, PageFormat), PageResult> = HashMap::new();
+        for mut job in jobs {
+            self.report(&job, &JobStatus::Uploading).await;
+            let upload_key = (job.bot_id.clone(), job.format);
+            let upload_result = match uploads.get(&upload_key) {
+                Some(upload_result) => Ok(upload_result.clone()),
+                None => convert_page(result, job.format)
+                    .await
+                    .map_err(|err| PageError::fetch(format!("{:#}", err))),
+            };
+            let sent = match upload_result {
+                Ok(upload_result) => {
+                    uploads.insert(upload_key.clone(), upload_result.clone());
+                    self.page_uploader
+                        .send_page(
+                            &job.recipient(),
+                            &job.id,
+                            &job.original_url,
+                            &upload_result,
+                            &page.metadata,
+                        )
+                        .await
+                }
+                Err(err) => Err(err),
+            };
+            let file_id = match sent {
+                Ok(file_id) => {
+                    job.state = JobState::Done;
+                    self.report(&job, &JobStatus::Done).await;
+                    file_id
+                }
+                Err(err) => {
+                    job.state = JobState::Failed;
+                    job.last_error = Some(err.to_string());
+                    self.report(&job, &JobStatus::Failed(err.user_message(job.language)))
+                        .await;
+                    None
+                }
+            };
+            self.update_job(&job).await;
+            let Some(file_id) = file_id else {
+                continue;
+            };
+            if let Some(PageResult::FilePath(_)) = uploads.get(&upload_key) {
+                if job.format == PageFormat::Html {
+                    println!("Saving file id {} to cache", file_id);
+                    save_to_cache(
+                        &file_id,
+                        page,
+                        &self.cache,
+                        page_url.to_string(),
+                        job.bot_id.clone(),
+                    )
+                    .await;
+                }
+                if let Some(converted) = uploads.insert(upload_key, PageResult::TelegramId(file_id))
+                {
+                    if converted != *result {
+                        clear_data(converted).await;
+                    }
+                }
+            }
+        }
+        for upload_result in uploads.into_values() {
+            if upload_result != *result {
+                clear_data(upload_result).await;
+            }
+        }
+    }
+
+    async fn update_job(&self, job: &Job) {
+        if let Err(err) = self.jobs.update_job(job).await {
+            println!("Can't update job {}: {}", job.id, err);
+        }
+    }
+
+    async fn report(&self, job: &Job, status: &JobStatus) {
+        if let Some(message) = job.status_message() {
+            if let Err(err) = self.status_reporter.report_status(&message, status).await {
+                println!(
+                    "Can't report status {:?} to {}: {}",
+                    status, message.chat_id, err
+                );
+            }
+        }
+    }
 }
 
-async fn send_result(
-    tg_result: PageResult,
-    chat_id: &str,
-    chat_id_queue: VecDeque<String>,
-    page_uploader: &Box<dyn PageUploader>,
-) {
-    println!(
-        "Sending result to the rest of the queue, size: {}",
-        chat_id_queue.len()
-    );
-    for chat_id_from_queue in chat_id_queue {
-        if chat_id_from_queue != chat_id {
-            let _ = page_uploader
-                .send_page(&chat_id_from_queue, &tg_result)
-                .await;
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use anyhow::bail;
+    use async_trait::async_trait;
+    use tempfile::{tempdir, TempDir};
+
+    use api::i18n::Language;
+    use api::{
+        Job, JobPersistent, JobState, JobStatus, LoadedPage, PageData, PageError, PageFormat,
+        PageInfo, PageMetadata, PagePersistent, PageResult, PageUploader, PageWorker, Recipient,
+        StatusMessage, StatusReporter,
+    };
+    use sqlite::sqlite_persistent::{init_db, SqlitePagePersistent};
+
+    use crate::load_page_handler::current_time;
+    use crate::queue_load_page_handler::{QueueConfig, QueuePageHandler, RetryPolicy};
+
+    type Reports = Arc<Mutex<Vec<(i32, JobStatus)>>>;
+    type Uploads = Arc<Mutex<Vec<PageResult>>>;
+
+    #[tokio::test]
+    async fn test_reports_stages_for_delivered_page() -> anyhow::Result<()> {
+        let test = TestQueue::new(Box::new(FailingPageWorker::new(0))).await?;
+
+        test.handler.submit_job(&job(1)).await?;
+        test.load_next_page().await?;
+
+        assert_eq!(
+            test.reports(),
+            vec![
+                (1, JobStatus::Loading),
+                (1, JobStatus::Uploading),
+                (1, JobStatus::Done)
+            ]
+        );
+        assert_eq!(test.job_state("job_1").await?, Some((JobState::Done, 0)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delivers_page_once_to_all_chats() -> anyhow::Result<()> {
+        let test = TestQueue::new(Box::new(FailingPageWorker::new(0))).await?;
+
+        test.handler.submit_job(&job(1)).await?;
+        test.handler.submit_job(&job(2)).await?;
+        test.load_next_page().await?;
+
+        assert!(test.handler.claim_page().await?.is_none());
+        assert_eq!(
+            test.reports()
+                .into_iter()
+                .filter(|(_, status)| *status == JobStatus::Done)
+                .count(),
+            2
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retries_failed_loading() -> anyhow::Result<()> {
+        let test = TestQueue::new(Box::new(FailingPageWorker::new(2))).await?;
+
+        test.handler.submit_job(&job(1)).await?;
+        test.load_next_page().await?;
+        assert_eq!(test.job_state("job_1").await?, Some((JobState::Queued, 1)));
+        test.load_next_page().await?;
+        assert_eq!(test.job_state("job_1").await?, Some((JobState::Queued, 2)));
+        test.load_next_page().await?;
+
+        assert_eq!(test.job_state("job_1").await?, Some((JobState::Done, 2)));
+        assert!(test.reports().contains(&(1, JobStatus::Done)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reports_failure_after_all_attempts() -> anyhow::Result<()> {
+        let test = TestQueue::new(Box::new(FailingPageWorker::new(3))).await?;
+
+        test.handler.submit_job(&job(1)).await?;
+        for _ in 0..3 {
+            test.load_next_page().await?;
+        }
+
+        assert!(test.reports().contains(&(
+            1,
+            JobStatus::Failed("The site can't be reached".to_string())
+        )));
+        assert_eq!(test.job_state("job_1").await?, Some((JobState::Failed, 3)));
+        assert!(test.handler.claim_page().await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_permanent_failure_is_not_retried() -> anyhow::Result<()> {
+        let test = TestQueue::new(Box::new(GonePageWorker {})).await?;
+
+        test.handler.submit_job(&job(1)).await?;
+        test.load_next_page().await?;
+
+        assert!(test.reports().contains(&(
+            1,
+            JobStatus::Failed("The site can't be reached".to_string())
+        )));
+        assert_eq!(test.job_state("job_1").await?, Some((JobState::Failed, 1)));
+        assert!(test.handler.claim_page().await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancel_last_job_stops_loading() -> anyhow::Result<()> {
+        let test = TestQueue::new(Box::new(PendingPageWorker {})).await?;
+        test.handler.submit_job(&job(1)).await?;
+        let claimed = test.handler.claim_page().await?.unwrap();
+
+        let loading = tokio::spawn({
+            let handler = test.handler.clone();
+            async move { handler.load_claimed_page(claimed).await }
+        });
+        while test.handler.loading.lock().unwrap().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        assert!(!test.handler.cancel_job("unknown").await?);
+        assert!(test.handler.cancel_job("job_1").await?);
+        loading.await?;
+
+        assert!(test.handler.loading.lock().unwrap().is_empty());
+        assert!(test.reports().contains(&(1, JobStatus::Cancelled)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delivers_page_in_requested_formats() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let test = TestQueue::new(Box::new(FilePageWorker {
+            dir: dir.path().to_str().unwrap().to_string(),
+        }))
+        .await?;
+
+        test.handler.submit_job(&job(1)).await?;
+        let gzip_job = Job {
+            format: PageFormat::Gzip,
+            ..job(2)
+        };
+        test.handler.submit_job(&gzip_job).await?;
+        test.load_next_page().await?;
+
+        assert_eq!(test.job_state("job_1").await?, Some((JobState::Done, 0)));
+        assert_eq!(test.job_state("job_2").await?, Some((JobState::Done, 0)));
+        let uploads = test.uploads.lock().unwrap().clone();
+        let page_path = dir.path().join("page.html");
+        assert_eq!(
+            uploads,
+            vec![
+                PageResult::FilePath(page_path.to_str().unwrap().to_string()),
+                PageResult::FilePath(format!("{}.gz", page_path.to_str().unwrap())),
+            ]
+        );
+        assert!(!page_path.with_extension("html.gz").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_requeues_cached_page_for_other_bot() -> anyhow::Result<()> {
+        let test = TestQueue::new(Box::new(FailingPageWorker::new(0))).await?;
+
+        test.handler.submit_job(&job(1)).await?;
+        let other_bot_job = Job {
+            bot_id: Some("123".to_string()),
+            ..job(2)
+        };
+        test.handler.submit_job(&other_bot_job).await?;
+        test.load_next_page().await?;
+
+        assert_eq!(test.job_state("job_1").await?, Some((JobState::Done, 0)));
+        assert_eq!(test.job_state("job_2").await?, Some((JobState::Queued, 0)));
+
+        test.load_next_page().await?;
+        assert_eq!(test.job_state("job_2").await?, Some((JobState::Done, 0)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(10),
+            max_delay: Duration::from_secs(30),
+        };
+
+        let in_range = |delay: Duration, max: u64| {
+            delay >= Duration::from_secs(max) / 2 && delay <= Duration::from_secs(max)
+        };
+        for _ in 0..100 {
+            assert!(in_range(policy.delay(1), 10));
+            assert!(in_range(policy.delay(2), 20));
+            assert!(in_range(policy.delay(3), 30));
+            assert!(in_range(policy.delay(100), 30));
+        }
+    }
+
+    struct TestQueue {
+        handler: Arc<QueuePageHandler>,
+        db: Arc<SqlitePagePersistent>,
+        reports: Reports,
+        uploads: Uploads,
+        _dir: TempDir,
+    }
+
+    impl TestQueue {
+        async fn new(page_worker: Box<dyn PageWorker>) -> anyhow::Result<Self> {
+            let dir = tempdir()?;
+            let path = dir.path().join("jobs.db");
+            std::fs::File::create(&path)?;
+            let db = Arc::new(init_db(path.to_str().unwrap().to_string()).await?);
+            let reports = Reports::default();
+            let uploads = Uploads::default();
+            let handler = QueuePageHandler::new(
+                page_worker,
+                Box::new(TestPageUploader {
+                    uploads: uploads.clone(),
+                }),
+                Box::new(TestStatusReporter {
+                    reports: reports.clone(),
+                }),
+                Arc::new(TestPagePersistent {}),
+                db.clone(),
+                QueueConfig {
+                    retry_policy: RetryPolicy {
+                        max_attempts: 3,
+                        base_delay: Duration::ZERO,
+                        max_delay: Duration::ZERO,
+                    },
+                    ..QueueConfig::default()
+                },
+            );
+            Ok(TestQueue {
+                handler: Arc::new(handler),
+                db,
+                reports,
+                uploads,
+                _dir: dir,
+            })
+        }
+
+        async fn load_next_page(&self) -> anyhow::Result<()> {
+            let Some(claimed) = self.handler.claim_page().await? else {
+                bail!("Nothing to claim")
+            };
+            self.handler.load_claimed_page(claimed).await;
+            Ok(())
+        }
+
+        async fn job_state(&self, job_id: &str) -> anyhow::Result<Option<(JobState, u32)>> {
+            let job = self.db.get_job(job_id).await?;
+            Ok(job.map(|job| (job.state, job.attempts)))
+        }
+
+        fn reports(&self) -> Vec<(i32, JobStatus)> {
+            self.reports.lock().unwrap().clone()
+        }
+    }
+
+    fn job(message_id: i32) -> Job {
+        Job {
+            id: format!("job_{}", message_id),
+            page_url: "url".to_string(),
+            chat_id: "chat".to_string(),
+            status_message_id: Some(message_id),
+            state: JobState::Queued,
+            attempts: 0,
+            next_attempt_at: current_time(),
+            last_error: None,
+            created_at: current_time(),
+            api_key_id: None,
+            format: PageFormat::Html,
+            bot_id: None,
+            original_url: "url".to_string(),
+            fresh: false,
+            reply_to_message_id: None,
+            language: Language::En,
+        }
+    }
+
+    /// Fails the given number of times before the page is loaded
+    struct FailingPageWorker {
+        failures: AtomicU32,
+    }
+
+    impl FailingPageWorker {
+        fn new(failures: u32) -> Self {
+            FailingPageWorker {
+                failures: AtomicU32::new(failures),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl PageWorker for FailingPageWorker {
+        async fn submit_page_generation(
+            &self,
+            _page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            let failures = self.failures.load(Ordering::SeqCst);
+            if failures > 0 {
+                self.failures.store(failures - 1, Ordering::SeqCst);
+                return Err(PageError::fetch("broken page"));
+            }
+            Ok(PageResult::TelegramId("id".to_string()).into())
+        }
+    }
+
+    struct GonePageWorker {}
+
+    #[async_trait]
+    impl PageWorker for GonePageWorker {
+        async fn submit_page_generation(
+            &self,
+            _page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            Err(PageError::FetchFailed {
+                message: "404 Not Found".to_string(),
+                transient: false,
+            })
+        }
+    }
+
+    struct PendingPageWorker {}
+
+    #[async_trait]
+    impl PageWorker for PendingPageWorker {
+        async fn submit_page_generation(
+            &self,
+            _page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            std::future::pending().await
+        }
+    }
+
+    /// Writes the page to a file, only the gzip format makes sense for the uploads test
+    struct FilePageWorker {
+        dir: String,
+    }
+
+    #[async_trait]
+    impl PageWorker for FilePageWorker {
+        async fn submit_page_generation(
+            &self,
+            page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
+            assert_eq!(page_data.format, PageFormat::Gzip);
+            let path = format!("{}/page.html", self.dir);
+            tokio::fs::write(&path, "<html/>")
+                .await
+                .map_err(PageError::storage)?;
+            Ok(PageResult::FilePath(path).into())
+        }
+    }
+
+    struct TestPageUploader {
+        uploads: Uploads,
+    }
+
+    #[async_trait]
+    impl PageUploader for TestPageUploader {
+        async fn send_page(
+            &self,
+            _recipient: &Recipient,
+            _job_id: &str,
+            _page_url: &str,
+            page_result: &PageResult,
+            _metadata: &PageMetadata,
+        ) -> Result<Option<String>, PageError> {
+            self.uploads.lock().unwrap().push(page_result.clone());
+            Ok(None)
+        }
+    }
+
+    struct TestStatusReporter {
+        reports: Reports,
+    }
+
+    #[async_trait]
+    impl StatusReporter for TestStatusReporter {
+        async fn report_status(
+            &self,
+            message: &StatusMessage,
+            status: &JobStatus,
+        ) -> anyhow::Result<()> {
+            self.reports
+                .lock()
+                .unwrap()
+                .push((message.message_id, status.clone()));
+            Ok(())
+        }
+    }
+
+    struct TestPagePersistent {}
+
+    #[async_trait]
+    impl PagePersistent for TestPagePersistent {
+        async fn save(&self, _page_info: &PageInfo) -> Result<(), PageError> {
+            Ok(())
+        }
+
+        async fn get(
+            &self,
+            _page_url: &str,
+            _bot_id: Option<&str>,
+        ) -> Result<Option<PageInfo>, PageError> {
+            Ok(None)
         }
     }
 }
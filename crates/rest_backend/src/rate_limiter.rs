@@ -0,0 +1,81 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Sliding window limit of requests per key, counted by this backend instance.
+pub(crate) struct RateLimiter {
+    window: Duration,
+    requests: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(window: Duration) -> Self {
+        RateLimiter {
+            window,
+            requests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts the request, returns false if the key already made `limit` requests in the window.
+    pub(crate) fn try_acquire(&self, key: &str, limit: u32) -> bool {
+        self.try_acquire_at(key, limit, Instant::now())
+    }
+
+    fn try_acquire_at(&self, key: &str, limit: u32, now: Instant) -> bool {
+        let mut requests = self.requests.lock().unwrap();
+        let key_requests = requests.entry(key.to_string()).or_default();
+        while key_requests
+            .front()
+            .is_some_and(|time| *time + self.window <= now)
+        {
+            key_requests.pop_front();
+        }
+        if key_requests.len() >= limit as usize {
+            return false;
+        }
+        key_requests.push_back(now);
+        true
+    }
+
+    /// Gives back the last request of the key, e.g. when the request was rejected after all.
+    pub(crate) fn release(&self, key: &str) {
+        let mut requests = self.requests.lock().unwrap();
+        if let Some(key_requests) = requests.get_mut(key) {
+            key_requests.pop_back();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::rate_limiter::RateLimiter;
+
+    #[test]
+    fn test_rate_limit() {
+        let limiter = RateLimiter::new(Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(limiter.try_acquire_at("key_1", 2, now));
+        assert!(limiter.try_acquire_at("key_1", 2, now + Duration::from_secs(10)));
+        assert!(!limiter.try_acquire_at("key_1", 2, now + Duration::from_secs(20)));
+        assert!(limiter.try_acquire_at("key_2", 2, now + Duration::from_secs(20)));
+
+        assert!(limiter.try_acquire_at("key_1", 2, now + Duration::from_secs(60)));
+        assert!(!limiter.try_acquire_at("key_1", 2, now + Duration::from_secs(61)));
+        assert!(!limiter.try_acquire_at("key_3", 0, now));
+    }
+
+    #[test]
+    fn test_release() {
+        let limiter = RateLimiter::new(Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(limiter.try_acquire_at("key_1", 1, now));
+        limiter.release("key_1");
+        assert!(limiter.try_acquire_at("key_1", 1, now));
+        assert!(!limiter.try_acquire_at("key_1", 1, now));
+        limiter.release("key_2");
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::middleware::from_fn;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::json;
+
+use api::{PageReport, ReportPersistent};
+use utils::canonical_url::canonicalize;
+
+use crate::auth::require_internal;
+use crate::error::AppError;
+use crate::load_page_handler::current_time;
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 1000;
+
+/// Routes the bot reports broken pages with and operators review them, available with
+/// the shared secret only.
+pub(crate) fn router(reports: Arc<dyn ReportPersistent>) -> Router {
+    Router::new()
+        .route("/v1/reports", post(save_report))
+        .route("/v1/admin/reports", get(list_reports))
+        .route_layer(from_fn(require_internal))
+        .with_state(reports)
+}
+
+async fn save_report(
+    State(reports): State<Arc<dyn ReportPersistent>>,
+    Json(payload): Json<serde_json::Value>,
+) -> Result<StatusCode, AppError> {
+    let page_url = payload["page_url"]
+        .as_str()
+        .ok_or(AppError::BadRequest("Page url is not set".to_string()))?;
+    let chat_id = payload["chat_id"]
+        .as_str()
+        .ok_or(AppError::BadRequest("Chat id is not set".to_string()))?;
+    let report = PageReport {
+        page_url: canonicalize(page_url),
+        chat_id: chat_id.to_string(),
+        telegram_file_id: payload["file_id"].as_str().map(str::to_string),
+        bot_id: payload["bot_id"].as_str().map(str::to_string),
+        reported_at: current_time(),
+    };
+    println!("Page {} is reported as broken", report.page_url);
+    reports.save_report(&report).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_reports(
+    State(reports): State<Arc<dyn ReportPersistent>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let limit = match params.get("limit") {
+        None => DEFAULT_LIMIT,
+        Some(limit) => limit
+            .parse::<u32>()
+            .map_err(|_| AppError::BadRequest("limit must be a number".to_string()))?
+            .min(MAX_LIMIT),
+    };
+    let reports = reports
+        .list_reports(limit)
+        .await?
+        .iter()
+        .map(report_json)
+        .collect::<Vec<_>>();
+    Ok(Json(json!({ "reports": reports })))
+}
+
+fn report_json(report: &PageReport) -> serde_json::Value {
+    json!({
+        "page_url": report.page_url,
+        "chat_id": report.chat_id,
+        "file_id": report.telegram_file_id,
+        "bot_id": report.bot_id,
+        "reported_at": report.reported_at.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Method, Request, StatusCode};
+    use axum::{Extension, Router};
+    use tower::ServiceExt;
+
+    use api::ReportPersistent;
+    use sqlite::sqlite_persistent::in_memory_db;
+
+    use crate::auth::Caller;
+    use crate::report_handler::router;
+
+    async fn send(
+        router: &Router,
+        method: Method,
+        uri: &str,
+        body: serde_json::Value,
+    ) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(&body)?))?;
+        let response = router.clone().oneshot(request).await?;
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await?;
+        let json = serde_json::from_slice(&bytes).unwrap_or_default();
+        Ok((status, json))
+    }
+
+    #[tokio::test]
+    async fn test_saves_and_lists_reports() -> anyhow::Result<()> {
+        let db: Arc<dyn ReportPersistent> = Arc::new(in_memory_db().await?);
+        let router = router(db).layer(Extension(Caller::Internal));
+
+        let report = serde_json::json!({
+            "page_url": "https://Example.com/page/",
+            "chat_id": "42",
+            "file_id": "file_id",
+        });
+        let (status, _) = send(&router, Method::POST, "/v1/reports", report).await?;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(
+            &router,
+            Method::POST,
+            "/v1/reports",
+            serde_json::json!({ "chat_id": "42" }),
+        )
+        .await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, json) = send(
+            &router,
+            Method::GET,
+            "/v1/admin/reports",
+            serde_json::Value::Null,
+        )
+        .await?;
+        assert_eq!(status, StatusCode::OK);
+        let report = &json["reports"][0];
+        assert_eq!(report["page_url"], "https://example.com/page");
+        assert_eq!(report["chat_id"], "42");
+        assert_eq!(report["file_id"], "file_id");
+        assert_eq!(report["bot_id"], serde_json::Value::Null);
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::middleware::from_fn;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::json;
+
+use api::i18n::Language;
+use api::{ChatSettings, ChatSettingsPersistent, PageFormat};
+
+use crate::auth::require_internal;
+use crate::error::AppError;
+
+/// Routes the bot keeps the settings of its chats with, available with the shared secret only.
+pub(crate) fn router(settings: Arc<dyn ChatSettingsPersistent>) -> Router {
+    Router::new()
+        .route(
+            "/v1/chats/{chat_id}/settings",
+            get(get_settings).put(update_settings),
+        )
+        .route_layer(from_fn(require_internal))
+        .with_state(settings)
+}
+
+async fn get_settings(
+    State(settings): State<Arc<dyn ChatSettingsPersistent>>,
+    Path(chat_id): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let settings = settings.get_settings(&chat_id).await?;
+    Ok(Json(settings_json(&settings)))
+}
+
+/// Changes only the fields present in the payload, `"language": null` resets the language.
+async fn update_settings(
+    State(persistent): State<Arc<dyn ChatSettingsPersistent>>,
+    Path(chat_id): Path<String>,
+    Json(payload): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let mut settings = persistent.get_settings(&chat_id).await?;
+    if let Some(format) = payload.get("format") {
+        settings.format = format
+            .as_str()
+            .and_then(|format| PageFormat::parse(format).ok())
+            .ok_or(AppError::BadRequest(format!("Unknown format {}", format)))?;
+    }
+    if let Some(auto_detect) = payload.get("auto_detect") {
+        settings.auto_detect = read_bool(auto_detect, "auto_detect")?;
+    }
+    if let Some(language) = payload.get("language") {
+        settings.language = match language {
+            serde_json::Value::Null => None,
+            language => Some(
+                language
+                    .as_str()
+                    .and_then(|language| Language::parse(language).ok())
+                    .ok_or(AppError::BadRequest(format!(
+                        "Unknown language {}",
+                        language
+                    )))?,
+            ),
+        };
+    }
+    if let Some(always_fresh) = payload.get("always_fresh") {
+        settings.always_fresh = read_bool(always_fresh, "always_fresh")?;
+    }
+    persistent.save_settings(&settings).await?;
+    Ok(Json(settings_json(&settings)))
+}
+
+fn read_bool(value: &serde_json::Value, name: &str) -> Result<bool, AppError> {
+    value
+        .as_bool()
+        .ok_or(AppError::BadRequest(format!("{} must be a boolean", name)))
+}
+
+fn settings_json(settings: &ChatSettings) -> serde_json::Value {
+    json!({
+        "chat_id": settings.chat_id,
+        "format": settings.format.as_str(),
+        "auto_detect": settings.auto_detect,
+        "language": settings.language.map(|language| language.code()),
+        "always_fresh": settings.always_fresh,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Method, Request, StatusCode};
+    use axum::{Extension, Router};
+    use tower::ServiceExt;
+
+    use api::ChatSettingsPersistent;
+    use sqlite::sqlite_persistent::in_memory_db;
+
+    use crate::auth::Caller;
+    use crate::settings_handler::router;
+
+    async fn send(
+        router: &Router,
+        method: Method,
+        body: serde_json::Value,
+    ) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+        let request = Request::builder()
+            .method(method)
+            .uri("/v1/chats/42/settings")
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(&body)?))?;
+        let response = router.clone().oneshot(request).await?;
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await?;
+        let json = serde_json::from_slice(&bytes).unwrap_or_default();
+        Ok((status, json))
+    }
+
+    #[tokio::test]
+    async fn test_updates_settings() -> anyhow::Result<()> {
+        let db: Arc<dyn ChatSettingsPersistent> = Arc::new(in_memory_db().await?);
+        let router = router(db.clone()).layer(Extension(Caller::Internal));
+
+        let (status, json) = send(&router, Method::GET, serde_json::Value::Null).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["format"], "html");
+        assert_eq!(json["auto_detect"], true);
+
+        let update = serde_json::json!({ "format": "gzip", "language": "ru" });
+        let (status, json) = send(&router, Method::PUT, update).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["format"], "gzip");
+        assert_eq!(json["language"], "ru");
+        assert_eq!(json["always_fresh"], false);
+
+        let update = serde_json::json!({ "language": null, "always_fresh": true });
+        send(&router, Method::PUT, update).await?;
+        let settings = db.get_settings("42").await?;
+        assert_eq!(settings.language, None);
+        assert!(settings.always_fresh);
+        assert_eq!(settings.format.as_str(), "gzip");
+
+        let update = serde_json::json!({ "language": "xx" });
+        let (status, _) = send(&router, Method::PUT, update).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let update = serde_json::json!({ "format": "pdf" });
+        let (status, _) = send(&router, Method::PUT, update).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+use std::time::Duration;
+
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::from_fn;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{post, put};
+use axum::{Json, Router};
+use serde_json::json;
+
+use api::worker_protocol::{failure_from_json, Registration, EGRESS_HEADER};
+use api::{Egress, PageMetadata};
+
+use crate::auth::require_internal;
+use crate::error::AppError;
+use crate::worker_pool::RenderWorkerPool;
+
+/// Rendered pages are kept in memory while they are uploaded
+pub(crate) const MAX_PAGE_SIZE: usize = 512 * 1024 * 1024;
+/// How long a claim request waits for a new page before returning no content
+const CLAIM_WAIT: Duration = Duration::from_secs(20);
+
+/// Routes used by render workers to take pages from the API node.
+pub(crate) fn router(pool: RenderWorkerPool) -> Router {
+    Router::new()
+        .route("/v1/workers", post(register))
+        .route("/v1/workers/{worker_id}/heartbeat", post(heartbeat))
+        .route("/v1/workers/{worker_id}/tasks/claim", post(claim))
+        .route(
+            "/v1/workers/{worker_id}/tasks/{task_id}/result",
+            put(complete).layer(DefaultBodyLimit::max(MAX_PAGE_SIZE)),
+        )
+        .route(
+            "/v1/workers/{worker_id}/tasks/{task_id}/failure",
+            post(fail),
+        )
+        .route_layer(from_fn(require_internal))
+        .with_state(pool)
+}
+
+async fn register(State(pool): State<RenderWorkerPool>) -> Json<serde_json::Value> {
+    let registration = Registration {
+        worker_id: pool.register(),
+        lease_duration: pool.lease_duration(),
+    };
+    Json(registration.to_json())
+}
+
+async fn heartbeat(
+    State(pool): State<RenderWorkerPool>,
+    Path(worker_id): Path<String>,
+    Json(payload): Json<serde_json::Value>,
+) -> Result<Response, AppError> {
+    let running_tasks: Vec<String> = payload["tasks"]
+        .as_array()
+        .ok_or(AppError::BadRequest("Tasks are not set".to_string()))?
+        .iter()
+        .filter_map(|task| task.as_str().map(str::to_string))
+        .collect();
+    match pool.heartbeat(&worker_id, &running_tasks) {
+        Some(cancelled) => Ok(Json(json!({ "cancelled": cancelled })).into_response()),
+        None => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+async fn claim(
+    State(pool): State<RenderWorkerPool>,
+    Path(worker_id): Path<String>,
+) -> Result<Response, AppError> {
+    let Ok(task) = pool.claim_or_wait(&worker_id, CLAIM_WAIT).await else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    match task {
+        Some(task) => Ok(Json(task.to_json()).into_response()),
+        None => Ok(StatusCode::NO_CONTENT.into_response()),
+    }
+}
+
+async fn complete(
+    State(pool): State<RenderWorkerPool>,
+    Path((worker_id, task_id)): Path<(String, String)>,
+    headers: HeaderMap,
+    content: Bytes,
+) -> Result<StatusCode, AppError> {
+    let egress = match headers.get(EGRESS_HEADER) {
+        None => Egress::Direct,
+        Some(egress) => egress
+            .to_str()
+            .ok()
+            .and_then(|egress| Egress::parse(egress).ok())
+            .ok_or(AppError::BadRequest("Unknown egress".to_string()))?,
+    };
+    let metadata = PageMetadata {
+        egress,
+        ..PageMetadata::default()
+    };
+    if pool
+        .complete(&worker_id, &task_id, &content, metadata)
+        .await?
+    {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+async fn fail(
+    State(pool): State<RenderWorkerPool>,
+    Path((worker_id, task_id)): Path<(String, String)>,
+    Json(payload): Json<serde_json::Value>,
+) -> Result<StatusCode, AppError> {
+    let error =
+        failure_from_json(&payload).ok_or(AppError::BadRequest("Error is not set".to_string()))?;
+    if pool.fail(&worker_id, &task_id, error) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use axum::{Extension, Router};
+    use tower::ServiceExt;
+
+    use api::worker_protocol::EGRESS_HEADER;
+    use api::{Egress, PageData, PageResult, PageWorker, RenderDiagnostics};
+
+    use crate::auth::Caller;
+    use crate::worker_handler::router;
+    use crate::worker_pool::RenderWorkerPool;
+
+    async fn send(
+        router: &Router,
+        method: &str,
+        uri: &str,
+        body: Body,
+    ) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(body)?;
+        let response = router.clone().oneshot(request).await?;
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await?;
+        let json = serde_json::from_slice(&bytes).unwrap_or_default();
+        Ok((status, json))
+    }
+
+    #[tokio::test]
+    async fn test_render_page_over_http() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let pool = RenderWorkerPool::new(
+            dir.path().to_str().unwrap().to_string(),
+            Duration::from_secs(60),
+        );
+        let router = router(pool.clone()).layer(Extension(Caller::Internal));
+
+        let (status, json) = send(&router, "POST", "/v1/workers", Body::empty()).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["lease_seconds"], 60);
+        let worker_id = json["worker_id"].as_str().unwrap().to_string();
+
+        let submit = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                pool.submit_page_generation(PageData::from_url("https://example.com".to_string()))
+                    .await
+            }
+        });
+        let claim_uri = format!("/v1/workers/{}/tasks/claim", worker_id);
+        let (status, json) = send(&router, "POST", &claim_uri, Body::empty()).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["page_url"], "https://example.com");
+        let task_id = json["task_id"].as_str().unwrap().to_string();
+
+        let heartbeat_uri = format!("/v1/workers/{}/heartbeat", worker_id);
+        let body = Body::from(format!(r#"{{"tasks": ["{}"]}}"#, task_id));
+        let (status, json) = send(&router, "POST", &heartbeat_uri, body).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["cancelled"], serde_json::json!([]));
+
+        let result_uri = format!("/v1/workers/{}/tasks/{}/result", worker_id, task_id);
+        let request = Request::builder()
+            .method("PUT")
+            .uri(&result_uri)
+            .header(EGRESS_HEADER, "tor")
+            .body(Body::from("<html/>"))?;
+        let response = router.clone().oneshot(request).await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let page = submit.await??;
+        assert_eq!(page.metadata.egress, Egress::Tor);
+        let PageResult::FilePath(path) = page.result else {
+            panic!("File path is expected");
+        };
+        assert_eq!(tokio::fs::read_to_string(path).await?, "<html/>");
+
+        let (status, _) = send(&router, "PUT", &result_uri, Body::from("<html/>")).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_worker() -> anyhow::Result<()> {
+        let router = router(RenderWorkerPool::new(
+            String::new(),
+            Duration::from_secs(60),
+        ))
+        .layer(Extension(Caller::Internal));
+        let (status, _) = send(
+            &router,
+            "POST",
+            "/v1/workers/unknown/tasks/claim",
+            Body::empty(),
+        )
+        .await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let body = Body::from(r#"{"tasks": []}"#);
+        let (status, _) = send(&router, "POST", "/v1/workers/unknown/heartbeat", body).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failure_keeps_diagnostics() -> anyhow::Result<()> {
+        let pool = RenderWorkerPool::new(String::new(), Duration::from_secs(60));
+        let router = router(pool.clone()).layer(Extension(Caller::Internal));
+        let worker_id = pool.register();
+        let submit = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                pool.submit_page_generation(PageData::from_url("https://example.com".to_string()))
+                    .await
+            }
+        });
+        let claim_uri = format!("/v1/workers/{}/tasks/claim", worker_id);
+        let (_, json) = send(&router, "POST", &claim_uri, Body::empty()).await?;
+        let task_id = json["task_id"].as_str().unwrap().to_string();
+
+        let failure_uri = format!("/v1/workers/{}/tasks/{}/failure", worker_id, task_id);
+        let body = Body::from(
+            r#"{"error": "singlefile failed", "transient": false, "diagnostics": {
+                "command": "single-file https://example.com page.html", "exit_code": 1,
+                "stderr": "net::ERR_CERT_DATE_INVALID", "duration_ms": 1500}}"#,
+        );
+        let (status, _) = send(&router, "POST", &failure_uri, body).await?;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let error = submit.await?.unwrap_err();
+        assert!(!error.is_transient());
+        assert_eq!(
+            error.diagnostics(),
+            Some(&RenderDiagnostics {
+                command: "single-file https://example.com page.html".to_string(),
+                exit_code: Some(1),
+                stderr: "net::ERR_CERT_DATE_INVALID".to_string(),
+                duration: Duration::from_millis(1500),
+            })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,467 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use nanoid::nanoid;
+use tokio::sync::{oneshot, Notify};
+
+use api::worker_protocol;
+use api::{LoadedPage, PageData, PageError, PageMetadata, PageResult, PageWorker};
+use utils::html_meta::read_html_meta;
+
+use crate::load_page_handler::current_time;
+
+/// Page worker that hands pages over to render workers connected through the HTTP API.
+/// Render workers register, claim pages, upload results and send heartbeats to keep
+/// their claims. Pages of a worker that stopped sending heartbeats go to other workers.
+#[derive(Clone)]
+pub struct RenderWorkerPool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<PoolState>,
+    work_dir: String,
+    lease_duration: Duration,
+    new_task: Notify,
+}
+
+#[derive(Default)]
+struct PoolState {
+    /// Registered workers and the time of their last heartbeat
+    workers: HashMap<String, Instant>,
+    pending: VecDeque<String>,
+    tasks: HashMap<String, RenderTask>,
+}
+
+struct RenderTask {
+    page_url: String,
+    lease: Option<Lease>,
+    result: Option<oneshot::Sender<Result<LoadedPage, PageError>>>,
+}
+
+struct Lease {
+    worker_id: String,
+    until: Instant,
+}
+
+impl RenderWorkerPool {
+    pub fn new(work_dir: String, lease_duration: Duration) -> Self {
+        RenderWorkerPool {
+            shared: Arc::new(Shared {
+                state: Mutex::new(PoolState::default()),
+                work_dir,
+                lease_duration,
+                new_task: Notify::new(),
+            }),
+        }
+    }
+
+    pub(crate) fn lease_duration(&self) -> Duration {
+        self.shared.lease_duration
+    }
+
+    pub(crate) fn register(&self) -> String {
+        let worker_id = nanoid!();
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .workers
+            .insert(worker_id.clone(), Instant::now());
+        println!("Render worker {} registered", worker_id);
+        worker_id
+    }
+
+    /// Extends the leases of the worker. Returns tasks from the given list the worker should
+    /// stop as they were cancelled or given to another worker, `None` if the worker is unknown.
+    pub(crate) fn heartbeat(
+        &self,
+        worker_id: &str,
+        running_tasks: &[String],
+    ) -> Option<Vec<String>> {
+        let mut state = self.shared.state.lock().unwrap();
+        expire(&mut state, Instant::now(), self.shared.lease_duration);
+        let last_seen = state.workers.get_mut(worker_id)?;
+        *last_seen = Instant::now();
+
+        let until = Instant::now() + self.shared.lease_duration;
+        let mut lost_tasks = Vec::new();
+        for task_id in running_tasks {
+            match state
+                .tasks
+                .get_mut(task_id)
+                .and_then(|task| task.lease.as_mut())
+                .filter(|lease| lease.worker_id == worker_id)
+            {
+                Some(lease) => lease.until = until,
+                None => lost_tasks.push(task_id.clone()),
+            }
+        }
+        Some(lost_tasks)
+    }
+
+    /// Gives the oldest pending page to the worker, `Err` if the worker is unknown.
+    pub(crate) fn claim(
+        &self,
+        worker_id: &str,
+    ) -> anyhow::Result<Option<worker_protocol::RenderTask>> {
+        let mut state = self.shared.state.lock().unwrap();
+        expire(&mut state, Instant::now(), self.shared.lease_duration);
+        if !state.workers.contains_key(worker_id) {
+            return Err(anyhow!("Unknown worker {}", worker_id));
+        }
+
+        while let Some(task_id) = state.pending.pop_front() {
+            // cancelled tasks are removed from the map only
+            if let Some(task) = state.tasks.get_mut(&task_id) {
+                task.lease = Some(Lease {
+                    worker_id: worker_id.to_string(),
+                    until: Instant::now() + self.shared.lease_duration,
+                });
+                return Ok(Some(worker_protocol::RenderTask {
+                    task_id,
+                    page_url: task.page_url.clone(),
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Saves the page uploaded by the worker. Returns false if the task doesn't belong
+    /// to the worker anymore.
+    pub(crate) async fn complete(
+        &self,
+        worker_id: &str,
+        task_id: &str,
+        content: &[u8],
+        metadata: PageMetadata,
+    ) -> anyhow::Result<bool> {
+        if !self.is_assigned(worker_id, task_id) {
+            return Ok(false);
+        }
+        let mut file_path = PathBuf::from(&self.shared.work_dir);
+        file_path.push(nanoid!());
+        file_path.set_extension("html");
+        tokio::fs::write(&file_path, content).await?;
+        let path = file_path
+            .to_str()
+            .ok_or(anyhow!("Can't convert path to str"))?
+            .to_string();
+
+        let html_meta = read_html_meta(&path).unwrap_or_default();
+        let page = LoadedPage {
+            result: PageResult::FilePath(path.clone()),
+            metadata: PageMetadata {
+                title: html_meta.title,
+                canonical_url: html_meta.canonical_url,
+                fetched_at: Some(current_time()),
+                ..metadata
+            },
+        };
+        let sent = self.finish(worker_id, task_id, Ok(page));
+        if !sent {
+            tokio::fs::remove_file(path).await.ok();
+        }
+        Ok(sent)
+    }
+
+    /// Fails the task with the error reported by the worker.
+    pub(crate) fn fail(&self, worker_id: &str, task_id: &str, error: PageError) -> bool {
+        self.finish(worker_id, task_id, Err(error))
+    }
+
+    fn is_assigned(&self, worker_id: &str, task_id: &str) -> bool {
+        let state = self.shared.state.lock().unwrap();
+        is_assigned(&state, worker_id, task_id)
+    }
+
+    fn finish(
+        &self,
+        worker_id: &str,
+        task_id: &str,
+        result: Result<LoadedPage, PageError>,
+    ) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        if !is_assigned(&state, worker_id, task_id) {
+            return false;
+        }
+        state
+            .tasks
+            .remove(task_id)
+            .and_then(|mut task| task.result.take())
+            .map(|sender| sender.send(result).is_ok())
+            .unwrap_or(false)
+    }
+
+    /// Claims a page, waits for a new one up to the timeout when nothing is pending.
+    /// Used for long polling, `Err` if the worker is unknown.
+    pub(crate) async fn claim_or_wait(
+        &self,
+        worker_id: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<worker_protocol::RenderTask>> {
+        // registered before the first claim, so a page submitted right after it
+        // wakes the worker too
+        let new_task = self.shared.new_task.notified();
+        tokio::pin!(new_task);
+        new_task.as_mut().enable();
+        if let Some(task) = self.claim(worker_id)? {
+            return Ok(Some(task));
+        }
+        let _ = tokio::time::timeout(timeout, new_task).await;
+        self.claim(worker_id)
+    }
+}
+
+#[async_trait]
+impl PageWorker for RenderWorkerPool {
+    async fn submit_page_generation(&self, page_data: PageData) -> Result<LoadedPage, PageError> {
+        let task_id = nanoid!();
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.tasks.insert(
+                task_id.clone(),
+                RenderTask {
+                    page_url: page_data.url,
+                    lease: None,
+                    result: Some(sender),
+                },
+            );
+            state.pending.push_back(task_id.clone());
+        }
+        self.shared.new_task.notify_waiters();
+
+        // removes the task if the loading is cancelled while the page is rendering
+        let _guard = TaskGuard {
+            shared: self.shared.clone(),
+            task_id,
+        };
+        receiver
+            .await
+            .unwrap_or_else(|_| Err(PageError::fetch("Render task was dropped")))
+    }
+}
+
+struct TaskGuard {
+    shared: Arc<Shared>,
+    task_id: String,
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .tasks
+            .remove(&self.task_id);
+    }
+}
+
+fn is_assigned(state: &PoolState, worker_id: &str, task_id: &str) -> bool {
+    state
+        .tasks
+        .get(task_id)
+        .and_then(|task| task.lease.as_ref())
+        .map(|lease| lease.worker_id == worker_id)
+        .unwrap_or(false)
+}
+
+/// Forgets workers without heartbeats and returns their tasks to the pending queue.
+fn expire(state: &mut PoolState, now: Instant, lease_duration: Duration) {
+    let mut expired_tasks = Vec::new();
+    for (task_id, task) in state.tasks.iter_mut() {
+        if task.lease.as_ref().is_some_and(|lease| lease.until <= now) {
+            println!("Lease of task {} for {} expired", task_id, task.page_url);
+            task.lease = None;
+            expired_tasks.push(task_id.clone());
+        }
+    }
+    for task_id in expired_tasks {
+        state.pending.push_front(task_id);
+    }
+
+    let tasks = &state.tasks;
+    state.workers.retain(|worker_id, last_seen| {
+        let has_lease = tasks.values().any(|task| {
+            task.lease
+                .as_ref()
+                .is_some_and(|lease| &lease.worker_id == worker_id)
+        });
+        has_lease || *last_seen + lease_duration > now
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+
+    use api::worker_protocol::RenderTask;
+    use api::{Egress, PageData, PageError, PageMetadata, PageResult, PageWorker};
+
+    use crate::worker_pool::RenderWorkerPool;
+
+    const URL: &str = "https://example.com";
+    const PAGE: &[u8] = b"<html><head><title>Example</title></head></html>";
+
+    async fn wait_for_claim(pool: &RenderWorkerPool, worker_id: &str) -> RenderTask {
+        loop {
+            if let Some(task) = pool.claim(worker_id).unwrap() {
+                return task;
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_worker_completes_page() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let pool = RenderWorkerPool::new(
+            dir.path().to_str().unwrap().to_string(),
+            Duration::from_secs(60),
+        );
+        let worker_id = pool.register();
+        assert!(pool.claim(&worker_id)?.is_none());
+
+        let submit = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                pool.submit_page_generation(PageData::from_url(URL.to_string()))
+                    .await
+            }
+        });
+        let task = wait_for_claim(&pool, &worker_id).await;
+        assert_eq!(task.page_url, URL);
+        assert!(pool.claim(&worker_id)?.is_none());
+        let metadata = PageMetadata {
+            egress: Egress::Tor,
+            ..PageMetadata::default()
+        };
+        assert!(
+            !pool
+                .complete("other", &task.task_id, PAGE, metadata.clone())
+                .await?
+        );
+        assert!(
+            pool.complete(&worker_id, &task.task_id, PAGE, metadata.clone())
+                .await?
+        );
+
+        let page = submit.await??;
+        assert_eq!(page.metadata.egress, Egress::Tor);
+        assert_eq!(page.metadata.title.as_deref(), Some("Example"));
+        assert!(page.metadata.fetched_at.is_some());
+        let PageResult::FilePath(path) = page.result else {
+            panic!("File path is expected");
+        };
+        assert_eq!(tokio::fs::read(path).await?, PAGE);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_waiting_worker_gets_new_page() -> anyhow::Result<()> {
+        let pool = RenderWorkerPool::new(String::new(), Duration::from_secs(60));
+        let worker_id = pool.register();
+        let claim = tokio::spawn({
+            let pool = pool.clone();
+            let worker_id = worker_id.clone();
+            async move {
+                pool.claim_or_wait(&worker_id, Duration::from_secs(30))
+                    .await
+            }
+        });
+        tokio::task::yield_now().await;
+        let submit = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                pool.submit_page_generation(PageData::from_url(URL.to_string()))
+                    .await
+            }
+        });
+
+        let task = tokio::time::timeout(Duration::from_secs(5), claim).await???;
+        assert_eq!(task.map(|task| task.page_url).as_deref(), Some(URL));
+        submit.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_worker_reports_failure() -> anyhow::Result<()> {
+        let pool = RenderWorkerPool::new(String::new(), Duration::from_secs(60));
+        let worker_id = pool.register();
+        let submit = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                pool.submit_page_generation(PageData::from_url(URL.to_string()))
+                    .await
+            }
+        });
+        let task = wait_for_claim(&pool, &worker_id).await;
+        assert!(pool.fail(&worker_id, &task.task_id, PageError::fetch("Timeout")));
+
+        let error = submit.await?.unwrap_err();
+        assert_eq!(error.to_string(), "Timeout");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_expired_lease_goes_to_another_worker() -> anyhow::Result<()> {
+        let pool = RenderWorkerPool::new(String::new(), Duration::from_millis(50));
+        let first = pool.register();
+        let _submit = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                pool.submit_page_generation(PageData::from_url(URL.to_string()))
+                    .await
+            }
+        });
+        let task = wait_for_claim(&pool, &first).await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let second = pool.register();
+        let reclaimed = pool.claim(&second)?.unwrap();
+        assert_eq!(reclaimed, task);
+
+        // the first worker is forgotten and has to stop the task
+        assert_eq!(
+            pool.heartbeat(&first, std::slice::from_ref(&task.task_id)),
+            None
+        );
+        assert!(!pool.fail(&first, &task.task_id, PageError::fetch("Late")));
+        assert_eq!(pool.heartbeat(&second, &[task.task_id]), Some(vec![]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_reports_cancelled_task() -> anyhow::Result<()> {
+        let pool = RenderWorkerPool::new(String::new(), Duration::from_secs(60));
+        let worker_id = pool.register();
+        let submit = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                pool.submit_page_generation(PageData::from_url(URL.to_string()))
+                    .await
+            }
+        });
+        let task = wait_for_claim(&pool, &worker_id).await;
+        assert_eq!(
+            pool.heartbeat(&worker_id, std::slice::from_ref(&task.task_id)),
+            Some(vec![])
+        );
+
+        submit.abort();
+        let _ = submit.await;
+        assert_eq!(
+            pool.heartbeat(&worker_id, std::slice::from_ref(&task.task_id)),
+            Some(vec![task.task_id])
+        );
+        Ok(())
+    }
+}
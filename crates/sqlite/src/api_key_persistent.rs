@@ -0,0 +1,383 @@
+use anyhow::bail;
+use async_trait::async_trait;
+use sqlx::postgres::PgRow;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{PgPool, Row, SqlitePool};
+use time::{Date, PrimitiveDateTime};
+
+use api::{ApiKey, ApiKeyPersistent, PageFormat};
+
+use crate::postgres_persistent::PostgresPersistent;
+use crate::sqlite_persistent::SqlitePagePersistent;
+
+const INSERT_API_KEY_QUERY: &str = r#"
+    INSERT INTO api_keys (id, name, secret, rate_limit_per_minute, daily_quota,
+                          allowed_formats, bot_token, revoked, created_at, bot_id)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+    "#;
+
+const REVOKE_API_KEY_QUERY: &str = r#"
+    UPDATE api_keys SET revoked = TRUE WHERE id = $1 AND revoked = FALSE
+    "#;
+
+/// Adds a request to the usage of the day unless the quota is already used
+const USE_QUOTA_QUERY: &str = r#"
+    INSERT INTO api_key_usage (api_key_id, day, requests)
+    VALUES ($1, $2, 1)
+    ON CONFLICT (api_key_id, day) DO UPDATE
+    SET requests = api_key_usage.requests + 1
+    WHERE api_key_usage.requests < $3
+    "#;
+
+const BOT_TOKEN_QUERY: &str = r#"
+    SELECT bot_token FROM api_keys
+    WHERE bot_id = $1 AND revoked = FALSE
+    ORDER BY created_at DESC
+    LIMIT 1
+    "#;
+
+const USAGE_QUERY: &str = r#"
+    SELECT requests FROM api_key_usage WHERE api_key_id = $1 AND day = $2
+    "#;
+
+pub(crate) async fn create_sqlite_api_keys_table(connection: &SqlitePool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_keys (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            secret TEXT NOT NULL,
+            rate_limit_per_minute INTEGER NOT NULL,
+            daily_quota INTEGER NOT NULL,
+            allowed_formats TEXT NOT NULL,
+            bot_token TEXT,
+            revoked BOOLEAN NOT NULL,
+            created_at INTEGER NOT NULL,
+            bot_id TEXT)
+        "#,
+    )
+    .execute(connection)
+    .await?;
+    sqlx::query(CREATE_USAGE_TABLE_QUERY)
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+pub(crate) async fn create_postgres_api_keys_table(connection: &PgPool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_keys (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            secret TEXT NOT NULL,
+            rate_limit_per_minute INTEGER NOT NULL,
+            daily_quota INTEGER NOT NULL,
+            allowed_formats TEXT NOT NULL,
+            bot_token TEXT,
+            revoked BOOLEAN NOT NULL,
+            created_at TIMESTAMP NOT NULL,
+            bot_id TEXT)
+        "#,
+    )
+    .execute(connection)
+    .await?;
+    sqlx::query(CREATE_USAGE_TABLE_QUERY)
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+const CREATE_USAGE_TABLE_QUERY: &str = r#"
+    CREATE TABLE IF NOT EXISTS api_key_usage (
+        api_key_id TEXT NOT NULL,
+        day TEXT NOT NULL,
+        requests INTEGER NOT NULL,
+        PRIMARY KEY (api_key_id, day))
+    "#;
+
+#[async_trait]
+impl ApiKeyPersistent for SqlitePagePersistent {
+    async fn save_api_key(&self, api_key: &ApiKey) -> anyhow::Result<()> {
+        let count = sqlx::query(INSERT_API_KEY_QUERY)
+            .bind(&api_key.id)
+            .bind(&api_key.name)
+            .bind(&api_key.secret)
+            .bind(api_key.rate_limit_per_minute as i32)
+            .bind(api_key.daily_quota as i32)
+            .bind(join_formats(&api_key.allowed_formats))
+            .bind(&api_key.bot_token)
+            .bind(api_key.revoked)
+            .bind(api_key.created_at)
+            .bind(api_key.bot_id())
+            .execute(&self.connection)
+            .await?
+            .rows_affected();
+        check_one_row(count)
+    }
+
+    async fn get_api_key(&self, id: &str) -> anyhow::Result<Option<ApiKey>> {
+        sqlx::query("SELECT * FROM api_keys WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.connection)
+            .await?
+            .map(map_sqlite_row)
+            .transpose()
+    }
+
+    async fn list_api_keys(&self) -> anyhow::Result<Vec<ApiKey>> {
+        sqlx::query("SELECT * FROM api_keys ORDER BY created_at")
+            .fetch_all(&self.connection)
+            .await?
+            .into_iter()
+            .map(map_sqlite_row)
+            .collect()
+    }
+
+    async fn revoke_api_key(&self, id: &str) -> anyhow::Result<bool> {
+        let count = sqlx::query(REVOKE_API_KEY_QUERY)
+            .bind(id)
+            .execute(&self.connection)
+            .await?
+            .rows_affected();
+        Ok(count == 1)
+    }
+
+    async fn try_use_quota(&self, id: &str, day: Date, daily_quota: u32) -> anyhow::Result<bool> {
+        if daily_quota == 0 {
+            return Ok(false);
+        }
+        let count = sqlx::query(USE_QUOTA_QUERY)
+            .bind(id)
+            .bind(day.to_string())
+            .bind(daily_quota as i32)
+            .execute(&self.connection)
+            .await?
+            .rows_affected();
+        Ok(count == 1)
+    }
+
+    async fn get_usage(&self, id: &str, day: Date) -> anyhow::Result<u64> {
+        let requests: Option<i32> = sqlx::query_scalar(USAGE_QUERY)
+            .bind(id)
+            .bind(day.to_string())
+            .fetch_optional(&self.connection)
+            .await?;
+        Ok(requests.unwrap_or_default() as u64)
+    }
+
+    async fn get_bot_token(&self, bot_id: &str) -> anyhow::Result<Option<String>> {
+        let token: Option<Option<String>> = sqlx::query_scalar(BOT_TOKEN_QUERY)
+            .bind(bot_id)
+            .fetch_optional(&self.connection)
+            .await?;
+        Ok(token.flatten())
+    }
+}
+
+#[async_trait]
+impl ApiKeyPersistent for PostgresPersistent {
+    async fn save_api_key(&self, api_key: &ApiKey) -> anyhow::Result<()> {
+        let count = sqlx::query(INSERT_API_KEY_QUERY)
+            .bind(&api_key.id)
+            .bind(&api_key.name)
+            .bind(&api_key.secret)
+            .bind(api_key.rate_limit_per_minute as i32)
+            .bind(api_key.daily_quota as i32)
+            .bind(join_formats(&api_key.allowed_formats))
+            .bind(&api_key.bot_token)
+            .bind(api_key.revoked)
+            .bind(api_key.created_at)
+            .bind(api_key.bot_id())
+            .execute(&self.connection)
+            .await?
+            .rows_affected();
+        check_one_row(count)
+    }
+
+    async fn get_api_key(&self, id: &str) -> anyhow::Result<Option<ApiKey>> {
+        sqlx::query("SELECT * FROM api_keys WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.connection)
+            .await?
+            .map(map_postgres_row)
+            .transpose()
+    }
+
+    async fn list_api_keys(&self) -> anyhow::Result<Vec<ApiKey>> {
+        sqlx::query("SELECT * FROM api_keys ORDER BY created_at")
+            .fetch_all(&self.connection)
+            .await?
+            .into_iter()
+            .map(map_postgres_row)
+            .collect()
+    }
+
+    async fn revoke_api_key(&self, id: &str) -> anyhow::Result<bool> {
+        let count = sqlx::query(REVOKE_API_KEY_QUERY)
+            .bind(id)
+            .execute(&self.connection)
+            .await?
+            .rows_affected();
+        Ok(count == 1)
+    }
+
+    async fn try_use_quota(&self, id: &str, day: Date, daily_quota: u32) -> anyhow::Result<bool> {
+        if daily_quota == 0 {
+            return Ok(false);
+        }
+        let count = sqlx::query(USE_QUOTA_QUERY)
+            .bind(id)
+            .bind(day.to_string())
+            .bind(daily_quota as i32)
+            .execute(&self.connection)
+            .await?
+            .rows_affected();
+        Ok(count == 1)
+    }
+
+    async fn get_usage(&self, id: &str, day: Date) -> anyhow::Result<u64> {
+        let requests: Option<i32> = sqlx::query_scalar(USAGE_QUERY)
+            .bind(id)
+            .bind(day.to_string())
+            .fetch_optional(&self.connection)
+            .await?;
+        Ok(requests.unwrap_or_default() as u64)
+    }
+
+    async fn get_bot_token(&self, bot_id: &str) -> anyhow::Result<Option<String>> {
+        let token: Option<Option<String>> = sqlx::query_scalar(BOT_TOKEN_QUERY)
+            .bind(bot_id)
+            .fetch_optional(&self.connection)
+            .await?;
+        Ok(token.flatten())
+    }
+}
+
+fn check_one_row(count: u64) -> anyhow::Result<()> {
+    if count == 1 {
+        Ok(())
+    } else {
+        bail!("Expected one row to be changed, but was {}", count)
+    }
+}
+
+fn join_formats(formats: &[PageFormat]) -> String {
+    formats
+        .iter()
+        .map(PageFormat::as_str)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn parse_formats(formats: &str) -> anyhow::Result<Vec<PageFormat>> {
+    formats
+        .split(',')
+        .filter(|format| !format.is_empty())
+        .map(PageFormat::parse)
+        .collect()
+}
+
+fn map_sqlite_row(row: SqliteRow) -> anyhow::Result<ApiKey> {
+    Ok(ApiKey {
+        id: row.try_get("id")?,
+        name: row.try_get("name")?,
+        secret: row.try_get("secret")?,
+        rate_limit_per_minute: row.try_get::<i32, &str>("rate_limit_per_minute")? as u32,
+        daily_quota: row.try_get::<i32, &str>("daily_quota")? as u32,
+        allowed_formats: parse_formats(row.try_get("allowed_formats")?)?,
+        bot_token: row.try_get("bot_token")?,
+        revoked: row.try_get("revoked")?,
+        created_at: row.try_get::<PrimitiveDateTime, &str>("created_at")?,
+    })
+}
+
+fn map_postgres_row(row: PgRow) -> anyhow::Result<ApiKey> {
+    Ok(ApiKey {
+        id: row.try_get("id")?,
+        name: row.try_get("name")?,
+        secret: row.try_get("secret")?,
+        rate_limit_per_minute: row.try_get::<i32, &str>("rate_limit_per_minute")? as u32,
+        daily_quota: row.try_get::<i32, &str>("daily_quota")? as u32,
+        allowed_formats: parse_formats(row.try_get("allowed_formats")?)?,
+        bot_token: row.try_get("bot_token")?,
+        revoked: row.try_get("revoked")?,
+        created_at: row.try_get::<PrimitiveDateTime, &str>("created_at")?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use time::macros::{date, datetime};
+
+    use api::{ApiKey, ApiKeyPersistent, PageFormat};
+
+    use crate::sqlite_persistent::init_db;
+
+    fn create_key(id: &str) -> ApiKey {
+        ApiKey {
+            id: id.to_string(),
+            name: "Other team".to_string(),
+            secret: "secret".to_string(),
+            rate_limit_per_minute: 10,
+            daily_quota: 2,
+            allowed_formats: vec![PageFormat::Html, PageFormat::Gzip],
+            bot_token: Some("123:token".to_string()),
+            revoked: false,
+            created_at: datetime!(2024-01-02 10:00:00),
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_save_and_revoke_key() -> anyhow::Result<()> {
+        let db = init_db("sqlite::memory:".to_string()).await?;
+        let key = create_key("key_1");
+        db.save_api_key(&key).await?;
+
+        assert_eq!(db.get_api_key("key_1").await?, Some(key.clone()));
+        assert_eq!(db.get_api_key("key_2").await?, None);
+
+        assert!(db.revoke_api_key("key_1").await?);
+        assert!(!db.revoke_api_key("key_1").await?);
+        assert!(!db.revoke_api_key("key_2").await?);
+        let revoked = ApiKey {
+            revoked: true,
+            ..key
+        };
+        assert_eq!(db.list_api_keys().await?, vec![revoked]);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_get_bot_token() -> anyhow::Result<()> {
+        let db = init_db("sqlite::memory:".to_string()).await?;
+        db.save_api_key(&create_key("key_1")).await?;
+
+        assert_eq!(
+            db.get_bot_token("123").await?,
+            Some("123:token".to_string())
+        );
+        assert_eq!(db.get_bot_token("456").await?, None);
+
+        db.revoke_api_key("key_1").await?;
+        assert_eq!(db.get_bot_token("123").await?, None);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_daily_quota() -> anyhow::Result<()> {
+        let db = init_db("sqlite::memory:".to_string()).await?;
+        let day = date!(2024 - 01 - 02);
+
+        assert!(db.try_use_quota("key_1", day, 2).await?);
+        assert!(db.try_use_quota("key_1", day, 2).await?);
+        assert!(!db.try_use_quota("key_1", day, 2).await?);
+        assert_eq!(db.get_usage("key_1", day).await?, 2);
+
+        let next_day = date!(2024 - 01 - 03);
+        assert_eq!(db.get_usage("key_1", next_day).await?, 0);
+        assert!(db.try_use_quota("key_1", next_day, 2).await?);
+        assert!(!db.try_use_quota("key_2", day, 0).await?);
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use sqlx::postgres::PgRow;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{PgPool, Row, SqlitePool};
+use time::PrimitiveDateTime;
+
+use api::{FailureLog, PageFailure, RenderDiagnostics};
+
+use crate::postgres_persistent::PostgresPersistent;
+use crate::sqlite_persistent::SqlitePagePersistent;
+
+const INSERT_FAILURE_QUERY: &str = r#"
+    INSERT INTO page_failures (page_url, error, transient, command, exit_code, stderr,
+                               duration_ms, failed_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+    "#;
+
+/// Urls are matched by a substring, an empty filter matches every url
+const LIST_FAILURES_QUERY: &str = r#"
+    SELECT * FROM page_failures
+    WHERE page_url LIKE '%' || $1 || '%'
+    ORDER BY failed_at DESC, id DESC
+    LIMIT $2
+    "#;
+
+pub(crate) async fn create_sqlite_failures_table(connection: &SqlitePool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS page_failures (
+            id INTEGER PRIMARY KEY,
+            page_url TEXT NOT NULL,
+            error TEXT NOT NULL,
+            transient BOOLEAN NOT NULL,
+            command TEXT,
+            exit_code INTEGER,
+            stderr TEXT,
+            duration_ms INTEGER,
+            failed_at INTEGER NOT NULL)
+        "#,
+    )
+    .execute(connection)
+    .await?;
+    sqlx::query(CREATE_FAILED_AT_INDEX_QUERY)
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+pub(crate) async fn create_postgres_failures_table(connection: &PgPool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS page_failures (
+            id SERIAL PRIMARY KEY,
+            page_url TEXT NOT NULL,
+            error TEXT NOT NULL,
+            transient BOOLEAN NOT NULL,
+            command TEXT,
+            exit_code INTEGER,
+            stderr TEXT,
+            duration_ms BIGINT,
+            failed_at TIMESTAMP NOT NULL)
+        "#,
+    )
+    .execute(connection)
+    .await?;
+    sqlx::query(CREATE_FAILED_AT_INDEX_QUERY)
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+const CREATE_FAILED_AT_INDEX_QUERY: &str = r#"
+    CREATE INDEX IF NOT EXISTS idx_page_failures_failed_at
+    ON page_failures(failed_at)
+    "#;
+
+#[async_trait]
+impl FailureLog for SqlitePagePersistent {
+    async fn record_failure(&self, failure: &PageFailure) -> anyhow::Result<()> {
+        let diagnostics = failure.diagnostics.as_ref();
+        sqlx::query(INSERT_FAILURE_QUERY)
+            .bind(&failure.page_url)
+            .bind(&failure.error)
+            .bind(failure.transient)
+            .bind(diagnostics.map(|diagnostics| &diagnostics.command))
+            .bind(diagnostics.and_then(|diagnostics| diagnostics.exit_code))
+            .bind(diagnostics.map(|diagnostics| &diagnostics.stderr))
+            .bind(diagnostics.map(|diagnostics| diagnostics.duration.as_millis() as i64))
+            .bind(failure.failed_at)
+            .execute(&self.connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_failures(
+        &self,
+        url_filter: Option<&str>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<PageFailure>> {
+        sqlx::query(LIST_FAILURES_QUERY)
+            .bind(url_filter.unwrap_or_default())
+            .bind(limit as i64)
+            .fetch_all(&self.connection)
+            .await?
+            .into_iter()
+            .map(map_sqlite_row)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl FailureLog for PostgresPersistent {
+    async fn record_failure(&self, failure: &PageFailure) -> anyhow::Result<()> {
+        let diagnostics = failure.diagnostics.as_ref();
+        sqlx::query(INSERT_FAILURE_QUERY)
+            .bind(&failure.page_url)
+            .bind(&failure.error)
+            .bind(failure.transient)
+            .bind(diagnostics.map(|diagnostics| &diagnostics.command))
+            .bind(diagnostics.and_then(|diagnostics| diagnostics.exit_code))
+            .bind(diagnostics.map(|diagnostics| &diagnostics.stderr))
+            .bind(diagnostics.map(|diagnostics| diagnostics.duration.as_millis() as i64))
+            .bind(failure.failed_at)
+            .execute(&self.connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_failures(
+        &self,
+        url_filter: Option<&str>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<PageFailure>> {
+        sqlx::query(LIST_FAILURES_QUERY)
+            .bind(url_filter.unwrap_or_default())
+            .bind(limit as i64)
+            .fetch_all(&self.connection)
+            .await?
+            .into_iter()
+            .map(map_postgres_row)
+            .collect()
+    }
+}
+
+/// Diagnostics are stored only for failures of the renderer, the command is always set then
+fn to_diagnostics(
+    command: Option<String>,
+    exit_code: Option<i32>,
+    stderr: Option<String>,
+    duration_ms: Option<i64>,
+) -> Option<RenderDiagnostics> {
+    Some(RenderDiagnostics {
+        command: command?,
+        exit_code,
+        stderr: stderr.unwrap_or_default(),
+        duration: Duration::from_millis(duration_ms.unwrap_or_default() as u64),
+    })
+}
+
+fn map_sqlite_row(row: SqliteRow) -> anyhow::Result<PageFailure> {
+    Ok(PageFailure {
+        page_url: row.try_get("page_url")?,
+        error: row.try_get("error")?,
+        transient: row.try_get("transient")?,
+        diagnostics: to_diagnostics(
+            row.try_get("command")?,
+            row.try_get("exit_code")?,
+            row.try_get("stderr")?,
+            row.try_get("duration_ms")?,
+        ),
+        failed_at: row.try_get::<PrimitiveDateTime, &str>("failed_at")?,
+    })
+}
+
+fn map_postgres_row(row: PgRow) -> anyhow::Result<PageFailure> {
+    Ok(PageFailure {
+        page_url: row.try_get("page_url")?,
+        error: row.try_get("error")?,
+        transient: row.try_get("transient")?,
+        diagnostics: to_diagnostics(
+            row.try_get("command")?,
+            row.try_get("exit_code")?,
+            row.try_get("stderr")?,
+            row.try_get("duration_ms")?,
+        ),
+        failed_at: row.try_get::<PrimitiveDateTime, &str>("failed_at")?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use time::macros::datetime;
+
+    use api::{FailureLog, PageFailure, RenderDiagnostics};
+
+    use crate::sqlite_persistent::in_memory_db;
+
+    fn failure(page_url: &str, diagnostics: Option<RenderDiagnostics>) -> PageFailure {
+        PageFailure {
+            page_url: page_url.to_string(),
+            error: "singlefile failed".to_string(),
+            transient: false,
+            diagnostics,
+            failed_at: datetime!(2024-01-02 10:00:00),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_records_failures() -> anyhow::Result<()> {
+        let db = in_memory_db().await?;
+        let diagnostics = RenderDiagnostics {
+            command: "single-file https://example.com/a /tmp/a.html".to_string(),
+            exit_code: Some(1),
+            stderr: "net::ERR_CERT_AUTHORITY_INVALID".to_string(),
+            duration: Duration::from_millis(1234),
+        };
+        let first = failure("https://example.com/a", Some(diagnostics));
+        let second = PageFailure {
+            failed_at: datetime!(2024-01-02 11:00:00),
+            ..failure("https://other.com/b", None)
+        };
+        db.record_failure(&first).await?;
+        db.record_failure(&second).await?;
+
+        assert_eq!(
+            db.list_failures(None, 10).await?,
+            vec![second.clone(), first.clone()]
+        );
+        assert_eq!(
+            db.list_failures(Some("example.com"), 10).await?,
+            vec![first]
+        );
+        assert_eq!(db.list_failures(None, 1).await?, vec![second]);
+        Ok(())
+    }
+}
@@ -0,0 +1,704 @@
+use anyhow::bail;
+use async_trait::async_trait;
+use sqlx::postgres::PgRow;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{PgPool, Row, SqlitePool};
+use time::PrimitiveDateTime;
+
+use api::i18n::Language;
+use api::{ClaimedPage, Job, JobPersistent, JobState, PageFormat};
+
+use crate::postgres_persistent::PostgresPersistent;
+use crate::sqlite_persistent::{add_column_if_missing, SqlitePagePersistent};
+
+const INSERT_JOB_QUERY: &str = r#"
+    INSERT INTO jobs (id, page_url, chat_id, status_message_id, state, attempts,
+                      next_attempt_at, last_error, created_at, api_key_id, format, bot_id,
+                      original_url, fresh, reply_to_message_id, language)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+    "#;
+
+const UPDATE_JOB_QUERY: &str = r#"
+    UPDATE jobs
+    SET state = $1, attempts = $2, next_attempt_at = $3, last_error = $4
+    WHERE id = $5
+    "#;
+
+/// The oldest page that is due and not loaded by anyone with a valid lease
+const CLAIMABLE_PAGE_QUERY: &str = r#"
+    SELECT page_url FROM jobs AS candidate
+    WHERE ((state = 'queued' AND next_attempt_at <= $1)
+           OR (state = 'running' AND lease_until <= $1))
+      AND NOT EXISTS (
+          SELECT 1 FROM jobs AS loading
+          WHERE loading.page_url = candidate.page_url
+            AND loading.state = 'running'
+            AND loading.lease_until > $1)
+    ORDER BY created_at
+    LIMIT 1
+    "#;
+
+/// Same as `CLAIMABLE_PAGE_QUERY`, pages other instances are claiming are skipped
+const POSTGRES_CLAIMABLE_PAGE_QUERY: &str = r#"
+    SELECT page_url FROM jobs AS candidate
+    WHERE ((state = 'queued' AND next_attempt_at <= $1)
+           OR (state = 'running' AND lease_until <= $1))
+      AND NOT EXISTS (
+          SELECT 1 FROM jobs AS loading
+          WHERE loading.page_url = candidate.page_url
+            AND loading.state = 'running'
+            AND loading.lease_until > $1)
+      AND candidate.page_url <> ALL($2)
+    ORDER BY created_at
+    LIMIT 1
+    FOR UPDATE SKIP LOCKED
+    "#;
+
+const CLAIM_JOBS_QUERY: &str = r#"
+    UPDATE jobs
+    SET state = 'running', owner = $1, lease_until = $2
+    WHERE page_url = $3
+      AND (state = 'queued' OR (state = 'running' AND lease_until <= $4))
+    RETURNING *
+    "#;
+
+const RENEW_LEASE_QUERY: &str = r#"
+    UPDATE jobs
+    SET lease_until = $1
+    WHERE owner = $2 AND page_url = $3 AND state = 'running'
+    "#;
+
+const TAKE_PAGE_JOBS_QUERY: &str = r#"
+    UPDATE jobs
+    SET state = 'running', owner = $1
+    WHERE page_url = $2
+      AND (state = 'queued' OR (state = 'running' AND owner = $1))
+    RETURNING *
+    "#;
+
+const CANCEL_JOB_QUERY: &str = r#"
+    UPDATE jobs
+    SET state = 'cancelled'
+    WHERE id = $1 AND state IN ('queued', 'running')
+    RETURNING *
+    "#;
+
+const COUNT_ACTIVE_JOBS_QUERY: &str = r#"
+    SELECT COUNT(*) FROM jobs
+    WHERE page_url = $1 AND state IN ('queued', 'running')
+    "#;
+
+pub(crate) async fn create_sqlite_jobs_table(connection: &SqlitePool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS jobs (
+            id TEXT PRIMARY KEY,
+            page_url TEXT NOT NULL,
+            chat_id TEXT NOT NULL,
+            status_message_id INTEGER,
+            state TEXT NOT NULL,
+            attempts INTEGER NOT NULL,
+            next_attempt_at INTEGER NOT NULL,
+            last_error TEXT,
+            created_at INTEGER NOT NULL,
+            owner TEXT,
+            lease_until INTEGER,
+            api_key_id TEXT,
+            format TEXT NOT NULL DEFAULT 'html',
+            bot_id TEXT,
+            original_url TEXT NOT NULL,
+            fresh BOOLEAN NOT NULL DEFAULT FALSE,
+            reply_to_message_id INTEGER,
+            language TEXT NOT NULL DEFAULT 'en')
+        "#,
+    )
+    .execute(connection)
+    .await?;
+    add_column_if_missing(
+        connection,
+        "jobs",
+        "fresh",
+        "BOOLEAN NOT NULL DEFAULT FALSE",
+    )
+    .await?;
+    add_column_if_missing(connection, "jobs", "reply_to_message_id", "INTEGER").await?;
+    add_column_if_missing(connection, "jobs", "language", "TEXT NOT NULL DEFAULT 'en'").await?;
+    // pages are claimed and taken by url and state
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_jobs_page_url_state ON jobs(page_url, state)")
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+pub(crate) async fn create_postgres_jobs_table(connection: &PgPool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS jobs (
+            id TEXT PRIMARY KEY,
+            page_url TEXT NOT NULL,
+            chat_id TEXT NOT NULL,
+            status_message_id INTEGER,
+            state TEXT NOT NULL,
+            attempts INTEGER NOT NULL,
+            next_attempt_at TIMESTAMP NOT NULL,
+            last_error TEXT,
+            created_at TIMESTAMP NOT NULL,
+            owner TEXT,
+            lease_until TIMESTAMP,
+            api_key_id TEXT,
+            format TEXT NOT NULL DEFAULT 'html',
+            bot_id TEXT,
+            original_url TEXT NOT NULL,
+            fresh BOOLEAN NOT NULL DEFAULT FALSE,
+            reply_to_message_id INTEGER,
+            language TEXT NOT NULL DEFAULT 'en')
+        "#,
+    )
+    .execute(connection)
+    .await?;
+    sqlx::query("ALTER TABLE jobs ADD COLUMN IF NOT EXISTS fresh BOOLEAN NOT NULL DEFAULT FALSE")
+        .execute(connection)
+        .await?;
+    sqlx::query("ALTER TABLE jobs ADD COLUMN IF NOT EXISTS reply_to_message_id INTEGER")
+        .execute(connection)
+        .await?;
+    sqlx::query("ALTER TABLE jobs ADD COLUMN IF NOT EXISTS language TEXT NOT NULL DEFAULT 'en'")
+        .execute(connection)
+        .await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_jobs_page_url_state ON jobs(page_url, state)")
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl JobPersistent for SqlitePagePersistent {
+    async fn save_job(&self, job: &Job) -> anyhow::Result<()> {
+        let count = sqlx::query(INSERT_JOB_QUERY)
+            .bind(&job.id)
+            .bind(&job.page_url)
+            .bind(&job.chat_id)
+            .bind(job.status_message_id)
+            .bind(job.state.as_str())
+            .bind(job.attempts as i32)
+            .bind(job.next_attempt_at)
+            .bind(&job.last_error)
+            .bind(job.created_at)
+            .bind(&job.api_key_id)
+            .bind(job.format.as_str())
+            .bind(&job.bot_id)
+            .bind(&job.original_url)
+            .bind(job.fresh)
+            .bind(job.reply_to_message_id)
+            .bind(job.language.code())
+            .execute(&self.connection)
+            .await?
+            .rows_affected();
+        check_one_row(count)
+    }
+
+    async fn update_job(&self, job: &Job) -> anyhow::Result<()> {
+        let count = sqlx::query(UPDATE_JOB_QUERY)
+            .bind(job.state.as_str())
+            .bind(job.attempts as i32)
+            .bind(job.next_attempt_at)
+            .bind(&job.last_error)
+            .bind(&job.id)
+            .execute(&self.connection)
+            .await?
+            .rows_affected();
+        check_one_row(count)
+    }
+
+    async fn get_job(&self, job_id: &str) -> anyhow::Result<Option<Job>> {
+        sqlx::query("SELECT * FROM jobs WHERE id = $1")
+            .bind(job_id)
+            .fetch_optional(&self.connection)
+            .await?
+            .map(map_sqlite_row)
+            .transpose()
+    }
+
+    async fn claim_page(
+        &self,
+        owner: &str,
+        now: PrimitiveDateTime,
+        lease_until: PrimitiveDateTime,
+    ) -> anyhow::Result<Option<ClaimedPage>> {
+        // take the write lock right away, a deferred transaction fails with "database is locked"
+        // when another claim upgrades its lock at the same time
+        let mut transaction = self.connection.begin_with("BEGIN IMMEDIATE").await?;
+        let page_url: Option<String> = sqlx::query_scalar(CLAIMABLE_PAGE_QUERY)
+            .bind(now)
+            .fetch_optional(&mut *transaction)
+            .await?;
+        let Some(page_url) = page_url else {
+            return Ok(None);
+        };
+        let jobs = sqlx::query(CLAIM_JOBS_QUERY)
+            .bind(owner)
+            .bind(lease_until)
+            .bind(&page_url)
+            .bind(now)
+            .fetch_all(&mut *transaction)
+            .await?
+            .into_iter()
+            .map(map_sqlite_row)
+            .collect::<anyhow::Result<Vec<Job>>>()?;
+        transaction.commit().await?;
+        Ok(Some(ClaimedPage {
+            page_url,
+            jobs: sort_jobs(jobs),
+        }))
+    }
+
+    async fn renew_lease(
+        &self,
+        owner: &str,
+        page_url: &str,
+        lease_until: PrimitiveDateTime,
+    ) -> anyhow::Result<u64> {
+        let count = sqlx::query(RENEW_LEASE_QUERY)
+            .bind(lease_until)
+            .bind(owner)
+            .bind(page_url)
+            .execute(&self.connection)
+            .await?
+            .rows_affected();
+        Ok(count)
+    }
+
+    async fn take_page_jobs(&self, owner: &str, page_url: &str) -> anyhow::Result<Vec<Job>> {
+        let jobs = sqlx::query(TAKE_PAGE_JOBS_QUERY)
+            .bind(owner)
+            .bind(page_url)
+            .fetch_all(&self.connection)
+            .await?
+            .into_iter()
+            .map(map_sqlite_row)
+            .collect::<anyhow::Result<Vec<Job>>>()?;
+        Ok(sort_jobs(jobs))
+    }
+
+    async fn cancel_job(&self, job_id: &str) -> anyhow::Result<Option<Job>> {
+        sqlx::query(CANCEL_JOB_QUERY)
+            .bind(job_id)
+            .fetch_optional(&self.connection)
+            .await?
+            .map(map_sqlite_row)
+            .transpose()
+    }
+
+    async fn count_active_jobs(&self, page_url: &str) -> anyhow::Result<u64> {
+        let count: i64 = sqlx::query_scalar(COUNT_ACTIVE_JOBS_QUERY)
+            .bind(page_url)
+            .fetch_one(&self.connection)
+            .await?;
+        Ok(count as u64)
+    }
+}
+
+#[async_trait]
+impl JobPersistent for PostgresPersistent {
+    async fn save_job(&self, job: &Job) -> anyhow::Result<()> {
+        let count = sqlx::query(INSERT_JOB_QUERY)
+            .bind(&job.id)
+            .bind(&job.page_url)
+            .bind(&job.chat_id)
+            .bind(job.status_message_id)
+            .bind(job.state.as_str())
+            .bind(job.attempts as i32)
+            .bind(job.next_attempt_at)
+            .bind(&job.last_error)
+            .bind(job.created_at)
+            .bind(&job.api_key_id)
+            .bind(job.format.as_str())
+            .bind(&job.bot_id)
+            .bind(&job.original_url)
+            .bind(job.fresh)
+            .bind(job.reply_to_message_id)
+            .bind(job.language.code())
+            .execute(&self.connection)
+            .await?
+            .rows_affected();
+        check_one_row(count)
+    }
+
+    async fn update_job(&self, job: &Job) -> anyhow::Result<()> {
+        let count = sqlx::query(UPDATE_JOB_QUERY)
+            .bind(job.state.as_str())
+            .bind(job.attempts as i32)
+            .bind(job.next_attempt_at)
+            .bind(&job.last_error)
+            .bind(&job.id)
+            .execute(&self.connection)
+            .await?
+            .rows_affected();
+        check_one_row(count)
+    }
+
+    async fn get_job(&self, job_id: &str) -> anyhow::Result<Option<Job>> {
+        sqlx::query("SELECT * FROM jobs WHERE id = $1")
+            .bind(job_id)
+            .fetch_optional(&self.connection)
+            .await?
+            .map(map_postgres_row)
+            .transpose()
+    }
+
+    async fn claim_page(
+        &self,
+        owner: &str,
+        now: PrimitiveDateTime,
+        lease_until: PrimitiveDateTime,
+    ) -> anyhow::Result<Option<ClaimedPage>> {
+        let mut transaction = self.connection.begin().await?;
+        // pages claimed by other instances are skipped, the next page is tried instead
+        let mut skipped: Vec<String> = vec![];
+        let page_url = loop {
+            // rows locked by other instances are skipped, they are claiming them right now
+            let page_url: Option<String> = sqlx::query_scalar(POSTGRES_CLAIMABLE_PAGE_QUERY)
+                .bind(now)
+                .bind(&skipped)
+                .fetch_optional(&mut *transaction)
+                .await?;
+            let Some(page_url) = page_url else {
+                return Ok(None);
+            };
+
+            // row locks don't cover jobs of the same page queued by other chats,
+            // the advisory lock makes sure only one instance claims the page
+            let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_xact_lock(hashtext($1))")
+                .bind(&page_url)
+                .fetch_one(&mut *transaction)
+                .await?;
+            if !locked {
+                skipped.push(page_url);
+                continue;
+            }
+            let loading: bool = sqlx::query_scalar(
+                r#"
+                SELECT EXISTS (
+                    SELECT 1 FROM jobs
+                    WHERE page_url = $1 AND state = 'running' AND lease_until > $2)
+                "#,
+            )
+            .bind(&page_url)
+            .bind(now)
+            .fetch_one(&mut *transaction)
+            .await?;
+            if loading {
+                skipped.push(page_url);
+                continue;
+            }
+            break page_url;
+        };
+
+        let jobs = sqlx::query(CLAIM_JOBS_QUERY)
+            .bind(owner)
+            .bind(lease_until)
+            .bind(&page_url)
+            .bind(now)
+            .fetch_all(&mut *transaction)
+            .await?
+            .into_iter()
+            .map(map_postgres_row)
+            .collect::<anyhow::Result<Vec<Job>>>()?;
+        transaction.commit().await?;
+        Ok(Some(ClaimedPage {
+            page_url,
+            jobs: sort_jobs(jobs),
+        }))
+    }
+
+    async fn renew_lease(
+        &self,
+        owner: &str,
+        page_url: &str,
+        lease_until: PrimitiveDateTime,
+    ) -> anyhow::Result<u64> {
+        let count = sqlx::query(RENEW_LEASE_QUERY)
+            .bind(lease_until)
+            .bind(owner)
+            .bind(page_url)
+            .execute(&self.connection)
+            .await?
+            .rows_affected();
+        Ok(count)
+    }
+
+    async fn take_page_jobs(&self, owner: &str, page_url: &str) -> anyhow::Result<Vec<Job>> {
+        let jobs = sqlx::query(TAKE_PAGE_JOBS_QUERY)
+            .bind(owner)
+            .bind(page_url)
+            .fetch_all(&self.connection)
+            .await?
+            .into_iter()
+            .map(map_postgres_row)
+            .collect::<anyhow::Result<Vec<Job>>>()?;
+        Ok(sort_jobs(jobs))
+    }
+
+    async fn cancel_job(&self, job_id: &str) -> anyhow::Result<Option<Job>> {
+        sqlx::query(CANCEL_JOB_QUERY)
+            .bind(job_id)
+            .fetch_optional(&self.connection)
+            .await?
+            .map(map_postgres_row)
+            .transpose()
+    }
+
+    async fn count_active_jobs(&self, page_url: &str) -> anyhow::Result<u64> {
+        let count: i64 = sqlx::query_scalar(COUNT_ACTIVE_JOBS_QUERY)
+            .bind(page_url)
+            .fetch_one(&self.connection)
+            .await?;
+        Ok(count as u64)
+    }
+}
+
+/// Jobs are served in the order they were requested
+fn sort_jobs(mut jobs: Vec<Job>) -> Vec<Job> {
+    jobs.sort_by_key(|job| job.created_at);
+    jobs
+}
+
+fn check_one_row(count: u64) -> anyhow::Result<()> {
+    if count == 1 {
+        Ok(())
+    } else {
+        bail!("Expected one row to be changed, but was {}", count)
+    }
+}
+
+fn map_sqlite_row(row: SqliteRow) -> anyhow::Result<Job> {
+    Ok(Job {
+        id: row.try_get("id")?,
+        page_url: row.try_get("page_url")?,
+        chat_id: row.try_get("chat_id")?,
+        status_message_id: row.try_get("status_message_id")?,
+        state: JobState::parse(row.try_get("state")?)?,
+        attempts: row.try_get::<i32, &str>("attempts")? as u32,
+        next_attempt_at: row.try_get::<PrimitiveDateTime, &str>("next_attempt_at")?,
+        last_error: row.try_get("last_error")?,
+        created_at: row.try_get::<PrimitiveDateTime, &str>("created_at")?,
+        api_key_id: row.try_get("api_key_id")?,
+        format: PageFormat::parse(row.try_get("format")?)?,
+        bot_id: row.try_get("bot_id")?,
+        original_url: row.try_get("original_url")?,
+        fresh: row.try_get("fresh")?,
+        reply_to_message_id: row.try_get("reply_to_message_id")?,
+        language: Language::parse(row.try_get("language")?)?,
+    })
+}
+
+fn map_postgres_row(row: PgRow) -> anyhow::Result<Job> {
+    Ok(Job {
+        id: row.try_get("id")?,
+        page_url: row.try_get("page_url")?,
+        chat_id: row.try_get("chat_id")?,
+        status_message_id: row.try_get("status_message_id")?,
+        state: JobState::parse(row.try_get("state")?)?,
+        attempts: row.try_get::<i32, &str>("attempts")? as u32,
+        next_attempt_at: row.try_get::<PrimitiveDateTime, &str>("next_attempt_at")?,
+        last_error: row.try_get("last_error")?,
+        created_at: row.try_get::<PrimitiveDateTime, &str>("created_at")?,
+        api_key_id: row.try_get("api_key_id")?,
+        format: PageFormat::parse(row.try_get("format")?)?,
+        bot_id: row.try_get("bot_id")?,
+        original_url: row.try_get("original_url")?,
+        fresh: row.try_get("fresh")?,
+        reply_to_message_id: row.try_get("reply_to_message_id")?,
+        language: Language::parse(row.try_get("language")?)?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+    use time::macros::datetime;
+    use time::PrimitiveDateTime;
+
+    use api::i18n::Language;
+    use api::{Job, JobPersistent, JobState, PageFormat};
+
+    use crate::sqlite_persistent::{init_db, SqlitePagePersistent};
+
+    const NOW: PrimitiveDateTime = datetime!(2024-01-02 10:00:00);
+    const LEASE_UNTIL: PrimitiveDateTime = datetime!(2024-01-02 10:01:00);
+
+    #[sqlx::test]
+    async fn test_claim_page() -> anyhow::Result<()> {
+        let db = init_db("sqlite::memory:".to_string()).await?;
+        let first = create_job("job_1", "url_1", NOW);
+        let second = create_job("job_2", "url_1", datetime!(2024-01-02 10:00:01));
+        let other_page = create_job("job_3", "url_2", datetime!(2024-01-02 10:00:02));
+        db.save_job(&first).await?;
+        db.save_job(&second).await?;
+        db.save_job(&other_page).await?;
+
+        let claimed = db.claim_page("owner_1", NOW, LEASE_UNTIL).await?.unwrap();
+        assert_eq!(claimed.page_url, "url_1");
+        assert_eq!(
+            claimed.jobs,
+            vec![running(first.clone()), running(second.clone())]
+        );
+
+        let claimed = db.claim_page("owner_2", NOW, LEASE_UNTIL).await?.unwrap();
+        assert_eq!(claimed.page_url, "url_2");
+        assert_eq!(db.claim_page("owner_2", NOW, LEASE_UNTIL).await?, None);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_skip_page_loaded_by_other_owner() -> anyhow::Result<()> {
+        let db = init_db("sqlite::memory:".to_string()).await?;
+        db.save_job(&create_job("job_1", "url_1", NOW)).await?;
+        db.claim_page("owner_1", NOW, LEASE_UNTIL).await?;
+
+        // a chat requests the same page while it's loading
+        let late_job = create_job("job_2", "url_1", NOW);
+        db.save_job(&late_job).await?;
+        assert_eq!(db.claim_page("owner_2", NOW, LEASE_UNTIL).await?, None);
+
+        let jobs = db.take_page_jobs("owner_1", "url_1").await?;
+        assert_eq!(
+            jobs.iter().map(|job| job.id.as_str()).collect::<Vec<_>>(),
+            vec!["job_1", "job_2"]
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_claim_page_with_expired_lease() -> anyhow::Result<()> {
+        let db = init_db("sqlite::memory:".to_string()).await?;
+        db.save_job(&create_job("job_1", "url_1", NOW)).await?;
+        db.claim_page("owner_1", NOW, LEASE_UNTIL).await?;
+
+        let renewed = db
+            .renew_lease("owner_1", "url_1", datetime!(2024-01-02 10:02:00))
+            .await?;
+        assert_eq!(renewed, 1);
+        assert_eq!(
+            db.claim_page("owner_2", datetime!(2024-01-02 10:01:30), LEASE_UNTIL)
+                .await?,
+            None
+        );
+
+        let claimed = db
+            .claim_page(
+                "owner_2",
+                datetime!(2024-01-02 10:02:00),
+                datetime!(2024-01-02 10:03:00),
+            )
+            .await?;
+        assert_eq!(
+            claimed.map(|claimed| claimed.page_url),
+            Some("url_1".to_string())
+        );
+        assert_eq!(
+            db.renew_lease("owner_1", "url_1", LEASE_UNTIL).await?,
+            0,
+            "the dead owner doesn't own the job anymore"
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_skip_retry_that_is_not_due() -> anyhow::Result<()> {
+        let db = init_db("sqlite::memory:".to_string()).await?;
+        let mut job = create_job("job_1", "url_1", NOW);
+        job.next_attempt_at = datetime!(2024-01-02 10:05:00);
+        db.save_job(&job).await?;
+
+        assert_eq!(db.claim_page("owner_1", NOW, LEASE_UNTIL).await?, None);
+        assert!(db
+            .claim_page("owner_1", datetime!(2024-01-02 10:05:00), LEASE_UNTIL)
+            .await?
+            .is_some());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_cancel_job() -> anyhow::Result<()> {
+        let db = init_db("sqlite::memory:".to_string()).await?;
+        let job = create_job("job_1", "url_1", NOW);
+        db.save_job(&job).await?;
+        assert_eq!(db.count_active_jobs("url_1").await?, 1);
+
+        let mut cancelled = job.clone();
+        cancelled.state = JobState::Cancelled;
+        assert_eq!(db.cancel_job("job_1").await?, Some(cancelled));
+        assert_eq!(db.cancel_job("job_1").await?, None);
+        assert_eq!(db.count_active_jobs("url_1").await?, 0);
+        assert_eq!(
+            db.get_job("job_1").await?.map(|job| job.state),
+            Some(JobState::Cancelled)
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_update_unknown_job() -> anyhow::Result<()> {
+        let db = init_db("sqlite::memory:".to_string()).await?;
+
+        assert!(db
+            .update_job(&create_job("job_1", "url_1", NOW))
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_claim_page_once_from_concurrent_tasks() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let db = file_db(&dir).await?;
+        db.save_job(&create_job("job_1", "url_1", NOW)).await?;
+
+        let (first, second) = tokio::join!(
+            db.claim_page("owner_1", NOW, LEASE_UNTIL),
+            db.claim_page("owner_2", NOW, LEASE_UNTIL)
+        );
+
+        assert_eq!(
+            [first?, second?]
+                .iter()
+                .filter(|claim| claim.is_some())
+                .count(),
+            1
+        );
+        Ok(())
+    }
+
+    async fn file_db(dir: &tempfile::TempDir) -> anyhow::Result<SqlitePagePersistent> {
+        let path = dir.path().join("jobs.db");
+        std::fs::File::create(&path)?;
+        init_db(path.to_str().unwrap().to_string()).await
+    }
+
+    fn running(mut job: Job) -> Job {
+        job.state = JobState::Running;
+        job
+    }
+
+    fn create_job(id: &str, page_url: &str, created_at: PrimitiveDateTime) -> Job {
+        Job {
+            id: id.to_string(),
+            page_url: page_url.to_string(),
+            chat_id: "chat".to_string(),
+            status_message_id: Some(1),
+            state: JobState::Queued,
+            attempts: 0,
+            next_attempt_at: NOW,
+            last_error: None,
+            created_at,
+            api_key_id: None,
+            format: PageFormat::Html,
+            bot_id: None,
+            original_url: page_url.to_string(),
+            fresh: false,
+            reply_to_message_id: None,
+            language: Language::Ru,
+        }
+    }
+}
@@ -1,3 +1,8 @@
+pub mod api_key_persistent;
+pub mod failure_log_persistent;
+pub mod job_persistent;
 pub mod persistent_page_worker;
 pub mod postgres_persistent;
+pub mod report_persistent;
+pub mod settings_persistent;
 pub mod sqlite_persistent;
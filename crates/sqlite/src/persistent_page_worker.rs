@@ -7,7 +7,11 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use async_trait::async_trait;
 use time::PrimitiveDateTime;
 
-use api::{PageData, PageInfo, PagePersistent, PageResult, PageWorker};
+use api::{
+    LoadedPage, PageData, PageError, PageFormat, PageInfo, PageMetadata, PagePersistent,
+    PageResult, PageWorker, PageWorkerLayer,
+};
+use utils::canonical_url::canonicalize;
 use utils::hash::make_hash_for_file;
 
 pub struct PersistentPageWorker {
@@ -24,19 +28,45 @@ impl PersistentPageWorker {
     }
 }
 
+/// Sends the cached document instead of loading the page when it's cached recently.
+pub struct CacheLayer {
+    storage: Arc<dyn PagePersistent>,
+}
+
+impl CacheLayer {
+    pub fn new(storage: Arc<dyn PagePersistent>) -> Self {
+        CacheLayer { storage }
+    }
+}
+
+impl PageWorkerLayer for CacheLayer {
+    fn layer(&self, inner: Box<dyn PageWorker>) -> Box<dyn PageWorker> {
+        Box::new(PersistentPageWorker::new(self.storage.clone(), inner))
+    }
+}
+
 #[async_trait]
 impl PageWorker for PersistentPageWorker {
-    async fn submit_page_generation(&self, page_data: PageData) -> anyhow::Result<PageResult> {
+    async fn submit_page_generation(&self, page_data: PageData) -> Result<LoadedPage, PageError> {
+        // only html pages are cached, other formats are made from the loaded file
+        if page_data.format != PageFormat::Html || page_data.fresh {
+            return self.fallback_worker.submit_page_generation(page_data).await;
+        }
         let persistent_page_data = self
             .storage
-            .get(page_data.url.as_str())
+            .get(&canonicalize(&page_data.url), page_data.bot_id.as_deref())
             .await
             .unwrap_or(None);
 
         match persistent_page_data {
             None => self.fallback_worker.submit_page_generation(page_data).await,
             Some(persistent_page) => {
-                download_for_existing_page(persistent_page, page_data, &self.fallback_worker).await
+                download_for_existing_page(
+                    persistent_page,
+                    page_data,
+                    self.fallback_worker.as_ref(),
+                )
+                .await
             }
         }
     }
@@ -45,23 +75,34 @@ impl PageWorker for PersistentPageWorker {
 async fn download_for_existing_page(
     persistent_page: PageInfo,
     page_data: PageData,
-    fallback_worker: &Box<dyn PageWorker>,
-) -> anyhow::Result<PageResult> {
+    fallback_worker: &dyn PageWorker,
+) -> Result<LoadedPage, PageError> {
     if is_expired(&persistent_page.timestamp_ms) {
         let new_page = fallback_worker.submit_page_generation(page_data).await?;
-        match new_page {
-            PageResult::FilePath(path) => Ok(handle_new_page(path, &persistent_page)),
+        match new_page.result {
+            PageResult::FilePath(path) => Ok(LoadedPage {
+                result: handle_new_page(path, &persistent_page),
+                metadata: new_page.metadata,
+            }),
             _ => Ok(new_page),
         }
     } else {
-        Ok(PageResult::TelegramId(
-            persistent_page.telegram_file_id.clone(),
-        ))
+        Ok(LoadedPage {
+            result: PageResult::TelegramId(persistent_page.telegram_file_id.clone()),
+            metadata: PageMetadata {
+                egress: persistent_page.egress,
+                title: persistent_page.title.clone(),
+                canonical_url: persistent_page.canonical_url.clone(),
+                fetched_at: Some(persistent_page.timestamp_ms),
+                from_cache: true,
+                ..PageMetadata::default()
+            },
+        })
     }
 }
 
 fn handle_new_page(path: String, current_page: &PageInfo) -> PageResult {
-    let new_page_hash = make_hash_for_file(&path).unwrap_or(String::new());
+    let new_page_hash = make_hash_for_file(&path).unwrap_or_default();
     if new_page_hash == current_page.file_hash {
         PageResult::TelegramId(current_page.telegram_file_id.clone())
     } else {
@@ -89,7 +130,7 @@ fn current_time_secs() -> i64 {
         .duration_since(UNIX_EPOCH)
         .map(|duration| duration.as_secs() as i64)
         .unwrap_or(i64::MAX);
-    return timestamp;
+    timestamp
 }
 
 #[cfg(test)]
@@ -101,7 +142,7 @@ mod tests {
     use tempfile::tempdir;
     use time::macros::datetime;
 
-    use api::{PageData, PageInfo, PageResult, PageWorker};
+    use api::{Egress, PageData, PageFormat, PageInfo, PageResult, PageWorker};
 
     use crate::persistent_page_worker::test_impl::{MockPagePersistent, MockPageWorker};
     use crate::persistent_page_worker::{
@@ -113,16 +154,16 @@ mod tests {
         let persistent = MockPagePersistent::new();
         let mut page_worker = Box::new(MockPageWorker::new());
         page_worker.data_storage.insert(
-            "url_1".to_string(),
+            "https://example.com/1".to_string(),
             PageResult::TelegramId("id_1".to_string()),
         );
         let worker = PersistentPageWorker::new(Arc::new(persistent), page_worker);
 
         let result = worker
-            .submit_page_generation(PageData::from_url("url_1".to_string()))
+            .submit_page_generation(PageData::from_url("https://example.com/1".to_string()))
             .await?;
 
-        assert_eq!(result, PageResult::TelegramId("id_1".to_string()));
+        assert_eq!(result.result, PageResult::TelegramId("id_1".to_string()));
 
         Ok(())
     }
@@ -131,26 +172,144 @@ mod tests {
     async fn test_item_in_cache() -> anyhow::Result<()> {
         let mut persistent = MockPagePersistent::new();
         persistent.data_storage.insert(
-            "url_1".to_string(),
+            "https://example.com/1".to_string(),
             PageInfo {
                 telegram_file_id: "telegram_id".to_string(),
                 file_hash: "hash".to_string(),
-                page_url: "url_1".to_string(),
+                page_url: "https://example.com/1".to_string(),
                 timestamp_ms: datetime!(2024-01-02 10:10:10),
+                bot_id: None,
+                egress: Egress::Tor,
+                title: None,
+                canonical_url: None,
             },
         );
         let mut page_worker = Box::new(MockPageWorker::new());
         page_worker.data_storage.insert(
-            "url_1".to_string(),
+            "https://example.com/1".to_string(),
             PageResult::FilePath("/some/path".to_string()),
         );
         let worker = PersistentPageWorker::new(Arc::new(persistent), page_worker);
 
+        // the cache is looked up by the canonical url
         let result = worker
-            .submit_page_generation(PageData::from_url("url_1".to_string()))
+            .submit_page_generation(PageData::from_url(
+                "https://example.com/1/?utm_source=x#top".to_string(),
+            ))
             .await?;
 
-        assert_eq!(result, PageResult::TelegramId("telegram_id".to_string()));
+        assert_eq!(
+            result.result,
+            PageResult::TelegramId("telegram_id".to_string())
+        );
+        assert_eq!(result.metadata.egress, Egress::Tor);
+        assert!(result.metadata.from_cache);
+        assert_eq!(
+            result.metadata.fetched_at,
+            Some(datetime!(2024-01-02 10:10:10))
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_cache_skipped_for_gzip() -> anyhow::Result<()> {
+        let mut persistent = MockPagePersistent::new();
+        persistent.data_storage.insert(
+            "https://example.com/1".to_string(),
+            PageInfo {
+                telegram_file_id: "telegram_id".to_string(),
+                file_hash: "hash".to_string(),
+                page_url: "https://example.com/1".to_string(),
+                timestamp_ms: datetime!(2024-01-02 10:10:10),
+                bot_id: None,
+                egress: Egress::Direct,
+                title: None,
+                canonical_url: None,
+            },
+        );
+        let mut page_worker = Box::new(MockPageWorker::new());
+        page_worker.data_storage.insert(
+            "https://example.com/1".to_string(),
+            PageResult::FilePath("/some/path".to_string()),
+        );
+        let worker = PersistentPageWorker::new(Arc::new(persistent), page_worker);
+
+        let page_data = PageData {
+            format: PageFormat::Gzip,
+            ..PageData::from_url("https://example.com/1".to_string())
+        };
+        let result = worker.submit_page_generation(page_data).await?;
+
+        assert_eq!(
+            result.result,
+            PageResult::FilePath("/some/path".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_cache_skipped_for_fresh_pages() -> anyhow::Result<()> {
+        let mut persistent = MockPagePersistent::new();
+        persistent.data_storage.insert(
+            "https://example.com/1".to_string(),
+            page_info_with_hash("hash", "telegram_id"),
+        );
+        let mut page_worker = Box::new(MockPageWorker::new());
+        page_worker.data_storage.insert(
+            "https://example.com/1".to_string(),
+            PageResult::FilePath("/some/path".to_string()),
+        );
+        let worker = PersistentPageWorker::new(Arc::new(persistent), page_worker);
+        CURRENT_TIMESTAMP.set(Some(1577836800));
+
+        let page_data = PageData {
+            fresh: true,
+            ..PageData::from_url("https://example.com/1".to_string())
+        };
+        let result = worker.submit_page_generation(page_data).await?;
+
+        assert_eq!(
+            result.result,
+            PageResult::FilePath("/some/path".to_string())
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_cache_of_other_bot_skipped() -> anyhow::Result<()> {
+        let mut persistent = MockPagePersistent::new();
+        persistent.data_storage.insert(
+            "https://example.com/1".to_string(),
+            PageInfo {
+                telegram_file_id: "telegram_id".to_string(),
+                file_hash: "hash".to_string(),
+                page_url: "https://example.com/1".to_string(),
+                timestamp_ms: datetime!(2024-01-02 10:10:10),
+                bot_id: None,
+                egress: Egress::Direct,
+                title: None,
+                canonical_url: None,
+            },
+        );
+        let mut page_worker = Box::new(MockPageWorker::new());
+        page_worker.data_storage.insert(
+            "https://example.com/1".to_string(),
+            PageResult::FilePath("/some/path".to_string()),
+        );
+        let worker = PersistentPageWorker::new(Arc::new(persistent), page_worker);
+
+        let page_data = PageData {
+            bot_id: Some("123".to_string()),
+            ..PageData::from_url("https://example.com/1".to_string())
+        };
+        let result = worker.submit_page_generation(page_data).await?;
+
+        assert_eq!(
+            result.result,
+            PageResult::FilePath("/some/path".to_string())
+        );
 
         Ok(())
     }
@@ -159,27 +318,34 @@ mod tests {
     async fn test_item_in_cache_not_expired() -> anyhow::Result<()> {
         let mut persistent = MockPagePersistent::new();
         persistent.data_storage.insert(
-            "url_1".to_string(),
+            "https://example.com/1".to_string(),
             PageInfo {
                 telegram_file_id: "telegram_id".to_string(),
                 file_hash: "hash".to_string(),
-                page_url: "url_1".to_string(),
+                page_url: "https://example.com/1".to_string(),
                 timestamp_ms: datetime!(2024-01-02 10:10:00),
+                bot_id: None,
+                egress: Egress::Direct,
+                title: None,
+                canonical_url: None,
             },
         );
         let mut page_worker = Box::new(MockPageWorker::new());
         page_worker.data_storage.insert(
-            "url_1".to_string(),
+            "https://example.com/1".to_string(),
             PageResult::FilePath("/some/path".to_string()),
         );
         let worker = PersistentPageWorker::new(Arc::new(persistent), page_worker);
         CURRENT_TIMESTAMP.set(Some(1704190510));
 
         let result = worker
-            .submit_page_generation(PageData::from_url("url_1".to_string()))
+            .submit_page_generation(PageData::from_url("https://example.com/1".to_string()))
             .await?;
 
-        assert_eq!(result, PageResult::TelegramId("telegram_id".to_string()));
+        assert_eq!(
+            result.result,
+            PageResult::TelegramId("telegram_id".to_string())
+        );
 
         Ok(())
     }
@@ -188,27 +354,34 @@ mod tests {
     async fn test_item_in_cache_expired() -> anyhow::Result<()> {
         let mut persistent = MockPagePersistent::new();
         persistent.data_storage.insert(
-            "url_1".to_string(),
+            "https://example.com/1".to_string(),
             PageInfo {
                 telegram_file_id: "telegram_id".to_string(),
                 file_hash: "hash".to_string(),
-                page_url: "url_1".to_string(),
+                page_url: "https://example.com/1".to_string(),
                 timestamp_ms: datetime!(2024-01-02 10:10:01),
+                bot_id: None,
+                egress: Egress::Direct,
+                title: None,
+                canonical_url: None,
             },
         );
         let mut page_worker = Box::new(MockPageWorker::new());
         page_worker.data_storage.insert(
-            "url_1".to_string(),
+            "https://example.com/1".to_string(),
             PageResult::FilePath("/some/path".to_string()),
         );
         let worker = PersistentPageWorker::new(Arc::new(persistent), page_worker);
         CURRENT_TIMESTAMP.set(Some(1704276910));
 
         let result = worker
-            .submit_page_generation(PageData::from_url("url_1".to_string()))
+            .submit_page_generation(PageData::from_url("https://example.com/1".to_string()))
             .await?;
 
-        assert_eq!(result, PageResult::FilePath("/some/path".to_string()));
+        assert_eq!(
+            result.result,
+            PageResult::FilePath("/some/path".to_string())
+        );
         Ok(())
     }
 
@@ -253,6 +426,10 @@ mod tests {
             file_hash: hash.to_string(),
             page_url: "page_url".to_string(),
             timestamp_ms: datetime!(2020-01-01 00:00:00),
+            bot_id: None,
+            egress: Egress::Direct,
+            title: None,
+            canonical_url: None,
         }
     }
 }
@@ -261,10 +438,9 @@ mod tests {
 mod test_impl {
     use std::collections::HashMap;
 
-    use anyhow::{bail, Error};
     use async_trait::async_trait;
 
-    use api::{PageData, PageInfo, PagePersistent, PageResult, PageWorker};
+    use api::{LoadedPage, PageData, PageError, PageInfo, PagePersistent, PageResult, PageWorker};
 
     pub struct MockPagePersistent {
         pub data_storage: HashMap<String, PageInfo>,
@@ -280,12 +456,20 @@ mod test_impl {
 
     #[async_trait]
     impl PagePersistent for MockPagePersistent {
-        async fn save(&self, _page_info: &PageInfo) -> anyhow::Result<()> {
-            bail!("Not supported")
+        async fn save(&self, _page_info: &PageInfo) -> Result<(), PageError> {
+            Err(PageError::storage("Not supported"))
         }
 
-        async fn get(&self, page_url: &str) -> anyhow::Result<Option<PageInfo>> {
-            Ok(self.data_storage.get(page_url).map(|result| result.clone()))
+        async fn get(
+            &self,
+            page_url: &str,
+            bot_id: Option<&str>,
+        ) -> Result<Option<PageInfo>, PageError> {
+            Ok(self
+                .data_storage
+                .get(page_url)
+                .filter(|page_info| page_info.bot_id.as_deref() == bot_id)
+                .cloned())
         }
     }
 
@@ -303,11 +487,15 @@ mod test_impl {
 
     #[async_trait]
     impl PageWorker for MockPageWorker {
-        async fn submit_page_generation(&self, page_data: PageData) -> anyhow::Result<PageResult> {
+        async fn submit_page_generation(
+            &self,
+            page_data: PageData,
+        ) -> Result<LoadedPage, PageError> {
             self.data_storage
                 .get(page_data.url.as_str())
-                .map(|result| result.clone())
-                .ok_or(Error::msg("Wrong result"))
+                .cloned()
+                .map(LoadedPage::from)
+                .ok_or(PageError::fetch("Wrong result"))
         }
     }
 }
@@ -1,13 +1,18 @@
-use anyhow::bail;
 use async_trait::async_trait;
 use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgRow};
 use sqlx::{PgPool, Row};
 use time::PrimitiveDateTime;
 
-use api::{PageInfo, PagePersistent};
+use api::{Egress, PageError, PageInfo, PagePersistent};
+
+use crate::api_key_persistent::create_postgres_api_keys_table;
+use crate::failure_log_persistent::create_postgres_failures_table;
+use crate::job_persistent::create_postgres_jobs_table;
+use crate::report_persistent::create_postgres_reports_table;
+use crate::settings_persistent::create_postgres_settings_table;
 
 pub struct PostgresPersistent {
-    connection: PgPool,
+    pub(crate) connection: PgPool,
 }
 
 impl PostgresPersistent {
@@ -16,7 +21,7 @@ impl PostgresPersistent {
         password: &str,
         database: &str,
         host: &str,
-    ) -> anyhow::Result<impl PagePersistent> {
+    ) -> anyhow::Result<PostgresPersistent> {
         let options = PgConnectOptions::new()
             .host(host)
             .port(5432)
@@ -40,11 +45,30 @@ async fn init_database(connection: &PgPool) -> anyhow::Result<()> {
                 page_url TEXT NOT NULL,
                 file_hash TEXT NOT NULL,
                 timestamp TIMESTAMP NOT NULL,
-                telegram_file_id TEXT NOT NULL)
+                telegram_file_id TEXT NOT NULL,
+                bot_id TEXT,
+                egress TEXT NOT NULL DEFAULT 'direct',
+                title TEXT,
+                canonical_url TEXT)
     "#,
     )
     .execute(connection)
     .await?;
+    // documents saved before the bot was stored were uploaded by the default bot
+    sqlx::query("ALTER TABLE telegram_documents ADD COLUMN IF NOT EXISTS bot_id TEXT")
+        .execute(connection)
+        .await?;
+    sqlx::query(
+        "ALTER TABLE telegram_documents ADD COLUMN IF NOT EXISTS egress TEXT NOT NULL DEFAULT 'direct'",
+    )
+    .execute(connection)
+    .await?;
+    sqlx::query("ALTER TABLE telegram_documents ADD COLUMN IF NOT EXISTS title TEXT")
+        .execute(connection)
+        .await?;
+    sqlx::query("ALTER TABLE telegram_documents ADD COLUMN IF NOT EXISTS canonical_url TEXT")
+        .execute(connection)
+        .await?;
 
     // index for the field that used for all get requests
     sqlx::query(
@@ -55,49 +79,70 @@ async fn init_database(connection: &PgPool) -> anyhow::Result<()> {
     )
     .execute(connection)
     .await?;
+    create_postgres_jobs_table(connection).await?;
+    create_postgres_api_keys_table(connection).await?;
+    create_postgres_failures_table(connection).await?;
+    create_postgres_reports_table(connection).await?;
+    create_postgres_settings_table(connection).await?;
     Ok(())
 }
 
 #[async_trait]
 impl PagePersistent for PostgresPersistent {
-    async fn save(&self, page_info: &PageInfo) -> anyhow::Result<()> {
+    async fn save(&self, page_info: &PageInfo) -> Result<(), PageError> {
         let count = sqlx::query(
             r#"
-                INSERT INTO telegram_documents (page_url, file_hash, timestamp, telegram_file_id)
-                VALUES ($1, $2, $3, $4)
+                INSERT INTO telegram_documents
+                    (page_url, file_hash, timestamp, telegram_file_id, bot_id, egress,
+                     title, canonical_url)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
                 "#,
         )
         .bind(&page_info.page_url)
         .bind(&page_info.file_hash)
         .bind(page_info.timestamp_ms)
         .bind(&page_info.telegram_file_id)
+        .bind(&page_info.bot_id)
+        .bind(page_info.egress.as_str())
+        .bind(&page_info.title)
+        .bind(&page_info.canonical_url)
         .execute(&self.connection)
-        .await?
+        .await
+        .map_err(PageError::storage)?
         .rows_affected();
 
         return if count == 1 {
             Ok(())
         } else {
-            bail!("Expected one row to be inserted, but was {}", count)
+            Err(PageError::StorageFailed(format!(
+                "Expected one row to be inserted, but was {}",
+                count
+            )))
         };
     }
 
-    async fn get(&self, page_url: &str) -> anyhow::Result<Option<PageInfo>> {
+    async fn get(
+        &self,
+        page_url: &str,
+        bot_id: Option<&str>,
+    ) -> Result<Option<PageInfo>, PageError> {
         let result = sqlx::query(
             "
             SELECT * FROM telegram_documents
-            WHERE page_url = $1
+            WHERE page_url = $1 AND bot_id IS NOT DISTINCT FROM $2
             ORDER BY timestamp DESC
             LIMIT 1
             ",
         )
         .bind(page_url)
+        .bind(bot_id)
         .fetch_optional(&self.connection)
-        .await?;
+        .await
+        .map_err(PageError::storage)?;
 
         return match result {
             None => Ok(None),
-            Some(row) => map_row(row),
+            Some(row) => map_row(row).map_err(PageError::storage),
         };
     }
 }
@@ -108,7 +153,11 @@ fn map_row(row: PgRow) -> anyhow::Result<Option<PageInfo>> {
         file_hash: row.try_get("file_hash")?,
         timestamp_ms: row.try_get::<PrimitiveDateTime, &str>("timestamp")?,
         telegram_file_id: row.try_get("telegram_file_id")?,
+        bot_id: row.try_get("bot_id")?,
+        egress: Egress::parse(row.try_get("egress")?)?,
+        title: row.try_get("title")?,
+        canonical_url: row.try_get("canonical_url")?,
     };
 
-    return Ok(Some(page_info));
+    Ok(Some(page_info))
 }
@@ -0,0 +1,158 @@
+use async_trait::async_trait;
+use sqlx::postgres::PgRow;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{PgPool, Row, SqlitePool};
+use time::PrimitiveDateTime;
+
+use api::{PageReport, ReportPersistent};
+
+use crate::postgres_persistent::PostgresPersistent;
+use crate::sqlite_persistent::SqlitePagePersistent;
+
+const INSERT_REPORT_QUERY: &str = r#"
+    INSERT INTO page_reports (page_url, chat_id, telegram_file_id, bot_id, reported_at)
+    VALUES ($1, $2, $3, $4, $5)
+    "#;
+
+const LIST_REPORTS_QUERY: &str = r#"
+    SELECT * FROM page_reports
+    ORDER BY reported_at DESC, id DESC
+    LIMIT $1
+    "#;
+
+pub(crate) async fn create_sqlite_reports_table(connection: &SqlitePool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS page_reports (
+            id INTEGER PRIMARY KEY,
+            page_url TEXT NOT NULL,
+            chat_id TEXT NOT NULL,
+            telegram_file_id TEXT,
+            bot_id TEXT,
+            reported_at INTEGER NOT NULL)
+        "#,
+    )
+    .execute(connection)
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn create_postgres_reports_table(connection: &PgPool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS page_reports (
+            id SERIAL PRIMARY KEY,
+            page_url TEXT NOT NULL,
+            chat_id TEXT NOT NULL,
+            telegram_file_id TEXT,
+            bot_id TEXT,
+            reported_at TIMESTAMP NOT NULL)
+        "#,
+    )
+    .execute(connection)
+    .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl ReportPersistent for SqlitePagePersistent {
+    async fn save_report(&self, report: &PageReport) -> anyhow::Result<()> {
+        sqlx::query(INSERT_REPORT_QUERY)
+            .bind(&report.page_url)
+            .bind(&report.chat_id)
+            .bind(&report.telegram_file_id)
+            .bind(&report.bot_id)
+            .bind(report.reported_at)
+            .execute(&self.connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_reports(&self, limit: u32) -> anyhow::Result<Vec<PageReport>> {
+        sqlx::query(LIST_REPORTS_QUERY)
+            .bind(limit as i64)
+            .fetch_all(&self.connection)
+            .await?
+            .into_iter()
+            .map(map_sqlite_row)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl ReportPersistent for PostgresPersistent {
+    async fn save_report(&self, report: &PageReport) -> anyhow::Result<()> {
+        sqlx::query(INSERT_REPORT_QUERY)
+            .bind(&report.page_url)
+            .bind(&report.chat_id)
+            .bind(&report.telegram_file_id)
+            .bind(&report.bot_id)
+            .bind(report.reported_at)
+            .execute(&self.connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_reports(&self, limit: u32) -> anyhow::Result<Vec<PageReport>> {
+        sqlx::query(LIST_REPORTS_QUERY)
+            .bind(limit as i64)
+            .fetch_all(&self.connection)
+            .await?
+            .into_iter()
+            .map(map_postgres_row)
+            .collect()
+    }
+}
+
+fn map_sqlite_row(row: SqliteRow) -> anyhow::Result<PageReport> {
+    Ok(PageReport {
+        page_url: row.try_get("page_url")?,
+        chat_id: row.try_get("chat_id")?,
+        telegram_file_id: row.try_get("telegram_file_id")?,
+        bot_id: row.try_get("bot_id")?,
+        reported_at: row.try_get::<PrimitiveDateTime, &str>("reported_at")?,
+    })
+}
+
+fn map_postgres_row(row: PgRow) -> anyhow::Result<PageReport> {
+    Ok(PageReport {
+        page_url: row.try_get("page_url")?,
+        chat_id: row.try_get("chat_id")?,
+        telegram_file_id: row.try_get("telegram_file_id")?,
+        bot_id: row.try_get("bot_id")?,
+        reported_at: row.try_get::<PrimitiveDateTime, &str>("reported_at")?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use time::macros::datetime;
+
+    use api::{PageReport, ReportPersistent};
+
+    use crate::sqlite_persistent::in_memory_db;
+
+    #[tokio::test]
+    async fn test_saves_reports() -> anyhow::Result<()> {
+        let db = in_memory_db().await?;
+        let first = PageReport {
+            page_url: "https://example.com/a".to_string(),
+            chat_id: "42".to_string(),
+            telegram_file_id: Some("file_id".to_string()),
+            bot_id: None,
+            reported_at: datetime!(2024-01-02 10:00:00),
+        };
+        let second = PageReport {
+            page_url: "https://example.com/b".to_string(),
+            telegram_file_id: None,
+            reported_at: datetime!(2024-01-02 11:00:00),
+            ..first.clone()
+        };
+        db.save_report(&first).await?;
+        db.save_report(&second).await?;
+
+        assert_eq!(db.list_reports(10).await?, vec![second.clone(), first]);
+        assert_eq!(db.list_reports(1).await?, vec![second]);
+        Ok(())
+    }
+}
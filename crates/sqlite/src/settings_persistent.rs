@@ -0,0 +1,161 @@
+use async_trait::async_trait;
+use sqlx::postgres::PgRow;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{PgPool, Row, SqlitePool};
+
+use api::i18n::Language;
+use api::{ChatSettings, ChatSettingsPersistent, PageFormat};
+
+use crate::postgres_persistent::PostgresPersistent;
+use crate::sqlite_persistent::SqlitePagePersistent;
+
+const SELECT_SETTINGS_QUERY: &str = "SELECT * FROM chat_settings WHERE chat_id = $1";
+
+const SAVE_SETTINGS_QUERY: &str = r#"
+    INSERT INTO chat_settings (chat_id, format, auto_detect, language, always_fresh)
+    VALUES ($1, $2, $3, $4, $5)
+    ON CONFLICT (chat_id) DO UPDATE SET
+        format = excluded.format,
+        auto_detect = excluded.auto_detect,
+        language = excluded.language,
+        always_fresh = excluded.always_fresh
+    "#;
+
+pub(crate) async fn create_sqlite_settings_table(connection: &SqlitePool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS chat_settings (
+            chat_id TEXT PRIMARY KEY,
+            format TEXT NOT NULL,
+            auto_detect BOOLEAN NOT NULL,
+            language TEXT,
+            always_fresh BOOLEAN NOT NULL)
+        "#,
+    )
+    .execute(connection)
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn create_postgres_settings_table(connection: &PgPool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS chat_settings (
+            chat_id TEXT PRIMARY KEY,
+            format TEXT NOT NULL,
+            auto_detect BOOLEAN NOT NULL,
+            language TEXT,
+            always_fresh BOOLEAN NOT NULL)
+        "#,
+    )
+    .execute(connection)
+    .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl ChatSettingsPersistent for SqlitePagePersistent {
+    async fn get_settings(&self, chat_id: &str) -> anyhow::Result<ChatSettings> {
+        sqlx::query(SELECT_SETTINGS_QUERY)
+            .bind(chat_id)
+            .fetch_optional(&self.connection)
+            .await?
+            .map(map_sqlite_row)
+            .unwrap_or_else(|| Ok(ChatSettings::new(chat_id)))
+    }
+
+    async fn save_settings(&self, settings: &ChatSettings) -> anyhow::Result<()> {
+        sqlx::query(SAVE_SETTINGS_QUERY)
+            .bind(&settings.chat_id)
+            .bind(settings.format.as_str())
+            .bind(settings.auto_detect)
+            .bind(settings.language.map(|language| language.code()))
+            .bind(settings.always_fresh)
+            .execute(&self.connection)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChatSettingsPersistent for PostgresPersistent {
+    async fn get_settings(&self, chat_id: &str) -> anyhow::Result<ChatSettings> {
+        sqlx::query(SELECT_SETTINGS_QUERY)
+            .bind(chat_id)
+            .fetch_optional(&self.connection)
+            .await?
+            .map(map_postgres_row)
+            .unwrap_or_else(|| Ok(ChatSettings::new(chat_id)))
+    }
+
+    async fn save_settings(&self, settings: &ChatSettings) -> anyhow::Result<()> {
+        sqlx::query(SAVE_SETTINGS_QUERY)
+            .bind(&settings.chat_id)
+            .bind(settings.format.as_str())
+            .bind(settings.auto_detect)
+            .bind(settings.language.map(|language| language.code()))
+            .bind(settings.always_fresh)
+            .execute(&self.connection)
+            .await?;
+        Ok(())
+    }
+}
+
+fn map_sqlite_row(row: SqliteRow) -> anyhow::Result<ChatSettings> {
+    Ok(ChatSettings {
+        chat_id: row.try_get("chat_id")?,
+        format: PageFormat::parse(row.try_get("format")?)?,
+        auto_detect: row.try_get("auto_detect")?,
+        language: row
+            .try_get::<Option<&str>, &str>("language")?
+            .map(Language::parse)
+            .transpose()?,
+        always_fresh: row.try_get("always_fresh")?,
+    })
+}
+
+fn map_postgres_row(row: PgRow) -> anyhow::Result<ChatSettings> {
+    Ok(ChatSettings {
+        chat_id: row.try_get("chat_id")?,
+        format: PageFormat::parse(row.try_get("format")?)?,
+        auto_detect: row.try_get("auto_detect")?,
+        language: row
+            .try_get::<Option<&str>, &str>("language")?
+            .map(Language::parse)
+            .transpose()?,
+        always_fresh: row.try_get("always_fresh")?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use api::i18n::Language;
+    use api::{ChatSettings, ChatSettingsPersistent, PageFormat};
+
+    use crate::sqlite_persistent::in_memory_db;
+
+    #[tokio::test]
+    async fn test_saves_settings() -> anyhow::Result<()> {
+        let db = in_memory_db().await?;
+        assert_eq!(db.get_settings("42").await?, ChatSettings::new("42"));
+
+        let settings = ChatSettings {
+            format: PageFormat::Gzip,
+            auto_detect: false,
+            language: Some(Language::Ru),
+            ..ChatSettings::new("42")
+        };
+        db.save_settings(&settings).await?;
+        assert_eq!(db.get_settings("42").await?, settings);
+
+        let settings = ChatSettings {
+            language: None,
+            always_fresh: true,
+            ..settings
+        };
+        db.save_settings(&settings).await?;
+        assert_eq!(db.get_settings("42").await?, settings);
+        assert_eq!(db.get_settings("43").await?, ChatSettings::new("43"));
+        Ok(())
+    }
+}
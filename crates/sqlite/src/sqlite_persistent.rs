@@ -1,13 +1,18 @@
-use anyhow::bail;
 use async_trait::async_trait;
 use sqlx::sqlite::SqliteRow;
 use sqlx::{Pool, Row, Sqlite, SqlitePool};
 use time::PrimitiveDateTime;
 
-use api::{PageInfo, PagePersistent};
+use api::{Egress, PageError, PageInfo, PagePersistent};
+
+use crate::api_key_persistent::create_sqlite_api_keys_table;
+use crate::failure_log_persistent::create_sqlite_failures_table;
+use crate::job_persistent::create_sqlite_jobs_table;
+use crate::report_persistent::create_sqlite_reports_table;
+use crate::settings_persistent::create_sqlite_settings_table;
 
 pub struct SqlitePagePersistent {
-    connection: SqlitePool,
+    pub(crate) connection: SqlitePool,
 }
 
 pub async fn init_db(path: String) -> anyhow::Result<SqlitePagePersistent> {
@@ -26,16 +31,32 @@ const CREATE_TABLE_QUERY: &str = r#"
             page_url TEXT NOT NULL,
             file_hash TEXT NOT NULL,
             timestamp INTEGER NOT NULL,
-            telegram_file_id TEXT NOT NULL)
+            telegram_file_id TEXT NOT NULL,
+            bot_id TEXT,
+            egress TEXT NOT NULL DEFAULT 'direct',
+            title TEXT,
+            canonical_url TEXT)
     "#;
 
 const INSERT_QUERY: &str = r#"
-    INSERT INTO telegram_documents (page_url, file_hash, timestamp, telegram_file_id)
-    VALUES ($1, $2, $3, $4)
+    INSERT INTO telegram_documents
+        (page_url, file_hash, timestamp, telegram_file_id, bot_id, egress, title, canonical_url)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
     "#;
 
 async fn create_table_if_exist(connection: &Pool<Sqlite>) -> anyhow::Result<()> {
     sqlx::query(CREATE_TABLE_QUERY).execute(connection).await?;
+    // documents saved before the bot was stored were uploaded by the default bot
+    add_column_if_missing(connection, "telegram_documents", "bot_id", "TEXT").await?;
+    add_column_if_missing(
+        connection,
+        "telegram_documents",
+        "egress",
+        "TEXT NOT NULL DEFAULT 'direct'",
+    )
+    .await?;
+    add_column_if_missing(connection, "telegram_documents", "title", "TEXT").await?;
+    add_column_if_missing(connection, "telegram_documents", "canonical_url", "TEXT").await?;
     // index for the field that used for all get requests
     sqlx::query(
         r#"
@@ -45,44 +66,87 @@ async fn create_table_if_exist(connection: &Pool<Sqlite>) -> anyhow::Result<()>
     )
     .execute(connection)
     .await?;
+    create_sqlite_jobs_table(connection).await?;
+    create_sqlite_api_keys_table(connection).await?;
+    create_sqlite_failures_table(connection).await?;
+    create_sqlite_reports_table(connection).await?;
+    create_sqlite_settings_table(connection).await?;
+    Ok(())
+}
+
+/// Sqlite has no `ADD COLUMN IF NOT EXISTS`
+pub(crate) async fn add_column_if_missing(
+    connection: &Pool<Sqlite>,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> anyhow::Result<()> {
+    let exists: bool =
+        sqlx::query_scalar("SELECT COUNT(*) > 0 FROM pragma_table_info($1) WHERE name = $2")
+            .bind(table)
+            .bind(column)
+            .fetch_one(connection)
+            .await?;
+    if !exists {
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .execute(connection)
+        .await?;
+    }
     Ok(())
 }
 
 #[async_trait]
 impl PagePersistent for SqlitePagePersistent {
-    async fn save(&self, page_info: &PageInfo) -> anyhow::Result<()> {
+    async fn save(&self, page_info: &PageInfo) -> Result<(), PageError> {
         let count = sqlx::query(INSERT_QUERY)
             .bind(&page_info.page_url)
             .bind(&page_info.file_hash)
             .bind(page_info.timestamp_ms)
             .bind(&page_info.telegram_file_id)
+            .bind(&page_info.bot_id)
+            .bind(page_info.egress.as_str())
+            .bind(&page_info.title)
+            .bind(&page_info.canonical_url)
             .execute(&self.connection)
-            .await?
+            .await
+            .map_err(PageError::storage)?
             .rows_affected();
 
         return if count == 1 {
             Ok(())
         } else {
-            bail!("Expected one row to be inserted, but was {}", count)
+            Err(PageError::StorageFailed(format!(
+                "Expected one row to be inserted, but was {}",
+                count
+            )))
         };
     }
 
-    async fn get(&self, page_url: &str) -> anyhow::Result<Option<PageInfo>> {
+    async fn get(
+        &self,
+        page_url: &str,
+        bot_id: Option<&str>,
+    ) -> Result<Option<PageInfo>, PageError> {
         let result = sqlx::query(
             r#"
             SELECT * FROM telegram_documents
-            WHERE page_url = $1
+            WHERE page_url = $1 AND bot_id IS $2
             ORDER BY timestamp DESC
             LIMIT 1
             "#,
         )
         .bind(page_url)
+        .bind(bot_id)
         .fetch_optional(&self.connection)
-        .await?;
+        .await
+        .map_err(PageError::storage)?;
 
         return match result {
             None => Ok(None),
-            Some(row) => map_row(row),
+            Some(row) => map_row(row).map_err(PageError::storage),
         };
     }
 }
@@ -93,19 +157,24 @@ fn map_row(row: SqliteRow) -> anyhow::Result<Option<PageInfo>> {
         file_hash: row.try_get(2)?,
         timestamp_ms: row.try_get::<PrimitiveDateTime, usize>(3)?,
         telegram_file_id: row.try_get(4)?,
+        bot_id: row.try_get("bot_id")?,
+        egress: Egress::parse(row.try_get("egress")?)?,
+        title: row.try_get("title")?,
+        canonical_url: row.try_get("canonical_url")?,
     };
 
-    return Ok(Some(page_info));
+    Ok(Some(page_info))
 }
 
 #[cfg(test)]
+#[allow(clippy::zero_prefixed_literal)]
 mod test {
     use sqlx::types::time::{Date, Time};
     use time::{Month, PrimitiveDateTime};
 
-    use api::{PageInfo, PagePersistent};
+    use api::{Egress, PageInfo, PagePersistent};
 
-    use crate::sqlite_persistent::init_db;
+    use crate::sqlite_persistent::{create_table_if_exist, init_db, SqlitePagePersistent};
 
     #[sqlx::test]
     async fn test_save_and_get_record() -> anyhow::Result<()> {
@@ -118,9 +187,13 @@ mod test {
                 Date::from_calendar_date(2024, Month::January, 02)?,
                 Time::from_hms(10, 10, 10)?,
             ),
+            bot_id: None,
+            egress: Egress::Direct,
+            title: None,
+            canonical_url: None,
         };
         db.save(&page_info).await?;
-        let result = db.get("url").await?;
+        let result = db.get("url", None).await?;
 
         assert_eq!(Some(page_info), result);
 
@@ -142,7 +215,7 @@ mod test {
         ));
         db.save(&page_info_second).await?;
 
-        let result = db.get("url").await?;
+        let result = db.get("url", None).await?;
 
         assert_eq!(Some(page_info_second), result);
 
@@ -155,6 +228,92 @@ mod test {
             file_hash: "file_hash".to_string(),
             page_url: "url".to_string(),
             timestamp_ms: date,
+            bot_id: None,
+            egress: Egress::Direct,
+            title: None,
+            canonical_url: None,
         }
     }
+
+    #[sqlx::test]
+    async fn test_documents_stored_per_bot() -> anyhow::Result<()> {
+        let db = init_db("sqlite::memory:".to_string()).await?;
+        let date = PrimitiveDateTime::new(
+            Date::from_calendar_date(2024, Month::January, 2)?,
+            Time::from_hms(10, 10, 10)?,
+        );
+        let other_bot_page = PageInfo {
+            telegram_file_id: "other_bot_file_id".to_string(),
+            bot_id: Some("123".to_string()),
+            ..create_page_info(date)
+        };
+        db.save(&create_page_info(date)).await?;
+        db.save(&other_bot_page).await?;
+
+        assert_eq!(db.get("url", None).await?, Some(create_page_info(date)));
+        assert_eq!(db.get("url", Some("123")).await?, Some(other_bot_page));
+        assert_eq!(db.get("url", Some("456")).await?, None);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_egress_stored() -> anyhow::Result<()> {
+        let db = init_db("sqlite::memory:".to_string()).await?;
+        let page_info = PageInfo {
+            egress: Egress::Tor,
+            ..create_page_info(PrimitiveDateTime::new(
+                Date::from_calendar_date(2024, Month::January, 2)?,
+                Time::from_hms(10, 10, 10)?,
+            ))
+        };
+        db.save(&page_info).await?;
+
+        assert_eq!(db.get("url", None).await?, Some(page_info));
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_page_meta_stored() -> anyhow::Result<()> {
+        let db = init_db("sqlite::memory:".to_string()).await?;
+        let page_info = PageInfo {
+            title: Some("Example page".to_string()),
+            canonical_url: Some("https://example.com/page".to_string()),
+            ..create_page_info(PrimitiveDateTime::new(
+                Date::from_calendar_date(2024, Month::January, 2)?,
+                Time::from_hms(10, 10, 10)?,
+            ))
+        };
+        db.save(&page_info).await?;
+
+        assert_eq!(db.get("url", None).await?, Some(page_info));
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_add_bot_to_existing_documents() -> anyhow::Result<()> {
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await?;
+        sqlx::query(
+            r#"
+            CREATE TABLE telegram_documents (
+                id INTEGER PRIMARY KEY,
+                page_url TEXT NOT NULL,
+                file_hash TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                telegram_file_id TEXT NOT NULL)
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+        create_table_if_exist(&pool).await?;
+        create_table_if_exist(&pool).await?;
+
+        let db = SqlitePagePersistent { connection: pool };
+        let date = PrimitiveDateTime::new(
+            Date::from_calendar_date(2024, Month::January, 2)?,
+            Time::from_hms(10, 10, 10)?,
+        );
+        db.save(&create_page_info(date)).await?;
+        assert_eq!(db.get("url", None).await?, Some(create_page_info(date)));
+        Ok(())
+    }
 }
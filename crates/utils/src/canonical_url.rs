@@ -0,0 +1,99 @@
+use url::Url;
+
+/// Query parameters added by trackers, they don't change the page
+const TRACKING_PARAMETERS: [&str; 10] = [
+    "fbclid", "gclid", "dclid", "yclid", "msclkid", "igshid", "mc_cid", "mc_eid", "_ga", "_hsenc",
+];
+
+/// Canonical form of the url used as the key of the cache and of the queue,
+/// so the same page requested by different links is loaded once.
+///
+/// The scheme defaults to https, the host is lowercased and converted to punycode,
+/// the default port, the fragment, tracking parameters and the trailing slash are removed.
+/// Urls that can't be parsed are returned as is.
+pub fn canonicalize(url: &str) -> String {
+    let url = url.trim();
+    let parsed = if url.contains("://") {
+        Url::parse(url)
+    } else {
+        Url::parse(&format!("https://{}", url))
+    };
+    let Ok(mut parsed) = parsed else {
+        return url.to_string();
+    };
+
+    parsed.set_fragment(None);
+    let query: Vec<(String, String)> = parsed
+        .query_pairs()
+        .filter(|(name, _)| !is_tracking_parameter(name))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if query.is_empty() {
+        parsed.set_query(None);
+    } else {
+        parsed.query_pairs_mut().clear().extend_pairs(query);
+    }
+    let path = parsed.path();
+    if path.len() > 1 && path.ends_with('/') {
+        let path = path.trim_end_matches('/').to_string();
+        parsed.set_path(&path);
+    }
+    if let Some(host) = parsed.host_str() {
+        let host = host.trim_end_matches('.').to_string();
+        let _ = parsed.set_host(Some(&host));
+    }
+    parsed.to_string()
+}
+
+fn is_tracking_parameter(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.starts_with("utm_") || TRACKING_PARAMETERS.contains(&name.as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::canonical_url::canonicalize;
+
+    #[test]
+    fn test_same_page_links() {
+        let expected = "https://example.com/a";
+        for url in [
+            "example.com/a",
+            "https://example.com/a/",
+            "https://EXAMPLE.com:443/a",
+            "https://example.com./a",
+            "https://example.com/a?utm_source=x&utm_medium=y",
+            "https://example.com/a#section",
+            " https://example.com/a?fbclid=123#top ",
+        ] {
+            assert_eq!(canonicalize(url), expected, "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_keeps_meaningful_parts() {
+        assert_eq!(canonicalize("http://example.com/a"), "http://example.com/a");
+        assert_eq!(canonicalize("https://example.com"), "https://example.com/");
+        assert_eq!(
+            canonicalize("https://example.com/search?q=rust&utm_source=x&page=2"),
+            "https://example.com/search?q=rust&page=2"
+        );
+        assert_eq!(
+            canonicalize("https://example.com:8080/a"),
+            "https://example.com:8080/a"
+        );
+    }
+
+    #[test]
+    fn test_idna_host() {
+        assert_eq!(
+            canonicalize("https://Пример.рф/страница"),
+            "https://xn--e1afmkfd.xn--p1ai/%D1%81%D1%82%D1%80%D0%B0%D0%BD%D0%B8%D1%86%D0%B0"
+        );
+    }
+
+    #[test]
+    fn test_not_a_url() {
+        assert_eq!(canonicalize("not a url"), "not a url");
+    }
+}
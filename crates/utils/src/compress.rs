@@ -0,0 +1,89 @@
+use std::fs::File;
+use std::io::{Read, Write};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+/// Compresses the file with gzip next to it, returns the path of the `.gz` file.
+pub fn gzip_file(path: &str) -> anyhow::Result<String> {
+    let compressed_path = format!("{}.gz", path);
+    let mut source = File::open(path)?;
+    let target = File::create(&compressed_path)?;
+    let mut encoder = GzEncoder::new(target, Compression::default());
+    std::io::copy(&mut source, &mut encoder)?;
+    encoder.finish()?;
+    Ok(compressed_path)
+}
+
+/// Splits the file into parts of at most `part_size` bytes named `<path>.001`, `<path>.002`...
+/// Joined back together in order the parts give the original file.
+pub fn split_file(path: &str, part_size: u64) -> anyhow::Result<Vec<String>> {
+    let mut source = File::open(path)?;
+    let mut parts = Vec::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let part_path = format!("{}.{:03}", path, parts.len() + 1);
+        let mut target = File::create(&part_path)?;
+        let mut written = 0;
+        while written < part_size {
+            let size = (part_size - written).min(buffer.len() as u64) as usize;
+            let read = source.read(&mut buffer[..size])?;
+            if read == 0 {
+                break;
+            }
+            target.write_all(&buffer[..read])?;
+            written += read as u64;
+        }
+        if written == 0 {
+            std::fs::remove_file(&part_path)?;
+            return Ok(parts);
+        }
+        parts.push(part_path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use tempfile::tempdir;
+
+    use crate::compress::{gzip_file, split_file};
+
+    #[test]
+    fn test_gzip_file() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("page.html");
+        std::fs::write(&path, "<html>".repeat(100))?;
+
+        let compressed = gzip_file(path.to_str().unwrap())?;
+
+        assert!(compressed.ends_with("page.html.gz"));
+        let mut content = String::new();
+        GzDecoder::new(std::fs::File::open(&compressed)?).read_to_string(&mut content)?;
+        assert_eq!(content, "<html>".repeat(100));
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_file() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("page.html.gz");
+        let content: Vec<u8> = (0..250).collect();
+        std::fs::write(&path, &content)?;
+
+        let parts = split_file(path.to_str().unwrap(), 100)?;
+
+        assert_eq!(parts.len(), 3);
+        assert!(parts[0].ends_with("page.html.gz.001"));
+        assert!(parts[2].ends_with("page.html.gz.003"));
+        let mut joined = Vec::new();
+        for part in &parts {
+            joined.extend(std::fs::read(part)?);
+        }
+        assert_eq!(joined, content);
+        assert_eq!(split_file(path.to_str().unwrap(), 250)?.len(), 1);
+        Ok(())
+    }
+}
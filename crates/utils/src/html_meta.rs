@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::io::Read;
+
+/// Only the beginning of the file is read. Singlefile inlines styles into the head,
+/// so it can still be large.
+const MAX_HEAD_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Title and canonical url of a saved page.
+#[derive(Debug, PartialEq, Default, Clone)]
+pub struct HtmlMeta {
+    pub title: Option<String>,
+    pub canonical_url: Option<String>,
+}
+
+pub fn read_html_meta(path: &str) -> anyhow::Result<HtmlMeta> {
+    let mut head = Vec::new();
+    File::open(path)?
+        .take(MAX_HEAD_SIZE)
+        .read_to_end(&mut head)?;
+    Ok(parse_html_meta(&String::from_utf8_lossy(&head)))
+}
+
+/// Looks up the `<title>` and `<link rel="canonical">` tags in the head of the page.
+/// Canonical urls that are not absolute http urls are ignored.
+pub fn parse_html_meta(html: &str) -> HtmlMeta {
+    // ascii lowercasing keeps the byte offsets, so positions are shared by both strings
+    let lowercase = html.to_ascii_lowercase();
+    let head_end = lowercase.find("</head").unwrap_or(html.len());
+    HtmlMeta {
+        title: find_title(&html[..head_end], &lowercase[..head_end]),
+        canonical_url: find_canonical_url(&html[..head_end], &lowercase[..head_end]),
+    }
+}
+
+fn find_title(html: &str, lowercase: &str) -> Option<String> {
+    let tag_start = lowercase.find("<title")?;
+    let content_start = tag_start + lowercase[tag_start..].find('>')? + 1;
+    let content_end = content_start + lowercase[content_start..].find("</title")?;
+    let title = decode_entities(&html[content_start..content_end])
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!title.is_empty()).then_some(title)
+}
+
+fn find_canonical_url(html: &str, lowercase: &str) -> Option<String> {
+    let mut position = 0;
+    while let Some(start) = lowercase[position..].find("<link") {
+        let tag_start = position + start + "<link".len();
+        let tag_end = tag_start + lowercase[tag_start..].find('>')?;
+        position = tag_end;
+        let attributes = parse_attributes(&html[tag_start..tag_end]);
+        let is_canonical = attributes.iter().any(|(name, value)| {
+            name == "rel"
+                && value
+                    .split_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case("canonical"))
+        });
+        if !is_canonical {
+            continue;
+        }
+        let href = attributes
+            .into_iter()
+            .find(|(name, _)| name == "href")
+            .map(|(_, href)| href.trim().to_string())?;
+        return (href.starts_with("https://") || href.starts_with("http://")).then_some(href);
+    }
+    None
+}
+
+/// Attributes of a tag with lowercase names and decoded values
+fn parse_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = tag;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        if name_end == 0 {
+            return attributes;
+        }
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+        let Some(value_start) = rest.strip_prefix('=') else {
+            attributes.push((name, String::new()));
+            continue;
+        };
+        let value_start = value_start.trim_start();
+        let (value, value_rest) = match value_start.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let value = &value_start[1..];
+                let end = value.find(quote).unwrap_or(value.len());
+                (&value[..end], value.get(end + 1..).unwrap_or_default())
+            }
+            _ => {
+                let end = value_start
+                    .find(char::is_whitespace)
+                    .unwrap_or(value_start.len());
+                (&value_start[..end], &value_start[end..])
+            }
+        };
+        attributes.push((name, decode_entities(value)));
+        rest = value_rest;
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end]).map(|c| (c, end)));
+        match entity {
+            Some((c, end)) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = entity.strip_prefix('#')?;
+            let code = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::html_meta::{parse_html_meta, HtmlMeta};
+
+    #[test]
+    fn test_parse_html_meta() {
+        let html = r#"<!DOCTYPE html><html><HEAD><meta charset="utf-8">
+            <Title>
+              Rust &amp; Tokio &#8212; &quot;docs&quot;
+            </Title>
+            <link rel="stylesheet" href="style.css">
+            <link href='https://example.com/docs' REL="Canonical"/>
+            </head><body><title>Not this</title></body></html>"#;
+
+        assert_eq!(
+            parse_html_meta(html),
+            HtmlMeta {
+                title: Some("Rust & Tokio \u{2014} \"docs\"".to_string()),
+                canonical_url: Some("https://example.com/docs".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_missing_meta() {
+        assert_eq!(parse_html_meta("<p>text</p>"), HtmlMeta::default());
+        assert_eq!(
+            parse_html_meta("<title> </title><link rel=canonical href=/docs>"),
+            HtmlMeta::default()
+        );
+        assert_eq!(
+            parse_html_meta("<head></head><title>Body title</title>").title,
+            None
+        );
+    }
+}
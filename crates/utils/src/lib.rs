@@ -1 +1,5 @@
+pub mod canonical_url;
+pub mod compress;
 pub mod hash;
+pub mod html_meta;
+pub mod signature;
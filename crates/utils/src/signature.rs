@@ -0,0 +1,178 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use url::Url;
+
+pub const SIGNATURE_HEADER: &str = "x-signature";
+pub const TIMESTAMP_HEADER: &str = "x-timestamp";
+/// Id of the API key the request is signed with, requests without it use the shared secret
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// How far the timestamp of a signed request can be from the current time.
+/// Requests captured by someone else can't be replayed after that.
+pub const MAX_CLOCK_SKEW_SECONDS: u64 = 300;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signs a request with the shared secret, returns the headers to add to the request.
+/// The query is signed as well, so it can't be changed in a captured request.
+pub fn sign_request(
+    secret: &str,
+    method: &str,
+    path_and_query: &str,
+    body: &[u8],
+) -> [(&'static str, String); 2] {
+    let timestamp = unix_time();
+    let signature = signature(secret, timestamp, method, path_and_query, body);
+    [
+        (TIMESTAMP_HEADER, timestamp.to_string()),
+        (SIGNATURE_HEADER, signature),
+    ]
+}
+
+/// Checks that the request was signed with the shared secret recently.
+pub fn verify_request(
+    secret: &str,
+    timestamp: u64,
+    signature: &str,
+    method: &str,
+    path_and_query: &str,
+    body: &[u8],
+) -> bool {
+    if unix_time().abs_diff(timestamp) > MAX_CLOCK_SKEW_SECONDS {
+        return false;
+    }
+    let Ok(signature) = BASE64_STANDARD.decode(signature) else {
+        return false;
+    };
+    request_mac(secret, timestamp, method, path_and_query, body)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+/// Path of the url with its query, the part of the url that is signed
+pub fn path_and_query(url: &Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    }
+}
+
+fn signature(
+    secret: &str,
+    timestamp: u64,
+    method: &str,
+    path_and_query: &str,
+    body: &[u8],
+) -> String {
+    let mac = request_mac(secret, timestamp, method, path_and_query, body);
+    BASE64_STANDARD.encode(mac.finalize().into_bytes())
+}
+
+fn request_mac(
+    secret: &str,
+    timestamp: u64,
+    method: &str,
+    path_and_query: &str,
+    body: &[u8],
+) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{}\n{}\n{}\n", timestamp, method, path_and_query).as_bytes());
+    mac.update(body);
+    mac
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use url::Url;
+
+    use crate::signature::{
+        path_and_query, sign_request, signature, unix_time, verify_request, MAX_CLOCK_SKEW_SECONDS,
+    };
+
+    fn sign(secret: &str, body: &[u8]) -> (u64, String) {
+        let [(_, timestamp), (_, signature)] = sign_request(secret, "POST", "/v1/jobs", body);
+        (timestamp.parse().unwrap(), signature)
+    }
+
+    #[test]
+    fn test_verify_signed_request() {
+        let (timestamp, signature) = sign("secret", b"body");
+
+        assert!(verify_request(
+            "secret", timestamp, &signature, "POST", "/v1/jobs", b"body"
+        ));
+        assert!(!verify_request(
+            "other", timestamp, &signature, "POST", "/v1/jobs", b"body"
+        ));
+        assert!(!verify_request(
+            "secret", timestamp, &signature, "POST", "/v1/jobs", b"other"
+        ));
+        assert!(!verify_request(
+            "secret", timestamp, &signature, "DELETE", "/v1/jobs", b"body"
+        ));
+        assert!(!verify_request(
+            "secret",
+            timestamp + 1,
+            &signature,
+            "POST",
+            "/v1/jobs",
+            b"body"
+        ));
+        assert!(!verify_request(
+            "secret",
+            timestamp,
+            "not base64",
+            "POST",
+            "/v1/jobs",
+            b"body"
+        ));
+    }
+
+    #[test]
+    fn test_reject_old_request() {
+        let timestamp = unix_time() - MAX_CLOCK_SKEW_SECONDS - 1;
+        let signature = signature("secret", timestamp, "POST", "/v1/jobs", b"body");
+
+        assert!(!verify_request(
+            "secret", timestamp, &signature, "POST", "/v1/jobs", b"body"
+        ));
+    }
+
+    #[test]
+    fn test_query_is_signed() -> anyhow::Result<()> {
+        let url = Url::parse("http://backend/v1/admin/failures?url=example.com&limit=50")?;
+        let signed = path_and_query(&url);
+        assert_eq!(signed, "/v1/admin/failures?url=example.com&limit=50");
+        assert_eq!(
+            path_and_query(&Url::parse("http://backend/v1/jobs")?),
+            "/v1/jobs"
+        );
+
+        let [(_, timestamp), (_, signature)] = sign_request("secret", "GET", &signed, b"");
+        let timestamp = timestamp.parse()?;
+        assert!(verify_request(
+            "secret", timestamp, &signature, "GET", &signed, b""
+        ));
+        assert!(!verify_request(
+            "secret",
+            timestamp,
+            &signature,
+            "GET",
+            "/v1/admin/failures?url=example.com&limit=1000",
+            b""
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,3 @@
+#!/bin/sh
+
+/usr/local/bin/render-worker --work-dir /workdir --api-url "${API_URL}"
@@ -1,13 +1,25 @@
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{anyhow, bail, Context};
 use clap::Parser;
 
-use api::{PagePersistent, PageUploader, PageWorker};
+use api::{PageUploader, PageWorker, StatusReporter};
+use botbackend::archive::WaybackClient;
+use botbackend::layers::PageMetrics;
 use botbackend::parallel_page_worker::ParallelPageWorker;
-use rest_backend::{init, RestBackend};
-use sqlite::persistent_page_worker::PersistentPageWorker;
+use botbackend::pipeline::{PageLayer, PagePipeline};
+use botbackend::proxy_pool::ProxyPool;
+use botbackend::tor::TorConfig;
+use botbackend::url_policy::UrlPolicy;
+use proto::bot_registry::{bot_from_env, BotRegistry};
+use proto::document::UploadConfig;
+use proto::status_reporter::TeloxideStatusReporter;
+use rest_backend::{
+    init, QueueConfig, RenderWorkerPool, RestBackend, RetryPolicy, ServerConfig, Storage,
+};
+use sqlite::persistent_page_worker::CacheLayer;
 use sqlite::postgres_persistent::PostgresPersistent;
 use sqlite::sqlite_persistent::init_db;
 
@@ -17,38 +29,141 @@ use crate::teloxide_bot::TeloxidePageUploader;
 mod backend_args;
 mod teloxide_bot;
 
+/// How long a render worker keeps a page without sending heartbeats
+const RENDER_LEASE: Duration = Duration::from_secs(60);
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let backend_args = BackendArgs::parse();
-    let persistence = create_persistent(&backend_args).await?;
-    let config = RestBackend::new(
-        8080,
-        create_loader(
-            persistence.clone(),
-            &backend_args.singlefile_cli,
-            &backend_args.work_dir,
-        ),
-        create_uploader(),
-        persistence,
-    );
+    let storage = create_storage(&backend_args).await?;
+    // render workers decide themselves whether they can reach onion links
+    let url_policy = UrlPolicy::new(
+        backend_args.allowed_hosts.clone(),
+        backend_args.denied_hosts.clone(),
+    )
+    .with_onion(backend_args.tor_proxy.is_some() || backend_args.remote_workers);
+    // bots of the API keys share the backend with the default one
+    let bot = bot_from_env(backend_args.telegram_api_url.as_deref())?;
+    let bots = Arc::new(BotRegistry::new(bot).with_api_keys(storage.api_keys.clone()));
+    let upload_config = UploadConfig::new(
+        backend_args.telegram_api_url.as_deref(),
+        backend_args.telegram_local_files,
+    )?;
+    let render_workers = backend_args
+        .remote_workers
+        .then(|| RenderWorkerPool::new(backend_args.work_dir.clone(), RENDER_LEASE));
+    let network_page_worker: Box<dyn PageWorker> = match render_workers.clone() {
+        Some(pool) => Box::new(pool),
+        None => Box::new(create_network_worker(&backend_args, url_policy.clone())?),
+    };
+    let page_metrics = backend_args
+        .page_layers
+        .contains(&PageLayer::Metrics)
+        .then(|| Arc::new(PageMetrics::default()));
+    let page_worker = create_page_worker(
+        &backend_args,
+        network_page_worker,
+        url_policy.clone(),
+        &storage,
+        page_metrics.clone(),
+    )?;
+    let server_config = ServerConfig {
+        port: 8080,
+        api_secret: backend_args.api_secret.clone(),
+    };
+    let mut config = RestBackend::new(
+        server_config,
+        page_worker,
+        create_uploader(bots.clone(), upload_config),
+        create_status_reporter(bots),
+        storage,
+        create_queue_config(&backend_args),
+    )
+    .with_url_policy(url_policy);
+    if let Some(pool) = render_workers {
+        config = config.with_render_workers(pool);
+    }
+    if let Some(metrics) = page_metrics {
+        config = config.with_page_metrics(metrics);
+    }
     init(config).await
 }
 
-fn create_loader(
-    cache: Arc<dyn PagePersistent>,
-    singlefile_cli: &str,
-    work_dir: &str,
-) -> impl PageWorker {
-    let network_page_worker =
-        ParallelPageWorker::new(work_dir.to_string(), singlefile_cli.to_string());
-    PersistentPageWorker::new(cache, Box::new(network_page_worker))
+fn create_network_worker(
+    args: &BackendArgs,
+    url_policy: UrlPolicy,
+) -> anyhow::Result<ParallelPageWorker> {
+    let singlefile_cli = args
+        .singlefile_cli
+        .clone()
+        .context("SINGLEFILE_CLI env variable must be set unless remote workers are used")?;
+    let proxies = ProxyPool::new(args.proxies.clone(), args.proxy_routes.clone())?;
+    let mut worker = ParallelPageWorker::new(args.work_dir.clone(), singlefile_cli)
+        .with_url_policy(url_policy)
+        .with_proxies(proxies);
+    if let Some(tor_proxy) = args.tor_proxy.as_deref() {
+        worker = worker.with_tor(TorConfig::new(
+            tor_proxy,
+            args.tor_domains.clone(),
+            Duration::from_secs(args.tor_timeout_seconds),
+        )?);
+    }
+    Ok(worker)
+}
+
+/// Pages are loaded through the layers of `page_layers`. Archives and the older cached
+/// copy are tried when the live page fails.
+fn create_page_worker(
+    args: &BackendArgs,
+    network_page_worker: Box<dyn PageWorker>,
+    url_policy: UrlPolicy,
+    storage: &Storage,
+    page_metrics: Option<Arc<PageMetrics>>,
+) -> anyhow::Result<Box<dyn PageWorker>> {
+    let mut pipeline = PagePipeline::new(args.page_layers.clone())
+        .with_retry(
+            args.max_attempts,
+            Duration::from_secs(args.retry_delay_seconds),
+        )
+        .with_cache(CacheLayer::new(storage.pages.clone()))
+        .with_failure_log(storage.failures.clone())
+        .with_url_policy(url_policy)
+        .with_stale_cache(storage.pages.clone());
+    if let Some(seconds) = args.page_timeout_seconds {
+        pipeline = pipeline.with_timeout(Duration::from_secs(seconds));
+    }
+    if let Some(max_pages) = args.max_parallel_pages {
+        pipeline = pipeline.with_concurrency_limit(max_pages);
+    }
+    if let Some(metrics) = page_metrics {
+        pipeline = pipeline.with_metrics(metrics);
+    }
+    if !args.no_archive_fallback {
+        pipeline = pipeline.with_archive(Box::new(WaybackClient::new(&args.wayback_url)?));
+    }
+    Ok(pipeline.service(network_page_worker))
+}
+
+fn create_uploader(bots: Arc<BotRegistry>, upload_config: UploadConfig) -> impl PageUploader {
+    TeloxidePageUploader::new(bots).with_upload_config(upload_config)
 }
 
-fn create_uploader() -> impl PageUploader {
-    TeloxidePageUploader::new_from_env()
+fn create_status_reporter(bots: Arc<BotRegistry>) -> impl StatusReporter {
+    TeloxideStatusReporter::from_registry(bots)
 }
 
-async fn create_persistent(args: &BackendArgs) -> anyhow::Result<Arc<dyn PagePersistent>> {
+fn create_queue_config(args: &BackendArgs) -> QueueConfig {
+    QueueConfig {
+        retry_policy: RetryPolicy {
+            max_attempts: args.max_attempts,
+            base_delay: Duration::from_secs(args.retry_delay_seconds),
+            ..RetryPolicy::default()
+        },
+        ..QueueConfig::default()
+    }
+}
+
+async fn create_storage(args: &BackendArgs) -> anyhow::Result<Storage> {
     if let Some(url) = args.pg_url.as_ref() {
         create_postgres(url, args).await
     } else {
@@ -56,10 +171,7 @@ async fn create_persistent(args: &BackendArgs) -> anyhow::Result<Arc<dyn PagePer
     }
 }
 
-async fn create_postgres(
-    host: &str,
-    args: &BackendArgs,
-) -> anyhow::Result<Arc<dyn PagePersistent>> {
+async fn create_postgres(host: &str, args: &BackendArgs) -> anyhow::Result<Storage> {
     let password = args
         .pg_password
         .as_ref()
@@ -74,13 +186,13 @@ async fn create_postgres(
         .context("Database must be set when postgres is used")?;
 
     let persistent = PostgresPersistent::connect(username, password, db, host).await?;
-    Ok(Arc::new(persistent))
+    Ok(persistent.into())
 }
 
-async fn create_sqlite(args: &BackendArgs) -> anyhow::Result<Arc<dyn PagePersistent>> {
+async fn create_sqlite(args: &BackendArgs) -> anyhow::Result<Storage> {
     let work_dir = create_file_if_needed(args.work_dir.as_ref(), "/bot_db.db").await?;
     let persistent = init_db(work_dir.to_string()).await?;
-    Ok(Arc::new(persistent))
+    Ok(persistent.into())
 }
 
 /// Check if file with the file name exist in the given folder.
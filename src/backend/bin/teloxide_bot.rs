@@ -1,44 +1,55 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
-use teloxide::prelude::Requester;
-use teloxide::types::{FileId, InputFile};
-use teloxide::Bot;
 
-use api::{PageResult, PageUploader};
+use api::{PageError, PageMetadata, PageResult, PageUploader, Recipient};
+use proto::bot_registry::BotRegistry;
+use proto::document::{send_page_document, UploadConfig};
 
 pub(crate) struct TeloxidePageUploader {
-    bot: Bot,
+    bots: Arc<BotRegistry>,
+    upload_config: UploadConfig,
 }
 
 impl TeloxidePageUploader {
-    pub(crate) fn new_from_env() -> Self {
+    pub(crate) fn new(bots: Arc<BotRegistry>) -> Self {
         TeloxidePageUploader {
-            bot: Bot::from_env(),
+            bots,
+            upload_config: UploadConfig::default(),
         }
     }
+
+    pub(crate) fn with_upload_config(mut self, upload_config: UploadConfig) -> Self {
+        self.upload_config = upload_config;
+        self
+    }
 }
 
 #[async_trait]
 impl PageUploader for TeloxidePageUploader {
     async fn send_page(
         &self,
-        chat_id: &str,
+        recipient: &Recipient,
+        job_id: &str,
+        page_url: &str,
         page_result: &PageResult,
-    ) -> anyhow::Result<Option<String>> {
-        println!("Sending page to {}", chat_id);
-        let input_file = to_input_file(page_result);
-        let result = self
-            .bot
-            .send_document(chat_id.to_string(), input_file)
-            .await?
-            .document()
-            .map(|document| document.file.id.to_string());
-        return Ok(result);
-    }
-}
-
-fn to_input_file(page_result: &PageResult) -> InputFile {
-    match page_result {
-        PageResult::FilePath(path) => InputFile::file(path),
-        PageResult::TelegramId(id) => InputFile::file_id(FileId::from(id.to_string())),
+        metadata: &PageMetadata,
+    ) -> Result<Option<String>, PageError> {
+        println!("Sending page to {}", recipient.chat_id);
+        let bot = self
+            .bots
+            .resolve(recipient.bot_id.as_deref())
+            .await
+            .map_err(PageError::upload)?;
+        send_page_document(
+            &bot,
+            recipient,
+            job_id,
+            page_url,
+            page_result,
+            metadata,
+            self.upload_config,
+        )
+        .await
     }
 }
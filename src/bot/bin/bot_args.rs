@@ -1,5 +1,7 @@
 use clap::Parser;
 
+use botbackend::pipeline::PageLayer;
+
 #[derive(Parser)]
 #[command(about, long_about = None)]
 pub(crate) struct BotArgs {
@@ -7,6 +9,10 @@ pub(crate) struct BotArgs {
     #[arg(long, value_name = "URL")]
     pub(crate) backend_url: Option<String>,
 
+    /// Secret shared with the backend to sign requests. Must be set for distributed mode
+    #[arg(long, env, value_name = "SECRET")]
+    pub(crate) api_secret: Option<String>,
+
     /// Path to singlefile binary
     #[arg(env)]
     pub(crate) singlefile_cli: Option<String>,
@@ -15,7 +21,86 @@ pub(crate) struct BotArgs {
     #[arg(long, value_name = "PATH")]
     pub(crate) work_dir: Option<String>,
 
+    /// How many times a page is loaded before the request fails in the standalone mode
+    #[arg(long, value_name = "COUNT", default_value_t = 3)]
+    pub(crate) max_attempts: u32,
+
+    /// Delay before the first retry of a failed page, doubled for every next attempt
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    pub(crate) retry_delay_seconds: u64,
+
+    /// How many pages are loaded at the same time in the standalone mode, not limited if not set
+    #[arg(long, value_name = "COUNT")]
+    pub(crate) max_parallel_pages: Option<usize>,
+
+    /// How long loading of a page may take in the standalone mode
+    #[arg(long, value_name = "SECONDS")]
+    pub(crate) page_timeout_seconds: Option<u64>,
+
+    /// Layers pages are loaded through in the standalone mode, the first one sees the requests
+    /// first: retry, timeout, cache, failure-log, concurrency-limit and url-policy
+    #[arg(
+        long,
+        env,
+        value_name = "LAYERS",
+        value_delimiter = ',',
+        value_parser = PageLayer::parse,
+        default_value = "concurrency-limit,retry,timeout,url-policy"
+    )]
+    pub(crate) page_layers: Vec<PageLayer>,
+
     /// Throttling timeout for load page request coming from the same user
     #[arg(long, value_name = "SECONDS", default_value_t = 10)]
     pub(crate) throttling_timeout_seconds: u64,
+
+    /// Hosts that can be rendered, also when they point to a private network.
+    /// Other hosts are rejected when set
+    #[arg(long, env, value_name = "HOSTS", value_delimiter = ',')]
+    pub(crate) allowed_hosts: Vec<String>,
+
+    /// Hosts that are never rendered, subdomains included
+    #[arg(long, env, value_name = "HOSTS", value_delimiter = ',')]
+    pub(crate) denied_hosts: Vec<String>,
+
+    /// Proxies pages are loaded through, e.g. socks5://10.0.0.1:1080, rotated per page
+    #[arg(long, env, value_name = "URLS", value_delimiter = ',')]
+    pub(crate) proxies: Vec<String>,
+
+    /// Proxies for specific domains in the domain=proxy_url form
+    #[arg(long, env, value_name = "ROUTES", value_delimiter = ',')]
+    pub(crate) proxy_routes: Vec<String>,
+
+    /// Tor SOCKS proxy for onion links and the Tor domains, e.g. socks5://127.0.0.1:9050
+    #[arg(long, env, value_name = "URL")]
+    pub(crate) tor_proxy: Option<String>,
+
+    /// Domains that are always loaded through Tor, subdomains included
+    #[arg(long, env, value_name = "DOMAINS", value_delimiter = ',')]
+    pub(crate) tor_domains: Vec<String>,
+
+    /// How long a page loaded through Tor may take
+    #[arg(long, value_name = "SECONDS", default_value_t = 300)]
+    pub(crate) tor_timeout_seconds: u64,
+
+    /// Wayback Machine API the snapshots of unavailable pages are looked up with
+    #[arg(long, env, value_name = "URL", default_value = "https://archive.org")]
+    pub(crate) wayback_url: String,
+
+    /// Don't look up unavailable pages in web archives
+    #[arg(long)]
+    pub(crate) no_archive_fallback: bool,
+
+    /// Domains whose links are loaded as soon as they are posted in groups and channels,
+    /// subdomains included. Otherwise groups need to mention the bot
+    #[arg(long, env, value_name = "DOMAINS", value_delimiter = ',')]
+    pub(crate) auto_domains: Vec<String>,
+
+    /// Self-hosted Telegram Bot API server, raises the upload limit to 2000 MB
+    #[arg(long, env, value_name = "URL")]
+    pub(crate) telegram_api_url: Option<String>,
+
+    /// Pass pages to the Bot API server by their path instead of uploading them.
+    /// The server must see the work dir at the same path
+    #[arg(long, env)]
+    pub(crate) telegram_local_files: bool,
 }
@@ -1,29 +1,21 @@
 use teloxide::RequestError;
+use thiserror::Error;
 
-use crate::bot_error::BotError::TelegramError;
+use api::PageError;
 
-#[derive(Debug)]
+#[derive(Debug, Error)]
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum BotError {
-    RequestError(reqwest::Error),
+    #[error("Request to the backend failed: {0}")]
+    RequestError(#[from] reqwest::Error),
+    #[error("Too many requests")]
     ThrottleError,
-    GenericError(anyhow::Error),
-    TelegramError(RequestError),
-}
-
-impl From<reqwest::Error> for BotError {
-    fn from(value: reqwest::Error) -> Self {
-        BotError::RequestError(value)
-    }
-}
-
-impl From<anyhow::Error> for BotError {
-    fn from(err: anyhow::Error) -> Self {
-        BotError::GenericError(err.into())
-    }
-}
-
-impl From<RequestError> for BotError {
-    fn from(value: RequestError) -> Self {
-        TelegramError(value)
-    }
+    #[error("The backend rejected the request: {0}")]
+    Rejected(String),
+    #[error(transparent)]
+    PageError(#[from] PageError),
+    #[error(transparent)]
+    GenericError(#[from] anyhow::Error),
+    #[error("Telegram request failed: {0}")]
+    TelegramError(#[from] RequestError),
 }
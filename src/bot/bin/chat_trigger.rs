@@ -0,0 +1,177 @@
+use reqwest::Url;
+use teloxide::types::{Message, MessageEntityKind, MessageEntityRef, MessageId};
+
+use botbackend::url_policy::matches_host;
+
+/// Link found in a chat and the message that holds it, the page is sent in reply to it.
+#[derive(Clone, Debug)]
+pub(crate) struct ChatLink {
+    pub(crate) url: String,
+    pub(crate) message_id: MessageId,
+}
+
+/// Decides which messages of groups and channels the bot loads pages for.
+/// In private chats any posted link is loaded when the chat detects links.
+pub(crate) struct ChatTrigger {
+    auto_domains: Vec<String>,
+}
+
+impl ChatTrigger {
+    pub(crate) fn new(auto_domains: Vec<String>) -> Self {
+        ChatTrigger {
+            auto_domains: auto_domains
+                .into_iter()
+                .map(|domain| domain.trim().to_lowercase())
+                .filter(|domain| !domain.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Link of a group message that mentions the bot, taken from the message itself
+    /// or from the message it replies to
+    pub(crate) fn mentioned_link(&self, message: &Message, bot_username: &str) -> Option<ChatLink> {
+        let entities = message_entities(message);
+        if !mentions(&entities, bot_username) {
+            return None;
+        }
+        match entity_links(&entities).into_iter().next() {
+            Some(url) => Some(ChatLink {
+                url,
+                message_id: message.id,
+            }),
+            None => replied_link(message),
+        }
+    }
+
+    /// The first link of the message from the auto domains, posting it is enough
+    /// for the bot to load the page
+    pub(crate) fn auto_link(&self, message: &Message) -> Option<ChatLink> {
+        let url = self.find_auto_link(entity_links(&message_entities(message)))?;
+        Some(ChatLink {
+            url,
+            message_id: message.id,
+        })
+    }
+
+    /// The first link of the message, private chats load any posted link
+    pub(crate) fn posted_link(&self, message: &Message) -> Option<ChatLink> {
+        let url = entity_links(&message_entities(message))
+            .into_iter()
+            .next()?;
+        Some(ChatLink {
+            url,
+            message_id: message.id,
+        })
+    }
+
+    fn find_auto_link(&self, links: Vec<String>) -> Option<String> {
+        links.into_iter().find(|link| {
+            Url::parse(link)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_lowercase))
+                .is_some_and(|host| {
+                    self.auto_domains
+                        .iter()
+                        .any(|domain| matches_host(&host, domain))
+                })
+        })
+    }
+}
+
+/// The first link of the message the command or the mention replies to
+pub(crate) fn replied_link(message: &Message) -> Option<ChatLink> {
+    let replied = message.reply_to_message()?;
+    let url = entity_links(&message_entities(replied))
+        .into_iter()
+        .next()?;
+    Some(ChatLink {
+        url,
+        message_id: replied.id,
+    })
+}
+
+fn message_entities(message: &Message) -> Vec<MessageEntityRef<'_>> {
+    message
+        .parse_entities()
+        .or_else(|| message.parse_caption_entities())
+        .unwrap_or_default()
+}
+
+fn mentions(entities: &[MessageEntityRef], bot_username: &str) -> bool {
+    entities.iter().any(|entity| {
+        matches!(entity.kind(), MessageEntityKind::Mention)
+            && entity
+                .text()
+                .trim_start_matches('@')
+                .eq_ignore_ascii_case(bot_username)
+    })
+}
+
+/// Links of the message in their order, links without a scheme get https
+fn entity_links(entities: &[MessageEntityRef]) -> Vec<String> {
+    entities
+        .iter()
+        .filter_map(|entity| match entity.kind() {
+            MessageEntityKind::Url if entity.text().contains("://") => {
+                Some(entity.text().to_string())
+            }
+            MessageEntityKind::Url => Some(format!("https://{}", entity.text())),
+            MessageEntityKind::TextLink { url } => Some(url.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use teloxide::types::{MessageEntity, MessageEntityKind, MessageEntityRef};
+
+    use crate::chat_trigger::{entity_links, mentions, ChatTrigger};
+
+    #[test]
+    fn test_entity_links() -> anyhow::Result<()> {
+        let text = "@page_bot see example.com and http://news.example.org/a or this";
+        let entities = vec![
+            MessageEntity::new(MessageEntityKind::Mention, 0, 9),
+            MessageEntity::new(MessageEntityKind::Url, 14, 11),
+            MessageEntity::new(MessageEntityKind::Url, 30, 25),
+            MessageEntity::text_link("https://blocked.example/post".parse()?, 59, 4),
+        ];
+        let entities = MessageEntityRef::parse(text, &entities);
+
+        assert_eq!(
+            entity_links(&entities),
+            vec![
+                "https://example.com",
+                "http://news.example.org/a",
+                "https://blocked.example/post",
+            ]
+        );
+        assert!(mentions(&entities, "page_bot"));
+        assert!(mentions(&entities, "Page_Bot"));
+        assert!(!mentions(&entities, "other_bot"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_auto_link() {
+        let trigger = ChatTrigger::new(vec!["Blocked.example".to_string(), " ".to_string()]);
+        let links = |links: &[&str]| links.iter().map(|link| link.to_string()).collect();
+
+        assert_eq!(
+            trigger.find_auto_link(links(&[
+                "https://example.com/",
+                "https://news.blocked.example/post",
+            ])),
+            Some("https://news.blocked.example/post".to_string())
+        );
+        assert_eq!(
+            trigger.find_auto_link(links(&["https://notblocked.example/", "not a url"])),
+            None
+        );
+        assert_eq!(
+            ChatTrigger::new(vec![]).find_auto_link(links(&["https://blocked.example/"])),
+            None
+        );
+    }
+}
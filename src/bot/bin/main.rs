@@ -1,36 +1,60 @@
 use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use clap::Parser;
 use dptree::case;
 use teloxide::dispatching::UpdateHandler;
-use teloxide::{prelude::*, utils::command::BotCommands};
+use teloxide::prelude::*;
+use teloxide::types::{Me, MessageId, ReplyParameters, User};
 
-use proto::command::Command;
+use api::i18n::{Language, Text};
+use api::{ChatSettings, JobStatus, StatusMessage, StatusReporter};
+use botbackend::archive::WaybackClient;
+use botbackend::parallel_page_worker::ParallelPageWorker;
+use botbackend::pipeline::{PageLayer, PagePipeline};
+use botbackend::proxy_pool::ProxyPool;
+use botbackend::tor::TorConfig;
+use botbackend::url_policy::UrlPolicy;
+use proto::bot_registry::bot_from_env;
+use proto::command::{help_text, register_commands, Command};
+use proto::document::{PageButton, UploadConfig};
+use proto::status_reporter::{
+    cancel_keyboard, status_text, TeloxideStatusReporter, CANCEL_CALLBACK,
+};
+use sqlite::persistent_page_worker::CacheLayer;
+use sqlite::sqlite_persistent::init_db;
 
 use crate::bot_args::BotArgs;
 use crate::bot_error::BotError;
-use crate::worker::page_loader::PageLoader;
+use crate::chat_trigger::{replied_link, ChatLink, ChatTrigger};
+use crate::settings_menu::{settings_keyboard, SettingsChoice};
+use crate::worker::page_loader::{BrokenPageReport, PageLoader, PageRequest};
 use crate::worker::remote_page_loader::RemotePageLoader;
 use crate::worker::standalone_page_loader::StandalonePageLoader;
 use crate::worker::throttled_page_loader::ThrottlePageLoader;
 
 mod bot_args;
 mod bot_error;
+mod chat_trigger;
+mod settings_menu;
 mod worker;
 
 type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let bot = Bot::from_env();
     let args = BotArgs::parse();
-    let duration = Duration::from_secs(args.throttling_timeout_seconds.clone());
-    let worker = create_worker(args, bot.clone())?;
+    let bot = bot_from_env(args.telegram_api_url.as_deref())?;
+    let duration = Duration::from_secs(args.throttling_timeout_seconds);
+    let trigger = Arc::new(ChatTrigger::new(args.auto_domains.clone()));
+    let worker = create_worker(args, bot.clone()).await?;
     let throttle_worker: Arc<dyn PageLoader> = Arc::new(ThrottlePageLoader::new(duration, worker));
+    if let Err(err) = register_commands(&bot).await {
+        println!("Can't register the commands: {}", err);
+    }
     Dispatcher::builder(bot, schema())
-        .dependencies(dptree::deps![throttle_worker])
+        .dependencies(dptree::deps![throttle_worker, trigger])
         .build()
         .dispatch()
         .await;
@@ -39,83 +63,507 @@ async fn main() -> anyhow::Result<()> {
 }
 
 fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>> {
-    Update::filter_message()
+    let command_handler = Update::filter_message()
         .filter_command::<Command>()
         .branch(case![Command::Help].endpoint(print_help))
         .branch(case![Command::GetPage { url }].endpoint(get_page))
+        .branch(case![Command::Cancel].endpoint(cancel))
+        .branch(case![Command::Settings].endpoint(show_settings));
+
+    let callback_handler = Update::filter_callback_query()
+        .branch(
+            dptree::filter(|query: CallbackQuery| query.data.as_deref() == Some(CANCEL_CALLBACK))
+                .endpoint(cancel_from_button),
+        )
+        .branch(
+            dptree::filter_map(|query: CallbackQuery| {
+                query.data.as_deref().and_then(PageButton::parse)
+            })
+            .branch(case![PageButton::Refresh(job_id)].endpoint(refresh_page))
+            .branch(case![PageButton::Report(job_id)].endpoint(report_page)),
+        )
+        .branch(
+            dptree::filter_map(|query: CallbackQuery| {
+                query.data.as_deref().and_then(SettingsChoice::parse)
+            })
+            .endpoint(change_setting),
+        );
+
+    // links posted without the command are loaded when the chat detects links
+    let private_handler = Update::filter_message()
+        .filter(|message: Message| message.chat.is_private())
+        .filter_map_async(
+            |message: Message, trigger: Arc<ChatTrigger>, worker: Arc<dyn PageLoader>| async move {
+                let link = trigger.posted_link(&message)?;
+                detects_links(&worker, message.chat.id)
+                    .await
+                    .then_some(link)
+            },
+        )
+        .endpoint(load_posted_link);
+
+    // groups are only answered when the bot is mentioned or a link of the auto domains is posted
+    let group_handler =
+        Update::filter_message()
+            .filter(|message: Message| message.chat.is_group() || message.chat.is_supergroup())
+            .filter_map_async(
+                |message: Message,
+                 me: Me,
+                 trigger: Arc<ChatTrigger>,
+                 worker: Arc<dyn PageLoader>| async move {
+                    if let Some(link) = trigger.mentioned_link(&message, me.username()) {
+                        return Some(link);
+                    }
+                    let link = trigger.auto_link(&message)?;
+                    detects_links(&worker, message.chat.id)
+                        .await
+                        .then_some(link)
+                },
+            )
+            .endpoint(load_posted_link);
+
+    let channel_handler = Update::filter_channel_post()
+        .filter_map_async(
+            |message: Message, trigger: Arc<ChatTrigger>, worker: Arc<dyn PageLoader>| async move {
+                let link = trigger.auto_link(&message)?;
+                detects_links(&worker, message.chat.id)
+                    .await
+                    .then_some(link)
+            },
+        )
+        .endpoint(load_channel_link);
+
+    dptree::entry()
+        .branch(command_handler)
+        .branch(private_handler)
+        .branch(group_handler)
+        .branch(channel_handler)
+        .branch(callback_handler)
 }
 
+/// Loads the page of the command argument or, without it, of the link in the replied message
 async fn get_page(
     url: String,
     message: Message,
     worker: Arc<dyn PageLoader>,
     bot: Bot,
 ) -> HandlerResult {
-    println!("Chat id {}", message.chat.id);
-    let result = worker
-        .load_page(url.to_string(), message.chat.id.to_string())
-        .await;
+    let link = match url.trim() {
+        "" => replied_link(&message),
+        url => Some(ChatLink {
+            url: url.to_string(),
+            message_id: message.id,
+        }),
+    };
+    let Some(link) = link else {
+        let language = chat_language(&worker, message.chat.id, message.from.as_ref()).await;
+        bot.send_message(message.chat.id, language.text(Text::SendLink))
+            .await?;
+        return Ok(());
+    };
+    let reply_to = group_reply(&message, link.message_id);
+    let user = message.from.as_ref();
+    request_page(
+        &bot,
+        &worker,
+        message.chat.id,
+        user,
+        reply_to,
+        link.url,
+        false,
+    )
+    .await
+}
+
+async fn load_posted_link(
+    link: ChatLink,
+    message: Message,
+    worker: Arc<dyn PageLoader>,
+    bot: Bot,
+) -> HandlerResult {
+    let reply_to = group_reply(&message, link.message_id);
+    let user = message.from.as_ref();
+    request_page(
+        &bot,
+        &worker,
+        message.chat.id,
+        user,
+        reply_to,
+        link.url,
+        false,
+    )
+    .await
+}
+
+/// Channel posts get the page as a comment without any status messages
+async fn load_channel_link(
+    link: ChatLink,
+    message: Message,
+    worker: Arc<dyn PageLoader>,
+) -> HandlerResult {
+    let settings = chat_settings(&worker, message.chat.id).await;
+    let request = PageRequest {
+        url: link.url,
+        chat_id: message.chat.id.to_string(),
+        status_message: None,
+        fresh: settings.always_fresh,
+        format: settings.format,
+        reply_to_message_id: Some(link.message_id.0),
+        language: settings.language.unwrap_or_default(),
+    };
+    if let Err(err) = worker.load_page(request).await {
+        println!("Can't load page for channel {}: {:?}", message.chat.id, err);
+    }
+    Ok(())
+}
+
+/// Settings of the chat, the defaults when the storage is not available
+async fn chat_settings(worker: &Arc<dyn PageLoader>, chat_id: ChatId) -> ChatSettings {
+    let chat_id = chat_id.to_string();
+    match worker.chat_settings(&chat_id).await {
+        Ok(settings) => settings,
+        Err(err) => {
+            println!("Can't get settings of chat {}: {}", chat_id, err);
+            ChatSettings::new(&chat_id)
+        }
+    }
+}
+
+/// Language of the chat setting or, when it's not set, of the user's Telegram app
+fn settings_language(settings: &ChatSettings, user: Option<&User>) -> Language {
+    settings
+        .language
+        .unwrap_or_else(|| Language::from_code(user.and_then(|user| user.language_code.as_deref())))
+}
+
+async fn chat_language(
+    worker: &Arc<dyn PageLoader>,
+    chat_id: ChatId,
+    user: Option<&User>,
+) -> Language {
+    settings_language(&chat_settings(worker, chat_id).await, user)
+}
+
+async fn detects_links(worker: &Arc<dyn PageLoader>, chat_id: ChatId) -> bool {
+    chat_settings(worker, chat_id).await.auto_detect
+}
+
+/// Pages are sent in reply to the message with the link everywhere but in private chats
+fn group_reply(message: &Message, message_id: MessageId) -> Option<MessageId> {
+    (!message.chat.is_private()).then_some(message_id)
+}
+
+/// Sends the status message and passes the page to the worker, the user who asked for the page
+/// picks the language when the chat has none
+async fn request_page(
+    bot: &Bot,
+    worker: &Arc<dyn PageLoader>,
+    chat_id: ChatId,
+    user: Option<&User>,
+    reply_to: Option<MessageId>,
+    url: String,
+    fresh: bool,
+) -> HandlerResult {
+    println!("Chat id {}", chat_id);
+    let settings = chat_settings(worker, chat_id).await;
+    let language = settings_language(&settings, user);
+    let status_text = status_text(&JobStatus::Queued, language).unwrap_or_default();
+    let mut status_request = bot
+        .send_message(chat_id, status_text)
+        .reply_markup(cancel_keyboard(language));
+    if let Some(message_id) = reply_to {
+        status_request = status_request
+            .reply_parameters(ReplyParameters::new(message_id).allow_sending_without_reply());
+    }
+    let status_message = status_request.await?;
+    let status_message = StatusMessage {
+        bot_id: None,
+        chat_id: chat_id.to_string(),
+        message_id: status_message.id.0,
+        language,
+    };
+    let request = PageRequest {
+        url,
+        chat_id: chat_id.to_string(),
+        status_message: Some(status_message.clone()),
+        fresh: fresh || settings.always_fresh,
+        format: settings.format,
+        reply_to_message_id: reply_to.map(|message_id| message_id.0),
+        language,
+    };
+    let result = worker.load_page(request).await;
     match result {
         Ok(_) => {}
-        Err(e) => handle_error(bot, message, e).await?,
+        Err(e) => handle_error(bot.clone(), status_message, e).await?,
     };
 
-    return Ok(());
+    Ok(())
 }
 
-async fn print_help(bot: Bot, message: Message) -> HandlerResult {
-    bot.send_message(message.chat.id, Command::descriptions().to_string())
+async fn cancel(message: Message, worker: Arc<dyn PageLoader>, bot: Bot) -> HandlerResult {
+    let cancelled = worker.cancel(&message.chat.id.to_string(), None).await?;
+    if !cancelled {
+        let language = chat_language(&worker, message.chat.id, message.from.as_ref()).await;
+        bot.send_message(message.chat.id, language.text(Text::NothingToCancel))
+            .await?;
+    }
+    Ok(())
+}
+
+async fn cancel_from_button(
+    query: CallbackQuery,
+    worker: Arc<dyn PageLoader>,
+    bot: Bot,
+) -> HandlerResult {
+    bot.answer_callback_query(query.id.clone()).await?;
+    if let Some(message) = query.message {
+        worker
+            .cancel(&message.chat().id.to_string(), Some(message.id().0))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Loads the page of the document again, the cached copy is not sent
+async fn refresh_page(
+    job_id: String,
+    query: CallbackQuery,
+    worker: Arc<dyn PageLoader>,
+    bot: Bot,
+) -> HandlerResult {
+    let Some((message, page_url)) = page_document(&query, &worker, &job_id).await else {
+        return answer_link_not_found(&bot, &query).await;
+    };
+    bot.answer_callback_query(query.id.clone()).await?;
+    let reply_to = group_reply(message, message.id);
+    request_page(
+        &bot,
+        &worker,
+        message.chat.id,
+        Some(&query.from),
+        reply_to,
+        page_url,
+        true,
+    )
+    .await
+}
+
+async fn report_page(
+    job_id: String,
+    query: CallbackQuery,
+    worker: Arc<dyn PageLoader>,
+    bot: Bot,
+) -> HandlerResult {
+    let Some((message, page_url)) = page_document(&query, &worker, &job_id).await else {
+        return answer_link_not_found(&bot, &query).await;
+    };
+    let language = chat_language(&worker, message.chat.id, Some(&query.from)).await;
+    let report = BrokenPageReport {
+        page_url: page_url.clone(),
+        chat_id: message.chat.id.to_string(),
+        file_id: message
+            .document()
+            .map(|document| document.file.id.to_string()),
+    };
+    let answer = match worker.report_broken(report).await {
+        Ok(()) => Text::ReportSaved,
+        Err(err) => {
+            println!("Can't report page {}: {}", page_url, err);
+            Text::ReportFailed
+        }
+    };
+    bot.answer_callback_query(query.id.clone())
+        .text(language.text(answer))
         .await?;
-    return Ok(());
+    Ok(())
 }
 
-async fn handle_error(bot: Bot, message: Message, bot_error: BotError) -> HandlerResult {
-    match bot_error {
-        BotError::ThrottleError => {
-            send_message(
-                bot,
-                message.chat.id,
-                "Too many requests. Try again later".to_string(),
-            )
-            .await
+async fn answer_link_not_found(bot: &Bot, query: &CallbackQuery) -> HandlerResult {
+    let language = Language::from_code(query.from.language_code.as_deref());
+    bot.answer_callback_query(query.id.clone())
+        .text(language.text(Text::LinkNotFound))
+        .await?;
+    Ok(())
+}
+
+async fn show_settings(message: Message, worker: Arc<dyn PageLoader>, bot: Bot) -> HandlerResult {
+    let settings = chat_settings(&worker, message.chat.id).await;
+    let language = settings_language(&settings, message.from.as_ref());
+    bot.send_message(message.chat.id, language.text(Text::SettingsTitle))
+        .reply_markup(settings_keyboard(&settings, language))
+        .await?;
+    Ok(())
+}
+
+/// Switches the setting of the button to the next value, only admins change them in groups
+async fn change_setting(
+    choice: SettingsChoice,
+    query: CallbackQuery,
+    worker: Arc<dyn PageLoader>,
+    bot: Bot,
+) -> HandlerResult {
+    let Some(message) = query
+        .message
+        .as_ref()
+        .and_then(|message| message.regular_message())
+    else {
+        bot.answer_callback_query(query.id).await?;
+        return Ok(());
+    };
+    let mut settings = worker.chat_settings(&message.chat.id.to_string()).await?;
+    if !message.chat.is_private() {
+        let member = bot.get_chat_member(message.chat.id, query.from.id).await?;
+        if !member.is_privileged() {
+            let language = settings_language(&settings, Some(&query.from));
+            bot.answer_callback_query(query.id)
+                .text(language.text(Text::OnlyAdmins))
+                .await?;
+            return Ok(());
         }
-        _ => {
-            println!("Error during page loading: {:?}", bot_error);
-            Ok(())
+    }
+    choice.apply(&mut settings);
+    worker.save_chat_settings(&settings).await?;
+    bot.answer_callback_query(query.id.clone()).await?;
+    // the menu switches to the new language right away
+    let language = settings_language(&settings, Some(&query.from));
+    bot.edit_message_text(
+        message.chat.id,
+        message.id,
+        language.text(Text::SettingsTitle),
+    )
+    .reply_markup(settings_keyboard(&settings, language))
+    .await?;
+    Ok(())
+}
+
+/// The document message the button belongs to and the url its job was requested with.
+/// Buttons of the documents sent before the jobs were kept have no job.
+async fn page_document<'a>(
+    query: &'a CallbackQuery,
+    worker: &Arc<dyn PageLoader>,
+    job_id: &str,
+) -> Option<(&'a Message, String)> {
+    let message = query.message.as_ref()?.regular_message()?;
+    if job_id.is_empty() {
+        return None;
+    }
+    match worker.job_page_url(job_id).await {
+        Ok(page_url) => Some((message, page_url?)),
+        Err(err) => {
+            println!("Can't find job {}: {}", job_id, err);
+            None
         }
     }
 }
 
-async fn send_message(bot: Bot, chat_id: ChatId, message: String) -> HandlerResult {
-    let _ = bot.send_message(chat_id, message).await?;
+async fn print_help(bot: Bot, message: Message, worker: Arc<dyn PageLoader>) -> HandlerResult {
+    let language = chat_language(&worker, message.chat.id, message.from.as_ref()).await;
+    bot.send_message(message.chat.id, help_text(language))
+        .await?;
+    Ok(())
+}
+
+async fn handle_error(
+    bot: Bot,
+    status_message: StatusMessage,
+    bot_error: BotError,
+) -> HandlerResult {
+    let language = status_message.language;
+    let status = match bot_error {
+        BotError::ThrottleError => {
+            JobStatus::Failed(language.text(Text::TooManyRequests).to_string())
+        }
+        BotError::Rejected(reason) => JobStatus::Failed(reason),
+        // the standalone loader reports its own failures
+        BotError::RequestError(_) => {
+            JobStatus::Failed(language.text(Text::ServiceUnavailable).to_string())
+        }
+        _ => {
+            println!("Error during page loading: {:?}", bot_error);
+            return Ok(());
+        }
+    };
+    TeloxideStatusReporter::new(bot)
+        .report_status(&status_message, &status)
+        .await?;
     Ok(())
 }
 
-fn create_worker(args: BotArgs, bot: Bot) -> anyhow::Result<Box<dyn PageLoader>> {
-    match args.backend_url {
-        None => start_standalone(args.singlefile_cli, args.work_dir, bot),
-        Some(url) => start_distributed(&url),
+async fn create_worker(args: BotArgs, bot: Bot) -> anyhow::Result<Box<dyn PageLoader>> {
+    match args.backend_url.clone() {
+        None => start_standalone(args, bot).await,
+        Some(url) => start_distributed(&url, args.api_secret),
     }
 }
 
-fn start_standalone(
-    singlefile_cli: Option<String>,
-    work_dir: Option<String>,
-    bot: Bot,
-) -> anyhow::Result<Box<dyn PageLoader>> {
-    let singlefile_cli_path = singlefile_cli
+async fn start_standalone(args: BotArgs, bot: Bot) -> anyhow::Result<Box<dyn PageLoader>> {
+    let singlefile_cli_path = args
+        .singlefile_cli
         .context("SINGLEFILE_CLI env variable must be set for the standalone mode")?;
 
-    let work_dir = work_dir.context("Working dir path must be set for standalone mode")?;
+    let work_dir = args
+        .work_dir
+        .context("Working dir path must be set for standalone mode")?;
+    // the chat settings and the reports of broken pages are kept next to the pages
+    tokio::fs::create_dir_all(&work_dir).await?;
+    let db = Arc::new(init_db(format!("sqlite://{}/settings.db?mode=rwc", work_dir)).await?);
+    let url_policy =
+        UrlPolicy::new(args.allowed_hosts, args.denied_hosts).with_onion(args.tor_proxy.is_some());
+    let proxies = ProxyPool::new(args.proxies, args.proxy_routes)?;
+    let mut worker = ParallelPageWorker::new(work_dir, singlefile_cli_path)
+        .with_url_policy(url_policy.clone())
+        .with_proxies(proxies);
+    if let Some(tor_proxy) = args.tor_proxy.as_deref() {
+        worker = worker.with_tor(TorConfig::new(
+            tor_proxy,
+            args.tor_domains,
+            Duration::from_secs(args.tor_timeout_seconds),
+        )?);
+    }
+    if args.page_layers.contains(&PageLayer::Metrics) {
+        bail!("The metrics layer is served by the backend, the standalone bot can't use it")
+    }
+    let uses_cache = args.page_layers.contains(&PageLayer::Cache);
+    let mut pipeline = PagePipeline::new(args.page_layers)
+        .with_retry(
+            args.max_attempts,
+            Duration::from_secs(args.retry_delay_seconds),
+        )
+        .with_failure_log(db.clone())
+        .with_url_policy(url_policy);
+    if uses_cache {
+        pipeline = pipeline
+            .with_cache(CacheLayer::new(db.clone()))
+            .with_stale_cache(db.clone());
+    }
+    if let Some(seconds) = args.page_timeout_seconds {
+        pipeline = pipeline.with_timeout(Duration::from_secs(seconds));
+    }
+    if let Some(max_pages) = args.max_parallel_pages {
+        pipeline = pipeline.with_concurrency_limit(max_pages);
+    }
+    if !args.no_archive_fallback {
+        pipeline = pipeline.with_archive(Box::new(WaybackClient::new(&args.wayback_url)?));
+    }
+    let page_worker = pipeline.service(worker);
 
-    Ok(Box::new(StandalonePageLoader::new(
-        singlefile_cli_path,
-        work_dir,
-        bot,
-    )))
+    let upload_config =
+        UploadConfig::new(args.telegram_api_url.as_deref(), args.telegram_local_files)?;
+    let mut loader =
+        StandalonePageLoader::new(page_worker, bot, db.clone(), db.clone(), db.clone())
+            .with_upload_config(upload_config);
+    if uses_cache {
+        loader = loader.with_cache(db);
+    }
+    Ok(Box::new(loader))
 }
 
-fn start_distributed(backend_url: &str) -> anyhow::Result<Box<dyn PageLoader>> {
-    let loader = RemotePageLoader::new(backend_url)?;
+fn start_distributed(
+    backend_url: &str,
+    api_secret: Option<String>,
+) -> anyhow::Result<Box<dyn PageLoader>> {
+    let api_secret =
+        api_secret.context("API_SECRET env variable must be set for the distributed mode")?;
+    let loader = RemotePageLoader::new(backend_url, api_secret)?;
     Ok(Box::new(loader))
 }
@@ -0,0 +1,161 @@
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use api::i18n::{Language, Text};
+use api::{ChatSettings, PageFormat};
+
+/// Callback data of the menu buttons starts with it, the rest names the setting
+pub(crate) const SETTINGS_CALLBACK: &str = "settings:";
+
+/// Setting a button of the menu switches to the next value
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum SettingsChoice {
+    Format,
+    AutoDetect,
+    Language,
+    AlwaysFresh,
+}
+
+impl SettingsChoice {
+    pub(crate) fn parse(data: &str) -> Option<Self> {
+        match data.strip_prefix(SETTINGS_CALLBACK)? {
+            "format" => Some(SettingsChoice::Format),
+            "auto_detect" => Some(SettingsChoice::AutoDetect),
+            "language" => Some(SettingsChoice::Language),
+            "always_fresh" => Some(SettingsChoice::AlwaysFresh),
+            _ => None,
+        }
+    }
+
+    fn callback_data(self) -> String {
+        let name = match self {
+            SettingsChoice::Format => "format",
+            SettingsChoice::AutoDetect => "auto_detect",
+            SettingsChoice::Language => "language",
+            SettingsChoice::AlwaysFresh => "always_fresh",
+        };
+        format!("{}{}", SETTINGS_CALLBACK, name)
+    }
+
+    pub(crate) fn apply(self, settings: &mut ChatSettings) {
+        match self {
+            SettingsChoice::Format => {
+                settings.format = match settings.format {
+                    PageFormat::Html => PageFormat::Gzip,
+                    PageFormat::Gzip => PageFormat::Html,
+                }
+            }
+            SettingsChoice::AutoDetect => settings.auto_detect = !settings.auto_detect,
+            // the language of the user's app comes first, then the supported ones
+            SettingsChoice::Language => {
+                settings.language = match settings.language {
+                    None => Language::ALL.first().copied(),
+                    Some(language) => Language::ALL
+                        .into_iter()
+                        .skip_while(|next| *next != language)
+                        .nth(1),
+                }
+            }
+            SettingsChoice::AlwaysFresh => settings.always_fresh = !settings.always_fresh,
+        }
+    }
+
+    fn label(self, settings: &ChatSettings, language: Language) -> String {
+        match self {
+            SettingsChoice::Format => match settings.format {
+                PageFormat::Html => language.text(Text::FormatHtml).to_string(),
+                PageFormat::Gzip => language.text(Text::FormatGzip).to_string(),
+            },
+            SettingsChoice::AutoDetect => language.format(
+                Text::DetectLinks,
+                &[&on_off(settings.auto_detect, language)],
+            ),
+            SettingsChoice::Language => {
+                let name = match settings.language {
+                    None => language.text(Text::LanguageFromApp),
+                    Some(chosen) => chosen.name(),
+                };
+                language.format(Text::LanguageSetting, &[&name])
+            }
+            SettingsChoice::AlwaysFresh => language.format(
+                Text::CachedCopies,
+                &[&on_off(!settings.always_fresh, language)],
+            ),
+        }
+    }
+}
+
+fn on_off(value: bool, language: Language) -> &'static str {
+    if value {
+        language.text(Text::On)
+    } else {
+        language.text(Text::Off)
+    }
+}
+
+/// One button per setting showing its current value
+pub(crate) fn settings_keyboard(
+    settings: &ChatSettings,
+    language: Language,
+) -> InlineKeyboardMarkup {
+    let choices = [
+        SettingsChoice::Format,
+        SettingsChoice::AutoDetect,
+        SettingsChoice::Language,
+        SettingsChoice::AlwaysFresh,
+    ];
+    InlineKeyboardMarkup::new(choices.map(|choice| {
+        [InlineKeyboardButton::callback(
+            choice.label(settings, language),
+            choice.callback_data(),
+        )]
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use api::i18n::Language;
+    use api::{ChatSettings, PageFormat};
+
+    use crate::settings_menu::{settings_keyboard, SettingsChoice};
+
+    #[test]
+    fn test_apply_choices() {
+        let mut settings = ChatSettings::new("42");
+        let choice = |data: &str| SettingsChoice::parse(data).unwrap();
+
+        choice("settings:format").apply(&mut settings);
+        choice("settings:auto_detect").apply(&mut settings);
+        choice("settings:always_fresh").apply(&mut settings);
+        assert_eq!(settings.format, PageFormat::Gzip);
+        assert!(!settings.auto_detect);
+        assert!(settings.always_fresh);
+
+        let mut languages = Vec::new();
+        for _ in 0..3 {
+            choice("settings:language").apply(&mut settings);
+            languages.push(settings.language);
+        }
+        assert_eq!(
+            languages,
+            vec![Some(Language::En), Some(Language::Ru), None]
+        );
+        assert_eq!(SettingsChoice::parse("settings:unknown"), None);
+        assert_eq!(SettingsChoice::parse("format"), None);
+    }
+
+    #[test]
+    fn test_keyboard_round_trip() {
+        let mut settings = ChatSettings::new("42");
+        settings.language = Some(Language::Ru);
+        let keyboard = settings_keyboard(&settings, Language::Ru);
+        assert_eq!(keyboard.inline_keyboard.len(), 4);
+        assert_eq!(keyboard.inline_keyboard[2][0].text, "Язык: Русский");
+        for row in keyboard.inline_keyboard {
+            let data = match &row[0].kind {
+                teloxide::types::InlineKeyboardButtonKind::CallbackData(data) => data.clone(),
+                kind => panic!("Unexpected button {:?}", kind),
+            };
+            assert!(SettingsChoice::parse(&data).is_some());
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Jobs older than this are forgotten even if nobody removed them explicitly
+const JOB_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Requests that are in progress in the chats, used to find what should be cancelled.
+pub(crate) struct ActiveJobs<T> {
+    jobs: Mutex<Vec<ActiveJob<T>>>,
+}
+
+struct ActiveJob<T> {
+    chat_id: String,
+    status_message_id: Option<i32>,
+    created: Instant,
+    value: T,
+}
+
+impl<T> Default for ActiveJobs<T> {
+    fn default() -> Self {
+        ActiveJobs {
+            jobs: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<T> ActiveJobs<T> {
+    pub(crate) fn add(&self, chat_id: &str, status_message_id: Option<i32>, value: T) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|job| job.created.elapsed() < JOB_TTL);
+        jobs.push(ActiveJob {
+            chat_id: chat_id.to_string(),
+            status_message_id,
+            created: Instant::now(),
+            value,
+        });
+    }
+
+    /// Removes and returns jobs of the chat. When the status message is not set all jobs of
+    /// the chat are taken.
+    pub(crate) fn take(&self, chat_id: &str, status_message_id: Option<i32>) -> Vec<T> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let (taken, rest) = jobs.drain(..).partition(|job: &ActiveJob<T>| {
+            job.chat_id == chat_id
+                && (status_message_id.is_none() || job.status_message_id == status_message_id)
+        });
+        *jobs = rest;
+        taken.into_iter().map(|job| job.value).collect()
+    }
+
+    pub(crate) fn remove_where(&self, predicate: impl Fn(&T) -> bool) {
+        self.jobs
+            .lock()
+            .unwrap()
+            .retain(|job| !predicate(&job.value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::worker::active_jobs::ActiveJobs;
+
+    #[test]
+    fn test_take_jobs() {
+        let jobs = ActiveJobs::default();
+        jobs.add("chat_1", Some(1), "job_1");
+        jobs.add("chat_1", Some(2), "job_2");
+        jobs.add("chat_1", None, "job_3");
+        jobs.add("chat_2", Some(1), "job_4");
+
+        assert_eq!(jobs.take("chat_1", Some(2)), vec!["job_2"]);
+        assert_eq!(jobs.take("chat_1", Some(2)), Vec::<&str>::new());
+        assert_eq!(jobs.take("chat_1", None), vec!["job_1", "job_3"]);
+
+        jobs.remove_where(|job| *job == "job_4");
+        assert_eq!(jobs.take("chat_2", None), Vec::<&str>::new());
+    }
+}
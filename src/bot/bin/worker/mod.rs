@@ -1,3 +1,4 @@
+pub(crate) mod active_jobs;
 pub(crate) mod page_loader;
 pub(crate) mod remote_page_loader;
 pub(crate) mod standalone_page_loader;
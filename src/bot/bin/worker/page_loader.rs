@@ -1,8 +1,49 @@
 use async_trait::async_trait;
 
+use api::i18n::Language;
+use api::{ChatSettings, PageFormat, StatusMessage};
+
 use crate::bot_error::BotError;
 
+pub(crate) struct PageRequest {
+    pub(crate) url: String,
+    pub(crate) chat_id: String,
+    /// Message that is edited in place to show the progress of the request
+    pub(crate) status_message: Option<StatusMessage>,
+    /// Load the page again instead of sending the cached document
+    pub(crate) fresh: bool,
+    pub(crate) format: PageFormat,
+    /// Message the page is sent in reply to, set in groups and channels
+    pub(crate) reply_to_message_id: Option<i32>,
+    /// Language of the caption and the failures
+    pub(crate) language: Language,
+}
+
+/// Sent page the user marked as broken.
+pub(crate) struct BrokenPageReport {
+    pub(crate) page_url: String,
+    pub(crate) chat_id: String,
+    /// Telegram file id of the reported document
+    pub(crate) file_id: Option<String>,
+}
+
 #[async_trait]
 pub(crate) trait PageLoader: Sync + Send {
-    async fn load_page(&self, url: String, chat_id: String) -> Result<(), BotError>;
+    async fn load_page(&self, request: PageRequest) -> Result<(), BotError>;
+
+    /// Cancels requests of the chat, only the one with the given status message if it is set.
+    /// Returns true if anything was cancelled.
+    async fn cancel(&self, chat_id: &str, status_message_id: Option<i32>)
+        -> Result<bool, BotError>;
+
+    /// Url the page of the job was requested with, `None` when the job is unknown.
+    async fn job_page_url(&self, job_id: &str) -> Result<Option<String>, BotError>;
+
+    /// Keeps the report for operators to review.
+    async fn report_broken(&self, report: BrokenPageReport) -> Result<(), BotError>;
+
+    /// Settings of the chat, the defaults when they were never changed.
+    async fn chat_settings(&self, chat_id: &str) -> Result<ChatSettings, BotError>;
+
+    async fn save_chat_settings(&self, settings: &ChatSettings) -> Result<(), BotError>;
 }
@@ -1,39 +1,184 @@
+use anyhow::Context;
 use async_trait::async_trait;
-use reqwest::{Client, Url};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Method, RequestBuilder, StatusCode, Url};
 use serde_json::json;
 
+use api::i18n::Language;
+use api::{ChatSettings, PageFormat};
+use utils::signature::{path_and_query, sign_request};
+
 use crate::bot_error::BotError;
-use crate::worker::page_loader::PageLoader;
+use crate::worker::active_jobs::ActiveJobs;
+use crate::worker::page_loader::{BrokenPageReport, PageLoader, PageRequest};
 
 pub(crate) struct RemotePageLoader {
     backend_url: Url,
     client: Client,
+    /// Secret shared with the backend to sign requests
+    api_secret: String,
+    /// Backend job ids of the requests sent from the chats
+    jobs: ActiveJobs<String>,
 }
 
 impl RemotePageLoader {
-    pub(crate) fn new(backend_url: &str) -> anyhow::Result<Self> {
+    pub(crate) fn new(backend_url: &str, api_secret: String) -> anyhow::Result<Self> {
         let url = Url::parse(backend_url)?;
         Ok(RemotePageLoader {
             backend_url: url,
             client: Client::new(),
+            api_secret,
+            jobs: ActiveJobs::default(),
         })
     }
+
+    fn settings_url(&self, chat_id: &str) -> Url {
+        let mut settings_url = self.backend_url.clone();
+        settings_url.set_path(&format!("v1/chats/{}/settings", chat_id));
+        settings_url
+    }
+
+    fn signed_request(&self, method: Method, url: Url, body: Vec<u8>) -> RequestBuilder {
+        let headers = sign_request(
+            &self.api_secret,
+            method.as_str(),
+            &path_and_query(&url),
+            &body,
+        );
+        let mut request = self.client.request(method, url).body(body);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        request
+    }
 }
 
 #[async_trait]
 impl PageLoader for RemotePageLoader {
-    async fn load_page(&self, url: String, chat_id: String) -> Result<(), BotError> {
+    async fn load_page(&self, request: PageRequest) -> Result<(), BotError> {
+        let status_message_id = request
+            .status_message
+            .as_ref()
+            .map(|message| message.message_id);
         let body = json!({
-            "page_url": url,
-            "user_id": chat_id
+            "page_url": request.url,
+            "user_id": request.chat_id,
+            "status_message_id": status_message_id,
+            "fresh": request.fresh,
+            "format": request.format.as_str(),
+            "reply_to_message_id": request.reply_to_message_id,
+            "language": request.language.code(),
         });
+        let body = serde_json::to_vec(&body).context("Can't serialize the request")?;
         let mut request_page_url = self.backend_url.clone();
         request_page_url.set_path("v1/requestPageForUser");
-        self.client
-            .post(request_page_url)
-            .json(&body)
+        let response = self
+            .signed_request(Method::POST, request_page_url, body)
+            .header(CONTENT_TYPE, "application/json")
+            .send()
+            .await?;
+        // e.g. invalid links or links to the internal network, the reason is shown to the user
+        if [StatusCode::BAD_REQUEST, StatusCode::FORBIDDEN].contains(&response.status()) {
+            return Err(BotError::Rejected(response.text().await?));
+        }
+        let response: serde_json::Value = response.error_for_status()?.json().await?;
+        let job_id = response["job_id"]
+            .as_str()
+            .context("Backend didn't return the job id")?;
+        self.jobs
+            .add(&request.chat_id, status_message_id, job_id.to_string());
+        Ok(())
+    }
+
+    async fn cancel(
+        &self,
+        chat_id: &str,
+        status_message_id: Option<i32>,
+    ) -> Result<bool, BotError> {
+        let mut cancelled = false;
+        for job_id in self.jobs.take(chat_id, status_message_id) {
+            let mut cancel_url = self.backend_url.clone();
+            cancel_url.set_path(&format!("v1/jobs/{}", job_id));
+            // the job might be already finished, the backend responds with 404 then
+            let response = self
+                .signed_request(Method::DELETE, cancel_url, Vec::new())
+                .send()
+                .await?;
+            cancelled |= response.status().is_success();
+        }
+        Ok(cancelled)
+    }
+
+    async fn job_page_url(&self, job_id: &str) -> Result<Option<String>, BotError> {
+        let mut job_url = self.backend_url.clone();
+        job_url.set_path(&format!("v1/jobs/{}", job_id));
+        let response = self
+            .signed_request(Method::GET, job_url, Vec::new())
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response: serde_json::Value = response.error_for_status()?.json().await?;
+        let page_url = response["page_url"]
+            .as_str()
+            .context("Backend didn't return the page url")?;
+        Ok(Some(page_url.to_string()))
+    }
+
+    async fn report_broken(&self, report: BrokenPageReport) -> Result<(), BotError> {
+        let body = json!({
+            "page_url": report.page_url,
+            "chat_id": report.chat_id,
+            "file_id": report.file_id,
+        });
+        let body = serde_json::to_vec(&body).context("Can't serialize the report")?;
+        let mut report_url = self.backend_url.clone();
+        report_url.set_path("v1/reports");
+        self.signed_request(Method::POST, report_url, body)
+            .header(CONTENT_TYPE, "application/json")
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn chat_settings(&self, chat_id: &str) -> Result<ChatSettings, BotError> {
+        let settings_url = self.settings_url(chat_id);
+        let response: serde_json::Value = self
+            .signed_request(Method::GET, settings_url, Vec::new())
             .send()
+            .await?
+            .error_for_status()?
+            .json()
             .await?;
+        let format = response["format"]
+            .as_str()
+            .context("Backend didn't return the format")?;
+        Ok(ChatSettings {
+            chat_id: chat_id.to_string(),
+            format: PageFormat::parse(format)?,
+            auto_detect: response["auto_detect"].as_bool().unwrap_or(true),
+            language: response["language"]
+                .as_str()
+                .and_then(|code| Language::parse(code).ok()),
+            always_fresh: response["always_fresh"].as_bool().unwrap_or(false),
+        })
+    }
+
+    async fn save_chat_settings(&self, settings: &ChatSettings) -> Result<(), BotError> {
+        let body = json!({
+            "format": settings.format.as_str(),
+            "auto_detect": settings.auto_detect,
+            "language": settings.language.map(|language| language.code()),
+            "always_fresh": settings.always_fresh,
+        });
+        let body = serde_json::to_vec(&body).context("Can't serialize the settings")?;
+        self.signed_request(Method::PUT, self.settings_url(&settings.chat_id), body)
+            .header(CONTENT_TYPE, "application/json")
+            .send()
+            .await?
+            .error_for_status()?;
         Ok(())
     }
 }
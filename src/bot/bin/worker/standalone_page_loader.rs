@@ -1,46 +1,430 @@
-use std::path::PathBuf;
+use std::sync::Arc;
 
 use async_trait::async_trait;
-use teloxide::prelude::Requester;
-use teloxide::types::{FileId, InputFile};
+use nanoid::nanoid;
 use teloxide::Bot;
+use time::{OffsetDateTime, PrimitiveDateTime};
+use tokio::sync::Notify;
 
-use api::{PageData, PageResult, PageWorker};
-use botbackend::parallel_page_worker::ParallelPageWorker;
+use api::i18n::Text;
+use api::{
+    ChatSettings, ChatSettingsPersistent, Job, JobPersistent, JobState, JobStatus, PageData,
+    PageFormat, PagePersistent, PageReport, PageResult, PageWorker, Recipient, ReportPersistent,
+    StatusMessage, StatusReporter,
+};
+use proto::document::{clear_data, convert_page, save_to_cache, send_page_document, UploadConfig};
+use proto::status_reporter::TeloxideStatusReporter;
+use utils::canonical_url::canonicalize;
 
 use crate::bot_error::BotError;
-use crate::worker::page_loader::PageLoader;
+use crate::worker::active_jobs::ActiveJobs;
+use crate::worker::page_loader::{BrokenPageReport, PageLoader, PageRequest};
 
 pub(crate) struct StandalonePageLoader {
-    worker: ParallelPageWorker,
+    sender: PageSender,
+    settings: Arc<dyn ChatSettingsPersistent>,
+    reports: Arc<dyn ReportPersistent>,
+    /// Cancel signals of the requests in progress
+    jobs: Arc<ActiveJobs<Arc<Notify>>>,
+}
+
+/// Loads the page and sends it to the chat, every request gets its own copy
+/// so the request runs in its own task
+#[derive(Clone)]
+struct PageSender {
+    worker: Arc<dyn PageWorker>,
+    status_reporter: TeloxideStatusReporter,
     bot: Bot,
+    upload_config: UploadConfig,
+    /// Sent pages are kept here when the cache layer is used
+    cache: Option<Arc<dyn PagePersistent>>,
+    /// Requests of the chats, the buttons of the sent page refer to them
+    job_store: Arc<dyn JobPersistent>,
 }
 
 impl StandalonePageLoader {
-    pub(crate) fn new(singlefile_cli_path: String, work_dir: String, bot: Bot) -> Self {
-        let worker = ParallelPageWorker::new(work_dir, singlefile_cli_path);
-        StandalonePageLoader { worker, bot }
+    pub(crate) fn new(
+        worker: Box<dyn PageWorker>,
+        bot: Bot,
+        settings: Arc<dyn ChatSettingsPersistent>,
+        reports: Arc<dyn ReportPersistent>,
+        job_store: Arc<dyn JobPersistent>,
+    ) -> Self {
+        let status_reporter = TeloxideStatusReporter::new(bot.clone());
+        StandalonePageLoader {
+            sender: PageSender {
+                worker: Arc::from(worker),
+                status_reporter,
+                bot,
+                upload_config: UploadConfig::default(),
+                cache: None,
+                job_store,
+            },
+            settings,
+            reports,
+            jobs: Arc::new(ActiveJobs::default()),
+        }
+    }
+
+    pub(crate) fn with_upload_config(mut self, upload_config: UploadConfig) -> Self {
+        self.sender.upload_config = upload_config;
+        self
+    }
+
+    pub(crate) fn with_cache(mut self, cache: Arc<dyn PagePersistent>) -> Self {
+        self.sender.cache = Some(cache);
+        self
+    }
+}
+
+impl PageSender {
+    /// Files of the page are added to `files`, the caller removes them once the request is over
+    async fn load_and_send(
+        &self,
+        job_id: &str,
+        request: &PageRequest,
+        files: &mut Vec<PageResult>,
+    ) -> Result<(), BotError> {
+        self.report(&request.status_message, JobStatus::Loading)
+            .await;
+        let page_data = PageData {
+            format: request.format,
+            fresh: request.fresh,
+            ..PageData::from_url(request.url.clone())
+        };
+        let page = match self.worker.submit_page_generation(page_data).await {
+            Ok(page) => page,
+            Err(err) => {
+                self.report(
+                    &request.status_message,
+                    JobStatus::Failed(err.user_message(request.language)),
+                )
+                .await;
+                return Err(err.into());
+            }
+        };
+
+        files.push(page.result.clone());
+
+        self.report(&request.status_message, JobStatus::Uploading)
+            .await;
+        let result = match convert_page(&page.result, request.format).await {
+            Ok(result) => {
+                if result != page.result {
+                    files.push(result.clone());
+                }
+                result
+            }
+            Err(err) => {
+                self.report(
+                    &request.status_message,
+                    JobStatus::Failed(request.language.text(Text::ConvertFailed).to_string()),
+                )
+                .await;
+                return Err(err.into());
+            }
+        };
+        let recipient = Recipient {
+            bot_id: None,
+            chat_id: request.chat_id.clone(),
+            reply_to_message_id: request.reply_to_message_id,
+            language: request.language,
+        };
+        let sent = send_page_document(
+            &self.bot,
+            &recipient,
+            job_id,
+            &request.url,
+            &result,
+            &page.metadata,
+            self.upload_config,
+        )
+        .await;
+        match sent {
+            Ok(file_id) => {
+                if let (Some(cache), Some(file_id)) = (&self.cache, file_id) {
+                    if request.format == PageFormat::Html {
+                        save_to_cache(&file_id, &page, cache, request.url.clone(), None).await;
+                    }
+                }
+                self.report(&request.status_message, JobStatus::Done).await;
+                Ok(())
+            }
+            Err(err) => {
+                self.report(
+                    &request.status_message,
+                    JobStatus::Failed(err.user_message(request.language)),
+                )
+                .await;
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn finish_job(&self, job: &mut Job, state: JobState, error: Option<String>) {
+        job.state = state;
+        job.last_error = error;
+        if let Err(err) = self.job_store.update_job(job).await {
+            println!("Can't update job {}: {:?}", job.id, err);
+        }
+    }
+
+    async fn report(&self, status_message: &Option<StatusMessage>, status: JobStatus) {
+        if let Some(message) = status_message {
+            if let Err(err) = self.status_reporter.report_status(message, &status).await {
+                println!("Can't report status {:?}: {}", status, err);
+            }
+        }
     }
 }
 
 #[async_trait]
 impl PageLoader for StandalonePageLoader {
-    async fn load_page(&self, url: String, chat_id: String) -> Result<(), BotError> {
-        let page_data = PageData::from_url(url);
-        let result = self.worker.submit_page_generation(page_data).await?;
-        send_document(chat_id, &self.bot, result).await
+    /// Starts loading the page and returns, the updates of the chat are handled one by one
+    /// and `/cancel` would wait for the page otherwise. The task reports its own failures.
+    async fn load_page(&self, request: PageRequest) -> Result<(), BotError> {
+        let cancel = Arc::new(Notify::new());
+        let status_message_id = request
+            .status_message
+            .as_ref()
+            .map(|message| message.message_id);
+        let now = OffsetDateTime::now_utc();
+        let now = PrimitiveDateTime::new(now.date(), now.time());
+        let mut job = Job {
+            id: nanoid!(),
+            page_url: canonicalize(&request.url),
+            chat_id: request.chat_id.clone(),
+            status_message_id,
+            state: JobState::Running,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            created_at: now,
+            api_key_id: None,
+            format: request.format,
+            bot_id: None,
+            original_url: request.url.clone(),
+            fresh: request.fresh,
+            reply_to_message_id: request.reply_to_message_id,
+            language: request.language,
+        };
+        self.sender.job_store.save_job(&job).await?;
+        self.jobs
+            .add(&request.chat_id, status_message_id, cancel.clone());
+
+        let sender = self.sender.clone();
+        let jobs = self.jobs.clone();
+        tokio::spawn(async move {
+            let mut files = vec![];
+            tokio::select! {
+                result = sender.load_and_send(&job.id, &request, &mut files) => {
+                    match result {
+                        Ok(()) => sender.finish_job(&mut job, JobState::Done, None).await,
+                        Err(err) => {
+                            println!("Can't send page {}: {:?}", request.url, err);
+                            sender
+                                .finish_job(&mut job, JobState::Failed, Some(err.to_string()))
+                                .await;
+                        }
+                    }
+                }
+                _ = cancel.notified() => {
+                    sender.report(&request.status_message, JobStatus::Cancelled).await;
+                    sender.finish_job(&mut job, JobState::Cancelled, None).await;
+                }
+            };
+            // the document is sent by then, or the request failed or was cancelled
+            for file in files {
+                clear_data(file).await;
+            }
+            jobs.remove_where(|job| Arc::ptr_eq(job, &cancel));
+        });
+        Ok(())
+    }
+
+    async fn cancel(
+        &self,
+        chat_id: &str,
+        status_message_id: Option<i32>,
+    ) -> Result<bool, BotError> {
+        let jobs = self.jobs.take(chat_id, status_message_id);
+        for cancel in &jobs {
+            cancel.notify_one();
+        }
+        Ok(!jobs.is_empty())
+    }
+
+    async fn job_page_url(&self, job_id: &str) -> Result<Option<String>, BotError> {
+        let job = self.sender.job_store.get_job(job_id).await?;
+        Ok(job.map(|job| job.original_url))
     }
-}
 
-async fn send_document(chat_id: String, bot: &Bot, result: PageResult) -> Result<(), BotError> {
-    let document = result_to_input_file(result);
-    bot.send_document(chat_id, document).await?;
-    Ok(())
+    async fn report_broken(&self, report: BrokenPageReport) -> Result<(), BotError> {
+        let now = OffsetDateTime::now_utc();
+        let report = PageReport {
+            page_url: canonicalize(&report.page_url),
+            chat_id: report.chat_id,
+            telegram_file_id: report.file_id,
+            bot_id: None,
+            reported_at: PrimitiveDateTime::new(now.date(), now.time()),
+        };
+        Ok(self.reports.save_report(&report).await?)
+    }
+
+    async fn chat_settings(&self, chat_id: &str) -> Result<ChatSettings, BotError> {
+        Ok(self.settings.get_settings(chat_id).await?)
+    }
+
+    async fn save_chat_settings(&self, settings: &ChatSettings) -> Result<(), BotError> {
+        Ok(self.settings.save_settings(settings).await?)
+    }
 }
 
-fn result_to_input_file(result: PageResult) -> InputFile {
-    match result {
-        PageResult::FilePath(path) => InputFile::file(PathBuf::from(path)),
-        PageResult::TelegramId(id) => InputFile::file_id(FileId::from(id)),
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use teloxide::Bot;
+
+    use api::i18n::Language;
+    use api::{
+        LoadedPage, PageData, PageError, PageFormat, PageResult, PageWorker, ReportPersistent,
+    };
+    use sqlite::sqlite_persistent::in_memory_db;
+
+    use crate::worker::page_loader::{BrokenPageReport, PageLoader, PageRequest};
+    use crate::worker::standalone_page_loader::StandalonePageLoader;
+
+    /// Never finishes the page, the guard is dropped with the cancelled request
+    struct SlowPageWorker {
+        guard: Arc<()>,
+    }
+
+    #[async_trait]
+    impl PageWorker for SlowPageWorker {
+        async fn submit_page_generation(&self, _: PageData) -> Result<LoadedPage, PageError> {
+            let _guard = self.guard.clone();
+            tokio::time::sleep(Duration::from_secs(600)).await;
+            Err(PageError::Timeout(Duration::from_secs(600)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancels_page_in_progress() -> anyhow::Result<()> {
+        let guard = Arc::new(());
+        let worker = Box::new(SlowPageWorker {
+            guard: guard.clone(),
+        });
+        let db = Arc::new(in_memory_db().await?);
+        let loader =
+            StandalonePageLoader::new(worker, Bot::new("token"), db.clone(), db.clone(), db);
+        let request = PageRequest {
+            url: "https://example.com".to_string(),
+            chat_id: "42".to_string(),
+            status_message: None,
+            fresh: false,
+            format: PageFormat::Html,
+            reply_to_message_id: None,
+            language: Language::En,
+        };
+
+        tokio::time::timeout(Duration::from_secs(1), loader.load_page(request)).await??;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(Arc::strong_count(&guard), 3);
+
+        assert!(loader.cancel("42", None).await?);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(Arc::strong_count(&guard), 2);
+        assert!(!loader.cancel("42", None).await?);
+        Ok(())
+    }
+
+    /// Renders every page to the same file
+    struct FilePageWorker {
+        path: String,
+        rendered: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl PageWorker for FilePageWorker {
+        async fn submit_page_generation(&self, _: PageData) -> Result<LoadedPage, PageError> {
+            self.rendered.store(true, Ordering::Relaxed);
+            tokio::fs::write(&self.path, "<html/>")
+                .await
+                .map_err(PageError::storage)?;
+            Ok(LoadedPage::from(PageResult::FilePath(self.path.clone())))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_removes_files_of_failed_page() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("page_{}.html", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let rendered = Arc::new(AtomicBool::new(false));
+        let worker = Box::new(FilePageWorker {
+            path: path.clone(),
+            rendered: rendered.clone(),
+        });
+        // nothing listens there, so the document can't be sent
+        let bot = Bot::new("token").set_api_url("http://127.0.0.1:1".parse()?);
+        let db = Arc::new(in_memory_db().await?);
+        let loader = StandalonePageLoader::new(worker, bot, db.clone(), db.clone(), db);
+        let request = PageRequest {
+            url: "https://example.com".to_string(),
+            chat_id: "42".to_string(),
+            status_message: None,
+            fresh: false,
+            format: PageFormat::Gzip,
+            reply_to_message_id: None,
+            language: Language::En,
+        };
+
+        loader.load_page(request).await?;
+        let page = Path::new(&path);
+        let compressed = format!("{}.gz", path);
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            if rendered.load(Ordering::Relaxed)
+                && !page.exists()
+                && !Path::new(&compressed).exists()
+            {
+                return Ok(());
+            }
+        }
+        panic!("Files of the page were not removed");
+    }
+
+    #[tokio::test]
+    async fn test_saves_reports() -> anyhow::Result<()> {
+        let worker = Box::new(SlowPageWorker {
+            guard: Arc::new(()),
+        });
+        let db = Arc::new(in_memory_db().await?);
+        let loader = StandalonePageLoader::new(
+            worker,
+            Bot::new("token"),
+            db.clone(),
+            db.clone(),
+            db.clone(),
+        );
+
+        loader
+            .report_broken(BrokenPageReport {
+                page_url: "https://Example.com/page/".to_string(),
+                chat_id: "42".to_string(),
+                file_id: Some("file_id".to_string()),
+            })
+            .await?;
+
+        let reports = db.list_reports(10).await?;
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].page_url, "https://example.com/page");
+        assert_eq!(reports[0].chat_id, "42");
+        assert_eq!(reports[0].telegram_file_id, Some("file_id".to_string()));
+        Ok(())
     }
 }
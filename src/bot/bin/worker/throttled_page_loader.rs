@@ -4,8 +4,10 @@ use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
 
+use api::ChatSettings;
+
 use crate::bot_error::BotError;
-use crate::worker::page_loader::PageLoader;
+use crate::worker::page_loader::{BrokenPageReport, PageLoader, PageRequest};
 
 pub(crate) struct ThrottlePageLoader {
     timeout: Duration,
@@ -18,6 +20,7 @@ struct Shared {
     purge_timeout: Duration,
 }
 
+#[derive(Default)]
 struct State {
     request_time: HashMap<String, u64>,
     shutdown: bool,
@@ -37,15 +40,6 @@ impl Shared {
     }
 }
 
-impl Default for State {
-    fn default() -> Self {
-        State {
-            request_time: HashMap::new(),
-            shutdown: false,
-        }
-    }
-}
-
 impl Drop for ThrottlePageLoader {
     fn drop(&mut self) {
         self.shared.shutdown_clear_task()
@@ -71,19 +65,43 @@ impl ThrottlePageLoader {
 
 #[async_trait]
 impl PageLoader for ThrottlePageLoader {
-    async fn load_page(&self, url: String, chat_id: String) -> Result<(), BotError> {
+    async fn load_page(&self, request: PageRequest) -> Result<(), BotError> {
         if can_request(
             &self.shared.state,
-            &chat_id,
+            &request.chat_id,
             self.timeout,
             current_time_sec(),
         ) {
-            self.worker.load_page(url, chat_id).await
+            self.worker.load_page(request).await
         } else {
-            println!("Throttle request for {}", chat_id);
+            println!("Throttle request for {}", request.chat_id);
             Err(BotError::ThrottleError)
         }
     }
+
+    async fn cancel(
+        &self,
+        chat_id: &str,
+        status_message_id: Option<i32>,
+    ) -> Result<bool, BotError> {
+        self.worker.cancel(chat_id, status_message_id).await
+    }
+
+    async fn job_page_url(&self, job_id: &str) -> Result<Option<String>, BotError> {
+        self.worker.job_page_url(job_id).await
+    }
+
+    async fn report_broken(&self, report: BrokenPageReport) -> Result<(), BotError> {
+        self.worker.report_broken(report).await
+    }
+
+    async fn chat_settings(&self, chat_id: &str) -> Result<ChatSettings, BotError> {
+        self.worker.chat_settings(chat_id).await
+    }
+
+    async fn save_chat_settings(&self, settings: &ChatSettings) -> Result<(), BotError> {
+        self.worker.save_chat_settings(settings).await
+    }
 }
 
 fn current_time_sec() -> u64 {
@@ -128,8 +146,11 @@ mod tests {
 
     use async_trait::async_trait;
 
+    use api::i18n::Language;
+    use api::{ChatSettings, PageFormat};
+
     use crate::bot_error::BotError;
-    use crate::worker::page_loader::PageLoader;
+    use crate::worker::page_loader::{BrokenPageReport, PageLoader, PageRequest};
     use crate::worker::throttled_page_loader::{can_request, Shared, State, ThrottlePageLoader};
 
     #[test]
@@ -192,7 +213,15 @@ mod tests {
         };
 
         throttled_loader
-            .load_page("url_1".to_string(), "chat_1".to_string())
+            .load_page(PageRequest {
+                url: "url_1".to_string(),
+                chat_id: "chat_1".to_string(),
+                status_message: None,
+                fresh: false,
+                format: PageFormat::Html,
+                reply_to_message_id: None,
+                language: Language::En,
+            })
             .await?;
 
         assert_eq!(
@@ -209,8 +238,35 @@ mod tests {
 
     #[async_trait]
     impl PageLoader for TestPageLoader {
-        async fn load_page(&self, url: String, chat_id: String) -> Result<(), BotError> {
-            self.load_page_requests.lock().unwrap().insert(url, chat_id);
+        async fn load_page(&self, request: PageRequest) -> Result<(), BotError> {
+            self.load_page_requests
+                .lock()
+                .unwrap()
+                .insert(request.url, request.chat_id);
+            Ok(())
+        }
+
+        async fn cancel(
+            &self,
+            _chat_id: &str,
+            _status_message_id: Option<i32>,
+        ) -> Result<bool, BotError> {
+            Ok(false)
+        }
+
+        async fn job_page_url(&self, _job_id: &str) -> Result<Option<String>, BotError> {
+            Ok(None)
+        }
+
+        async fn report_broken(&self, _report: BrokenPageReport) -> Result<(), BotError> {
+            Ok(())
+        }
+
+        async fn chat_settings(&self, chat_id: &str) -> Result<ChatSettings, BotError> {
+            Ok(ChatSettings::new(chat_id))
+        }
+
+        async fn save_chat_settings(&self, _settings: &ChatSettings) -> Result<(), BotError> {
             Ok(())
         }
     }
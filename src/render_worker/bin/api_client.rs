@@ -0,0 +1,151 @@
+use anyhow::Context;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, Url};
+use serde_json::json;
+
+use api::worker_protocol::{failure_to_json, Registration, RenderTask, EGRESS_HEADER};
+use api::{Egress, PageError};
+use utils::signature::{path_and_query, sign_request};
+
+/// Client for the render worker routes of the API node.
+pub(crate) struct ApiClient {
+    api_url: Url,
+    client: Client,
+    /// Secret shared with the API node to sign requests
+    api_secret: String,
+}
+
+impl ApiClient {
+    pub(crate) fn new(api_url: &str, api_secret: String) -> anyhow::Result<Self> {
+        Ok(ApiClient {
+            api_url: Url::parse(api_url)?,
+            client: Client::new(),
+            api_secret,
+        })
+    }
+
+    pub(crate) async fn register(&self) -> anyhow::Result<Registration> {
+        let response: serde_json::Value = self
+            .signed_request(Method::POST, "v1/workers", Vec::new())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Registration::from_json(&response)
+    }
+
+    /// Returns tasks the worker must stop, `None` if the API node doesn't know the worker.
+    pub(crate) async fn heartbeat(
+        &self,
+        worker_id: &str,
+        running_tasks: &[String],
+    ) -> anyhow::Result<Option<Vec<String>>> {
+        let response = self
+            .signed_json_request(
+                &format!("v1/workers/{}/heartbeat", worker_id),
+                json!({ "tasks": running_tasks }),
+            )?
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response: serde_json::Value = response.error_for_status()?.json().await?;
+        let cancelled = response["cancelled"]
+            .as_array()
+            .context("API node didn't return cancelled tasks")?
+            .iter()
+            .filter_map(|task| task.as_str().map(str::to_string))
+            .collect();
+        Ok(Some(cancelled))
+    }
+
+    /// Waits for a page to render, `Ok(None)` if there are no pages yet.
+    pub(crate) async fn claim(&self, worker_id: &str) -> anyhow::Result<Option<RenderTask>> {
+        let response = self
+            .signed_request(
+                Method::POST,
+                &format!("v1/workers/{}/tasks/claim", worker_id),
+                Vec::new(),
+            )
+            .send()
+            .await?
+            .error_for_status()?;
+        if response.status() == StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        let response: serde_json::Value = response.json().await?;
+        RenderTask::from_json(&response).map(Some)
+    }
+
+    pub(crate) async fn upload_result(
+        &self,
+        worker_id: &str,
+        task_id: &str,
+        content: Vec<u8>,
+        egress: Egress,
+    ) -> anyhow::Result<()> {
+        let response = self
+            .signed_request(
+                Method::PUT,
+                &format!("v1/workers/{}/tasks/{}/result", worker_id, task_id),
+                content,
+            )
+            .header(EGRESS_HEADER, egress.as_str())
+            .send()
+            .await?;
+        check_task_response(response)
+    }
+
+    pub(crate) async fn report_failure(
+        &self,
+        worker_id: &str,
+        task_id: &str,
+        error: &PageError,
+    ) -> anyhow::Result<()> {
+        let response = self
+            .signed_json_request(
+                &format!("v1/workers/{}/tasks/{}/failure", worker_id, task_id),
+                failure_to_json(error),
+            )?
+            .send()
+            .await?;
+        check_task_response(response)
+    }
+
+    fn signed_json_request(
+        &self,
+        path: &str,
+        body: serde_json::Value,
+    ) -> anyhow::Result<RequestBuilder> {
+        let request = self
+            .signed_request(Method::POST, path, serde_json::to_vec(&body)?)
+            .header(CONTENT_TYPE, "application/json");
+        Ok(request)
+    }
+
+    fn signed_request(&self, method: Method, path: &str, body: Vec<u8>) -> RequestBuilder {
+        let mut url = self.api_url.clone();
+        url.set_path(path);
+        let headers = sign_request(
+            &self.api_secret,
+            method.as_str(),
+            &path_and_query(&url),
+            &body,
+        );
+        let mut request = self.client.request(method, url).body(body);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        request
+    }
+}
+
+fn check_task_response(response: Response) -> anyhow::Result<()> {
+    // 404 means the page was cancelled or given to another worker
+    if response.status() != StatusCode::NOT_FOUND {
+        response.error_for_status()?;
+    }
+    Ok(())
+}
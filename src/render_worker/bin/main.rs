@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use clap::Parser;
+use tokio::sync::Notify;
+
+use api::worker_protocol::RenderTask;
+use api::{Egress, PageData, PageError, PageResult, PageWorker};
+use botbackend::parallel_page_worker::ParallelPageWorker;
+use botbackend::proxy_pool::ProxyPool;
+use botbackend::tor::TorConfig;
+use botbackend::url_policy::UrlPolicy;
+
+use crate::api_client::ApiClient;
+use crate::worker_args::WorkerArgs;
+
+mod api_client;
+mod worker_args;
+
+/// Delay before the next request after the API node couldn't be reached
+const ERROR_DELAY: Duration = Duration::from_secs(5);
+
+/// Render worker connected to the API node. Takes pages from the node,
+/// renders them with singlefile and uploads the result back.
+struct RenderWorker {
+    client: ApiClient,
+    page_worker: ParallelPageWorker,
+    worker_id: Mutex<String>,
+    /// Cancel signals of the pages being rendered
+    running: Mutex<HashMap<String, Arc<Notify>>>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = WorkerArgs::parse();
+    let client = ApiClient::new(&args.api_url, args.api_secret)?;
+    let registration = client.register().await?;
+    println!("Registered as render worker {}", registration.worker_id);
+
+    let url_policy =
+        UrlPolicy::new(args.allowed_hosts, args.denied_hosts).with_onion(args.tor_proxy.is_some());
+    let proxies = ProxyPool::new(args.proxies, args.proxy_routes)?;
+    let mut page_worker = ParallelPageWorker::new(args.work_dir, args.singlefile_cli)
+        .with_url_policy(url_policy)
+        .with_proxies(proxies);
+    if let Some(tor_proxy) = args.tor_proxy.as_deref() {
+        page_worker = page_worker.with_tor(TorConfig::new(
+            tor_proxy,
+            args.tor_domains,
+            Duration::from_secs(args.tor_timeout_seconds),
+        )?);
+    }
+    let worker = Arc::new(RenderWorker {
+        client,
+        page_worker,
+        worker_id: Mutex::new(registration.worker_id),
+        running: Mutex::new(HashMap::new()),
+    });
+    for _ in 0..args.parallel_pages {
+        tokio::spawn(worker.clone().render_pages());
+    }
+    worker
+        .send_heartbeats(registration.lease_duration / 3)
+        .await
+}
+
+impl RenderWorker {
+    async fn send_heartbeats(&self, interval: Duration) -> anyhow::Result<()> {
+        loop {
+            tokio::time::sleep(interval).await;
+            let running: Vec<String> = self.running.lock().unwrap().keys().cloned().collect();
+            let result = self.client.heartbeat(&self.worker_id(), &running).await;
+            match result {
+                Ok(Some(cancelled)) => self.cancel(&cancelled),
+                Ok(None) => {
+                    // the node forgot the worker, e.g. after a restart, so all pages are lost
+                    self.cancel(&running);
+                    let registration = self.client.register().await?;
+                    println!(
+                        "Registered again as render worker {}",
+                        registration.worker_id
+                    );
+                    *self.worker_id.lock().unwrap() = registration.worker_id;
+                }
+                Err(err) => println!("Heartbeat failed: {:?}", err),
+            }
+        }
+    }
+
+    async fn render_pages(self: Arc<Self>) {
+        loop {
+            let worker_id = self.worker_id();
+            match self.client.claim(&worker_id).await {
+                Ok(Some(task)) => self.render_page(&worker_id, task).await,
+                Ok(None) => {}
+                Err(err) => {
+                    println!("Can't claim a page: {:?}", err);
+                    tokio::time::sleep(ERROR_DELAY).await;
+                }
+            }
+        }
+    }
+
+    async fn render_page(&self, worker_id: &str, task: RenderTask) {
+        println!("Rendering {}", task.page_url);
+        let cancel = Arc::new(Notify::new());
+        self.running
+            .lock()
+            .unwrap()
+            .insert(task.task_id.clone(), cancel.clone());
+
+        let result = tokio::select! {
+            result = self.render(&task.page_url) => Some(result),
+            _ = cancel.notified() => None,
+        };
+        self.running.lock().unwrap().remove(&task.task_id);
+
+        let sent = match result {
+            Some(Ok((content, egress))) => {
+                self.client
+                    .upload_result(worker_id, &task.task_id, content, egress)
+                    .await
+            }
+            Some(Err(err)) => {
+                println!("Can't render {}: {:?}", task.page_url, err);
+                self.client
+                    .report_failure(worker_id, &task.task_id, &err)
+                    .await
+            }
+            None => {
+                println!("Rendering of {} was cancelled", task.page_url);
+                Ok(())
+            }
+        };
+        if let Err(err) = sent {
+            println!("Can't send the result of {}: {:?}", task.page_url, err);
+        }
+    }
+
+    async fn render(&self, page_url: &str) -> Result<(Vec<u8>, Egress), PageError> {
+        let page = self
+            .page_worker
+            .submit_page_generation(PageData::from_url(page_url.to_string()))
+            .await?;
+        let PageResult::FilePath(path) = page.result else {
+            return Err(PageError::storage("Page worker returned no file"));
+        };
+        let content = tokio::fs::read(&path).await.map_err(PageError::storage)?;
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(PageError::storage)?;
+        Ok((content, page.metadata.egress))
+    }
+
+    fn cancel(&self, tasks: &[String]) {
+        let running = self.running.lock().unwrap();
+        for task_id in tasks {
+            if let Some(cancel) = running.get(task_id) {
+                cancel.notify_one();
+            }
+        }
+    }
+
+    fn worker_id(&self) -> String {
+        self.worker_id.lock().unwrap().clone()
+    }
+}
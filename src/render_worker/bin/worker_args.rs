@@ -0,0 +1,54 @@
+use clap::Parser;
+
+#[derive(Parser)]
+#[command(about, long_about = None)]
+pub(crate) struct WorkerArgs {
+    /// Url of the API node the worker takes pages from
+    #[arg(long, value_name = "URL")]
+    pub(crate) api_url: String,
+
+    /// Secret shared with the API node to sign requests
+    #[arg(long, env, value_name = "SECRET")]
+    pub(crate) api_secret: String,
+
+    /// Working directory where pages are rendered before the upload
+    #[arg(long, value_name = "PATH")]
+    pub(crate) work_dir: String,
+
+    /// How many pages are rendered at the same time
+    #[arg(long, value_name = "COUNT", default_value_t = 2)]
+    pub(crate) parallel_pages: usize,
+
+    /// Hosts that can be rendered, also when they point to a private network.
+    /// Other hosts are rejected when set
+    #[arg(long, env, value_name = "HOSTS", value_delimiter = ',')]
+    pub(crate) allowed_hosts: Vec<String>,
+
+    /// Hosts that are never rendered, subdomains included
+    #[arg(long, env, value_name = "HOSTS", value_delimiter = ',')]
+    pub(crate) denied_hosts: Vec<String>,
+
+    /// Proxies pages are loaded through, e.g. socks5://10.0.0.1:1080, rotated per page
+    #[arg(long, env, value_name = "URLS", value_delimiter = ',')]
+    pub(crate) proxies: Vec<String>,
+
+    /// Proxies for specific domains in the domain=proxy_url form
+    #[arg(long, env, value_name = "ROUTES", value_delimiter = ',')]
+    pub(crate) proxy_routes: Vec<String>,
+
+    /// Tor SOCKS proxy for onion links and the Tor domains, e.g. socks5://127.0.0.1:9050
+    #[arg(long, env, value_name = "URL")]
+    pub(crate) tor_proxy: Option<String>,
+
+    /// Domains that are always loaded through Tor, subdomains included
+    #[arg(long, env, value_name = "DOMAINS", value_delimiter = ',')]
+    pub(crate) tor_domains: Vec<String>,
+
+    /// How long a page loaded through Tor may take
+    #[arg(long, value_name = "SECONDS", default_value_t = 300)]
+    pub(crate) tor_timeout_seconds: u64,
+
+    /// Path to singlefile binary
+    #[arg(env)]
+    pub(crate) singlefile_cli: String,
+}